@@ -1,45 +1,41 @@
 use flate2::read::ZlibDecoder;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
+
+use crate::error::{Result, RevyError};
 
 #[derive(Debug)]
 pub struct Blob {
-    pub data: String,
+    pub data: Vec<u8>,
 }
 
 impl Blob {
-    pub fn new(data: String) -> Blob {
+    pub fn new(data: Vec<u8>) -> Blob {
         Blob { data }
     }
 
-    pub fn as_str(&self) -> &str {
+    pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend(self.data.as_bytes());
-        bytes
-    }
-
-    pub fn from_file(mut decoded_reader: BufReader<ZlibDecoder<File>>) -> Result<Blob, String> {
-        let mut blob = Blob {
-            data: String::new(),
-        };
-
+    pub fn from_file(mut decoded_reader: BufReader<ZlibDecoder<File>>) -> Result<Blob> {
         let mut buffer = Vec::new();
         match decoded_reader.read_to_end(&mut buffer) {
             Ok(_size) => {}
             Err(_err) => {
-                return Err("Failed to read object file while parsing blob".to_string());
+                return Err(RevyError::malformed("failed to decompress blob content"));
             }
         }
 
-        blob.data = String::from_utf8(buffer).unwrap();
-        Ok(blob)
+        Ok(Blob { data: buffer })
     }
 
-    pub fn print_blob(&self) {
-        println!("{}", self.data);
+    /// Writes the exact blob bytes to stdout, without any added newline.
+    pub fn print_blob(&self) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&self.data)
+            .and_then(|_| stdout.flush())
+            .map_err(|err| RevyError::io("Failed to write to stdout", err))
     }
 }
@@ -0,0 +1,369 @@
+//! Branches: the refs under `refs/heads/`, and the upstream each one tracks, which is
+//! recorded in its `branch.<name>` section of the repository config.
+//!
+//! Like git, creating, deleting and renaming a branch keeps its config section in step, and
+//! a branch can only be deleted unforced once it is merged.
+
+use std::path::PathBuf;
+
+use crate::config;
+use crate::error::{Result, RevyError};
+use crate::object_id::ObjectId;
+use crate::refs::{self, Expected};
+use crate::repository::Repository;
+use crate::revision;
+
+/// A branch `list_branches` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// The name without `refs/heads/`.
+    pub name: String,
+    /// The commit the branch points at.
+    pub id: ObjectId,
+    /// Whether `HEAD` points at the branch.
+    pub is_current: bool,
+    /// The full name of the ref the branch tracks, if any.
+    pub upstream: Option<String>,
+}
+
+/// Returns the full ref name of the branch `name`, checking that it is a valid name.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if `name` can't name a branch.
+pub fn branch_ref_name(name: &str) -> Result<String> {
+    if name == "HEAD" || !refs::is_valid_ref_name(name) {
+        return Err(RevyError::InvalidArgument(format!(
+            "'{}' is not a valid branch name",
+            name
+        )));
+    }
+    Ok(format!("{}{}", refs::HEADS_PREFIX, name))
+}
+
+/// Returns the branch `HEAD` points at, without `refs/heads/`, or `None` when it is
+/// detached.
+pub(crate) fn current_branch(repo: &Repository) -> Option<String> {
+    repo.read_symbolic_ref("HEAD")
+        .and_then(|name| name.strip_prefix(refs::HEADS_PREFIX).map(str::to_string))
+}
+
+/// Returns the full name of the ref the branch `name` tracks, from `branch.<name>.remote`
+/// and `branch.<name>.merge`. A remote of `.` means a local branch.
+pub(crate) fn branch_upstream(repo: &Repository, name: &str) -> Option<String> {
+    let config = repo.config();
+    let remote = config.get(&format!("branch.{}.remote", name))?;
+    let merge = config.get(&format!("branch.{}.merge", name))?;
+    match remote {
+        "." => Some(merge.to_string()),
+        remote => Some(format!(
+            "{}{}/{}",
+            refs::REMOTES_PREFIX,
+            remote,
+            merge.strip_prefix(refs::HEADS_PREFIX).unwrap_or(merge)
+        )),
+    }
+}
+
+fn local_config(repo: &Repository) -> PathBuf {
+    repo.revy_dir().join("config")
+}
+
+/// Records in the repository config that the branch `name` tracks the ref `upstream`,
+/// a local or remote-tracking branch.
+fn write_branch_upstream(repo: &Repository, name: &str, upstream: &str) -> Result<()> {
+    let (remote, merge) = match upstream.strip_prefix(refs::REMOTES_PREFIX) {
+        Some(remote_branch) => {
+            let (remote, branch) = remote_branch.split_once('/').ok_or_else(|| {
+                RevyError::InvalidArgument(format!("'{}' names no remote branch", upstream))
+            })?;
+            (remote, format!("{}{}", refs::HEADS_PREFIX, branch))
+        }
+        None => (".", upstream.to_string()),
+    };
+    let path = local_config(repo);
+    config::set_value(&path, &format!("branch.{}.remote", name), remote, false)?;
+    config::set_value(&path, &format!("branch.{}.merge", name), &merge, false)
+}
+
+/// Creates the branch `name` at the commit `start` resolves to, `HEAD` by default, and
+/// returns the full name of the upstream it was set up to track, if any.
+///
+/// Like git, a branch started from a remote-tracking branch of a configured remote tracks
+/// it; `track` forces tracking of a local start branch too, or turns it off.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the branch exists and `force` isn't set, or `start` doesn't
+/// name a commit, and an `InvalidArgument` error for an invalid name, for resetting the
+/// current branch, or for tracking a start point that isn't a branch.
+pub(crate) fn create_branch(
+    repo: &Repository,
+    name: &str,
+    start: Option<&str>,
+    force: bool,
+    track: Option<bool>,
+) -> Result<Option<String>> {
+    let ref_name = branch_ref_name(name)?;
+    let start = start.unwrap_or("HEAD");
+    let id = repo.resolve_revision(&format!("{}^{{commit}}", start))?;
+
+    let exists = repo.read_ref(&ref_name).is_some();
+    if exists && !force {
+        return Err(RevyError::BadRef(format!(
+            "a branch named '{}' already exists",
+            name
+        )));
+    }
+    if exists && current_branch(repo).as_deref() == Some(name) {
+        return Err(RevyError::InvalidArgument(format!(
+            "cannot force update the current branch '{}'",
+            name
+        )));
+    }
+
+    let start_branch = repo.expand_ref_name(start).filter(|full_name| {
+        full_name.starts_with(refs::HEADS_PREFIX) || full_name.starts_with(refs::REMOTES_PREFIX)
+    });
+    let upstream = match (track, start_branch) {
+        (Some(false), _) => None,
+        (Some(true), None) => {
+            return Err(RevyError::InvalidArgument(format!(
+                "cannot set up tracking information; starting point '{}' is not a branch",
+                start
+            )))
+        }
+        (Some(true), Some(start_branch)) => Some(start_branch),
+        (None, start_branch) => start_branch.filter(|full_name| {
+            full_name
+                .strip_prefix(refs::REMOTES_PREFIX)
+                .and_then(|remote_branch| remote_branch.split_once('/'))
+                .is_some_and(|(remote, _branch)| {
+                    repo.config()
+                        .get(&format!("remote.{}.url", remote))
+                        .is_some()
+                })
+        }),
+    };
+
+    let message = match exists {
+        true => format!("branch: Reset to {}", start),
+        false => format!("branch: Created from {}", start),
+    };
+    repo.update_ref(&ref_name, &id, Expected::Any, &message)?;
+    if let Some(upstream) = &upstream {
+        write_branch_upstream(repo, name, upstream)?;
+    }
+    Ok(upstream)
+}
+
+/// Deletes the branch `name`, returning the commit it pointed at, along with its reflog and
+/// config.
+///
+/// Unless `force` is set the branch must be merged: reachable from its upstream if it has
+/// one, or from `HEAD` otherwise.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the branch doesn't exist, and an `InvalidArgument` error if
+/// it is the current branch or isn't merged.
+pub(crate) fn delete_branch(repo: &Repository, name: &str, force: bool) -> Result<ObjectId> {
+    let ref_name = format!("{}{}", refs::HEADS_PREFIX, name);
+    let id = repo
+        .resolve_ref(&ref_name)
+        .ok_or_else(|| RevyError::BadRef(format!("branch '{}' not found", name)))?;
+    if current_branch(repo).as_deref() == Some(name) {
+        return Err(RevyError::InvalidArgument(format!(
+            "cannot delete branch '{}' which you are currently on",
+            name
+        )));
+    }
+
+    if !force {
+        let merged_into = branch_upstream(repo, name)
+            .and_then(|upstream| repo.resolve_ref(&upstream))
+            .or_else(|| repo.resolve_ref("HEAD"));
+        let merged = match merged_into {
+            Some(target) => revision::is_ancestor(repo, &id, &target)?,
+            None => false,
+        };
+        if !merged {
+            return Err(RevyError::InvalidArgument(format!(
+                "the branch '{}' is not fully merged\n\
+                 hint: if you are sure you want to delete it, run 'revy branch -D {}'",
+                name, name
+            )));
+        }
+    }
+
+    repo.delete_ref(&ref_name, Expected::Value(id))?;
+    config::rename_section(&local_config(repo), &format!("branch.{}", name), None)?;
+    Ok(id)
+}
+
+/// Renames the branch `old`, the current branch by default, to `new`, along with its reflog
+/// and config. `HEAD` follows if it pointed at `old`.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if `old` doesn't exist or `new` does and `force` isn't set, and
+/// an `InvalidArgument` error for an invalid name or a detached `HEAD` without `old`.
+pub(crate) fn rename_branch(
+    repo: &Repository,
+    old: Option<&str>,
+    new: &str,
+    force: bool,
+) -> Result<()> {
+    let current = current_branch(repo);
+    let old = match old.map(str::to_string).or_else(|| current.clone()) {
+        Some(old) => old,
+        None => {
+            return Err(RevyError::InvalidArgument(
+                "HEAD is detached; name the branch to rename".to_string(),
+            ))
+        }
+    };
+    let old_ref = format!("{}{}", refs::HEADS_PREFIX, old);
+    let new_ref = branch_ref_name(new)?;
+    if old == new {
+        return Ok(());
+    }
+
+    // The current branch may not have any commits yet, in which case only HEAD changes.
+    if repo.read_ref(&old_ref).is_none() {
+        if current.as_deref() == Some(old.as_str()) && repo.read_ref(&new_ref).is_none() {
+            return repo.write_symbolic_ref("HEAD", &new_ref);
+        }
+        return Err(RevyError::BadRef(format!("no branch named '{}'", old)));
+    }
+
+    let path = local_config(repo);
+    if repo.read_ref(&new_ref).is_some() {
+        if !force {
+            return Err(RevyError::BadRef(format!(
+                "a branch named '{}' already exists",
+                new
+            )));
+        }
+        if current.as_deref() == Some(new) {
+            return Err(RevyError::InvalidArgument(format!(
+                "cannot force update the current branch '{}'",
+                new
+            )));
+        }
+        repo.delete_ref(&new_ref, Expected::Any)?;
+        config::rename_section(&path, &format!("branch.{}", new), None)?;
+    }
+
+    repo.rename_ref(
+        &old_ref,
+        &new_ref,
+        &format!("Branch: renamed {} to {}", old_ref, new_ref),
+    )?;
+    config::rename_section(
+        &path,
+        &format!("branch.{}", old),
+        Some(&format!("branch.{}", new)),
+    )?;
+    Ok(())
+}
+
+/// Returns the branch `branch` names, or the current branch, checking that it exists.
+fn existing_branch(repo: &Repository, branch: Option<&str>) -> Result<String> {
+    let name = match branch.map(str::to_string).or_else(|| current_branch(repo)) {
+        Some(name) => name,
+        None => {
+            return Err(RevyError::InvalidArgument(
+                "HEAD is detached; name the branch to change".to_string(),
+            ))
+        }
+    };
+    match repo.read_ref(&format!("{}{}", refs::HEADS_PREFIX, name)) {
+        Some(_value) => Ok(name),
+        None => Err(RevyError::BadRef(format!(
+            "branch '{}' does not exist",
+            name
+        ))),
+    }
+}
+
+/// Makes `branch`, the current branch by default, track `upstream`, a local or
+/// remote-tracking branch. Returns the name of the branch and the full name of the upstream.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if either branch doesn't exist.
+pub(crate) fn set_upstream(
+    repo: &Repository,
+    branch: Option<&str>,
+    upstream: &str,
+) -> Result<(String, String)> {
+    let name = existing_branch(repo, branch)?;
+    let upstream_ref = repo
+        .expand_ref_name(upstream)
+        .filter(|full_name| {
+            full_name.starts_with(refs::HEADS_PREFIX) || full_name.starts_with(refs::REMOTES_PREFIX)
+        })
+        .ok_or_else(|| {
+            RevyError::BadRef(format!(
+                "the requested upstream branch '{}' does not exist",
+                upstream
+            ))
+        })?;
+    write_branch_upstream(repo, &name, &upstream_ref)?;
+    Ok((name, upstream_ref))
+}
+
+/// Stops `branch`, the current branch by default, from tracking an upstream.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if the branch has no upstream.
+pub(crate) fn unset_upstream(repo: &Repository, branch: Option<&str>) -> Result<()> {
+    let name = existing_branch(repo, branch)?;
+    let path = local_config(repo);
+    let removed = config::unset_value(&path, &format!("branch.{}.remote", name), true)?
+        + config::unset_value(&path, &format!("branch.{}.merge", name), true)?;
+    if removed == 0 {
+        return Err(RevyError::InvalidArgument(format!(
+            "branch '{}' has no upstream information",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Lists the branches whose names match one of the glob `patterns`, or all of them, sorted
+/// by name.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if a pattern isn't a valid glob.
+pub(crate) fn list_branches(repo: &Repository, patterns: &[String]) -> Result<Vec<Branch>> {
+    let patterns = patterns
+        .iter()
+        .map(|pattern| {
+            glob::Pattern::new(pattern).map_err(|err| {
+                RevyError::InvalidArgument(format!("invalid pattern '{}': {}", pattern, err))
+            })
+        })
+        .collect::<Result<Vec<glob::Pattern>>>()?;
+
+    let current = repo.read_symbolic_ref("HEAD");
+    let mut branches = Vec::new();
+    for ref_name in repo.list_refs(refs::HEADS_PREFIX) {
+        let name = refs::short_name(&ref_name);
+        if !patterns.is_empty() && !patterns.iter().any(|pattern| pattern.matches(name)) {
+            continue;
+        }
+        if let Some(id) = repo.resolve_ref(&ref_name) {
+            branches.push(Branch {
+                name: name.to_string(),
+                id,
+                is_current: current.as_deref() == Some(ref_name.as_str()),
+                upstream: branch_upstream(repo, name),
+            });
+        }
+    }
+    Ok(branches)
+}
@@ -0,0 +1,498 @@
+//! Checking out commits: making the index and the working tree match the tree of a commit,
+//! and moving `HEAD` to it.
+//!
+//! Like git, paths whose content is the same in `HEAD` and in the target keep their local
+//! changes, and a checkout that would overwrite a local change or an untracked file is
+//! refused before anything is written, unless it is forced.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+
+use crate::branch;
+use crate::error::{Result, RevyError};
+use crate::index::{Index, IndexEntry, StatData};
+use crate::object::{Metadata, ObjectType};
+use crate::object_id::ObjectId;
+use crate::reflog;
+use crate::refs::{self, Expected};
+use crate::repository::Repository;
+use crate::status;
+use crate::tree::FileMode;
+use crate::worktree;
+
+/// What a checkout does to one path.
+#[derive(Debug)]
+enum Update {
+    /// Write the blob to the working tree and stage it.
+    Write(FileMode, ObjectId),
+    /// Remove the file from the working tree and the index.
+    Remove,
+    /// Only drop the path from the index, keeping the file, which no commit has.
+    Unstage,
+}
+
+/// Makes the index and the working tree match the tree of `target`, a commit or tree,
+/// starting from the tree of `HEAD`.
+///
+/// With `force` local changes are thrown away and untracked files in the way overwritten;
+/// files that were only staged are unstaged but kept.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error listing the paths if local changes or untracked files
+/// would be overwritten, in which case nothing has been changed, and an error if an object
+/// can't be read or a file written.
+pub fn checkout_tree(repo: &Repository, target: &ObjectId, force: bool) -> Result<()> {
+    let status = repo.status()?;
+    let mut old = BTreeMap::new();
+    if let Some(head) = &status.head {
+        status::flatten_tree(repo, head, "", &mut old)?;
+    }
+    let mut new = BTreeMap::new();
+    status::flatten_tree(repo, target, "", &mut new)?;
+    let mut index = repo.read_index()?;
+    let unstaged: BTreeSet<&str> = status
+        .unstaged
+        .iter()
+        .map(|(path, _change)| path.as_str())
+        .collect();
+    let patterns = worktree::ignore_patterns(repo)?;
+
+    let paths: BTreeSet<String> = old
+        .keys()
+        .chain(new.keys())
+        .chain(index.entries().iter().map(|entry| &entry.path))
+        .cloned()
+        .collect();
+    let mut updates = Vec::new();
+    let mut changed = Vec::new();
+    let mut untracked = Vec::new();
+    for path in paths {
+        let (old_entry, new_entry) = (old.get(&path), new.get(&path));
+        let staged = index.get(&path).map(|entry| (entry.mode, entry.id));
+        let modified = unstaged.contains(path.as_str());
+        let dirty = staged.as_ref() != old_entry || modified;
+
+        let update = match (new_entry, old_entry) {
+            (Some((mode, id)), _) => Update::Write(*mode, *id),
+            (None, Some(_old)) => Update::Remove,
+            (None, None) => Update::Unstage,
+        };
+        if force {
+            if old_entry != new_entry || dirty {
+                updates.push((path, update));
+            }
+            continue;
+        }
+        if old_entry == new_entry {
+            continue;
+        }
+        if dirty {
+            // A change that already matches the target is kept as it is.
+            if staged.as_ref() != new_entry || modified {
+                changed.push(path);
+            }
+            continue;
+        }
+        if staged.is_none() && blocked_by_untracked(repo, &path, &index, &patterns)? {
+            untracked.push(path);
+            continue;
+        }
+        updates.push((path, update));
+    }
+
+    if !changed.is_empty() {
+        return Err(RevyError::InvalidArgument(format!(
+            "your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+             please commit your changes before you switch branches",
+            changed.join("\n\t")
+        )));
+    }
+    if !untracked.is_empty() {
+        return Err(RevyError::InvalidArgument(format!(
+            "the following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+             please move or remove them before you switch branches",
+            untracked.join("\n\t")
+        )));
+    }
+
+    // Removals go first, so the directories they empty can be replaced by files.
+    for (path, update) in &updates {
+        match update {
+            Update::Remove => {
+                remove_file(repo, path)?;
+                index.remove(path);
+            }
+            Update::Unstage => {
+                index.remove(path);
+            }
+            Update::Write(_mode, _id) => {}
+        }
+    }
+    for (path, update) in &updates {
+        if let Update::Write(mode, id) = update {
+            let stat = write_file(repo, path, *mode, id)?;
+            index.add(IndexEntry::new(path.clone(), *mode, *id, stat));
+        }
+    }
+    repo.write_index(&index)
+}
+
+/// Check if writing the untracked `path` would destroy something: a file that isn't
+/// ignored, a directory holding files that aren't tracked, or a file where one of the
+/// directories above it has to go.
+fn blocked_by_untracked(
+    repo: &Repository,
+    path: &str,
+    index: &Index,
+    patterns: &[String],
+) -> Result<bool> {
+    match fs::symlink_metadata(repo.work_tree().join(path)) {
+        Ok(metadata) if metadata.is_dir() => {
+            return Ok(worktree::list_files(repo, path)?
+                .iter()
+                .any(|file| index.get(file).is_none()))
+        }
+        Ok(_metadata) => return Ok(!worktree::is_ignored(path, patterns)),
+        Err(_err) => {}
+    }
+
+    let mut rest = path;
+    while let Some((dir, _name)) = rest.rsplit_once('/') {
+        if fs::symlink_metadata(repo.work_tree().join(dir)).is_ok_and(|metadata| !metadata.is_dir())
+            && index.get(dir).is_none()
+            && !worktree::is_ignored(dir, patterns)
+        {
+            return Ok(true);
+        }
+        rest = dir;
+    }
+    Ok(false)
+}
+
+/// Removes the working tree file `path`, and the directories above it it leaves empty.
+fn remove_file(repo: &Repository, path: &str) -> Result<()> {
+    let full_path = repo.work_tree().join(path);
+    let result = match fs::symlink_metadata(&full_path) {
+        // A gitlink is checked out as a directory.
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(&full_path),
+        Ok(_metadata) => fs::remove_file(&full_path),
+        Err(_err) => Ok(()),
+    };
+    result.map_err(|err| RevyError::io(format!("unable to remove '{}'", path), err))?;
+
+    let mut parent = full_path.parent();
+    while let Some(dir) = parent {
+        if dir == repo.work_tree() || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+/// Writes the blob `id` to the working tree as `path` with `mode`, replacing whatever is
+/// there, and returns the stat data of the new file. Symbolic links are created from the
+/// target the blob holds, and a gitlink becomes an empty directory.
+fn write_file(repo: &Repository, path: &str, mode: FileMode, id: &ObjectId) -> Result<StatData> {
+    let full_path = repo.work_tree().join(path);
+    let write_error = |err| RevyError::io(format!("unable to write '{}'", path), err);
+
+    // Only ignored files and empty directories can still be in the way, unless forced.
+    let mut in_the_way = Vec::new();
+    let mut ancestor = full_path.parent();
+    while let Some(dir) = ancestor.filter(|dir| *dir != repo.work_tree()) {
+        in_the_way.push(dir.to_path_buf());
+        ancestor = dir.parent();
+    }
+    for dir in in_the_way.iter().rev() {
+        if fs::symlink_metadata(dir).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(dir).map_err(write_error)?;
+        }
+    }
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() && mode == FileMode::Gitlink => {}
+        Ok(metadata) if metadata.is_dir() => {
+            fs::remove_dir_all(&full_path).map_err(write_error)?;
+        }
+        Ok(_metadata) => fs::remove_file(&full_path).map_err(write_error)?,
+        Err(_err) => {}
+    }
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+
+    match mode {
+        FileMode::Gitlink => fs::create_dir_all(&full_path).map_err(write_error)?,
+        // A link target is short, and has to be whole before the link can be made.
+        FileMode::SymbolicLink => match repo.read_object(id)?.metadata {
+            Metadata::Blob(blob) => write_symlink(&full_path, &blob.data).map_err(write_error)?,
+            _ => return Err(not_a_blob(id, path)),
+        },
+        _ => {
+            let mut reader = repo.open_object(id)?;
+            if !matches!(reader.kind, ObjectType::Blob) {
+                return Err(not_a_blob(id, path));
+            }
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                // The umask decides the final permissions, as for any new file.
+                options.mode(match mode {
+                    FileMode::ExecutableFile => 0o777,
+                    _ => 0o666,
+                });
+            }
+            let mut file = options.open(&full_path).map_err(write_error)?;
+            let copied = io::copy(&mut reader, &mut file).and_then(|copied| {
+                file.flush()?;
+                Ok(copied)
+            });
+            match copied {
+                Ok(copied) if copied == reader.size as u64 => {}
+                Ok(_copied) => {
+                    let _ = fs::remove_file(&full_path);
+                    return Err(RevyError::corrupt(id, "blob is shorter than its size"));
+                }
+                Err(err) => {
+                    let _ = fs::remove_file(&full_path);
+                    return Err(write_error(err));
+                }
+            }
+        }
+    }
+
+    let metadata = fs::symlink_metadata(&full_path).map_err(write_error)?;
+    Ok(StatData::from_metadata(&metadata))
+}
+
+fn not_a_blob(id: &ObjectId, path: &str) -> RevyError {
+    RevyError::corrupt(id, format!("'{}' is not a blob", path))
+}
+
+/// Creates a symbolic link at `path` to `target`. Without symbolic links the target is
+/// written as a plain file, as git does with `core.symlinks` off.
+fn write_symlink(path: &std::path::Path, target: &[u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::write(path, target)
+    }
+}
+
+/// Points `HEAD` at `branch`, the full name of a branch holding `id`, or detaches it at the
+/// commit `id` when `branch` is `None`, recording the move in the reflog of `HEAD` with
+/// `message`.
+///
+/// # Errors
+///
+/// Returns an error if `branch` is not a valid ref name or `HEAD` can't be written.
+pub fn update_head(
+    repo: &Repository,
+    branch: Option<&str>,
+    id: &ObjectId,
+    message: &str,
+) -> Result<()> {
+    match branch {
+        Some(branch) => {
+            let old = repo.resolve_ref("HEAD");
+            refs::write_symbolic_ref(repo, "HEAD", branch)?;
+            reflog::append(repo, "HEAD", old.as_ref(), id, message)
+        }
+        None => refs::update_ref(repo, "HEAD", id, Expected::Any, message),
+    }
+}
+
+/// How `switch_branch` moves `HEAD`.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchOptions {
+    /// Create the branch first, at `start`.
+    pub create: bool,
+    /// Where a created branch starts, `HEAD` by default.
+    pub start: Option<String>,
+    /// Detach `HEAD` at the commit the name resolves to, which may be any revision.
+    pub detach: bool,
+    /// Throw away local changes instead of refusing to overwrite them.
+    pub force: bool,
+}
+
+/// Where `switch_branch` or `checkout` left `HEAD`, for the command line to report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Switched {
+    /// The branch `HEAD` points at, or `None` when it is detached.
+    pub branch: Option<String>,
+    /// The commit `HEAD` is at, or `None` on a new branch that has no commits yet.
+    pub id: Option<ObjectId>,
+    /// Whether `HEAD` already pointed at the branch, so it didn't move.
+    pub already_on: bool,
+    /// Whether the branch was created.
+    pub created: bool,
+    /// The full name of the upstream a created branch was set up to track.
+    pub upstream: Option<String>,
+    /// The commit a detached `HEAD` was at before, when it moved away from it.
+    pub left_detached: Option<ObjectId>,
+}
+
+/// Switches to the branch `name`, making the index and the working tree match its commit.
+///
+/// With `create` the branch is first created at `start`, and with `detach` `name` may be
+/// any revision, at whose commit `HEAD` is detached.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if `name` isn't a branch or the branch to create exists, an
+/// `InvalidArgument` error if `name` is a commit but not a branch, and an error if local
+/// changes would be overwritten.
+pub fn switch_branch(repo: &Repository, name: &str, options: &SwitchOptions) -> Result<Switched> {
+    if options.create {
+        return switch_to_new_branch(repo, name, options.start.as_deref(), options.force);
+    }
+    if options.detach {
+        let id = repo.resolve_revision(&format!("{}^{{commit}}", name))?;
+        return checkout_commit(repo, None, name, &id, options.force);
+    }
+    match branch::branch_ref_name(name)
+        .ok()
+        .and_then(|ref_name| repo.resolve_ref(&ref_name))
+    {
+        Some(id) => checkout_commit(repo, Some(name), name, &id, options.force),
+        None if repo
+            .resolve_revision(&format!("{}^{{commit}}", name))
+            .is_ok() =>
+        {
+            Err(RevyError::InvalidArgument(format!(
+                "a branch is expected, got '{}'\n\
+                 hint: use --detach to check out the commit with a detached HEAD",
+                name
+            )))
+        }
+        None => Err(RevyError::BadRef(format!("invalid reference: {}", name))),
+    }
+}
+
+/// Checks out `target`: switches to it if it is a local branch, or detaches `HEAD` at the
+/// commit it names otherwise, or always with `detach`. `force` throws away local changes
+/// instead of refusing to overwrite them.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if `target` doesn't name a commit, and an error if local
+/// changes would be overwritten.
+pub fn checkout(repo: &Repository, target: &str, detach: bool, force: bool) -> Result<Switched> {
+    let branch = match target {
+        _ if detach => None,
+        "HEAD" => repo.current_branch(),
+        _ => branch::branch_ref_name(target)
+            .ok()
+            .filter(|ref_name| repo.resolve_ref(ref_name).is_some())
+            .map(|_ref_name| target.to_string()),
+    };
+    let id = repo.resolve_revision(&format!("{}^{{commit}}", target))?;
+    let id = match &branch {
+        Some(branch) => repo
+            .resolve_ref(&format!("{}{}", refs::HEADS_PREFIX, branch))
+            .unwrap_or(id),
+        None => id,
+    };
+    checkout_commit(repo, branch.as_deref(), target, &id, force)
+}
+
+/// Creates the branch `name` at `start` and switches to it. On an unborn branch without a
+/// `start` there is nothing to check out, and `HEAD` is just pointed at the new name.
+fn switch_to_new_branch(
+    repo: &Repository,
+    name: &str,
+    start: Option<&str>,
+    force: bool,
+) -> Result<Switched> {
+    let ref_name = branch::branch_ref_name(name)?;
+    if repo.read_ref(&ref_name).is_some() {
+        return Err(RevyError::BadRef(format!(
+            "a branch named '{}' already exists",
+            name
+        )));
+    }
+    let mut switched = Switched {
+        branch: Some(name.to_string()),
+        id: None,
+        already_on: false,
+        created: true,
+        upstream: None,
+        left_detached: None,
+    };
+    if start.is_none() && repo.resolve_ref("HEAD").is_none() {
+        repo.write_symbolic_ref("HEAD", &ref_name)?;
+        return Ok(switched);
+    }
+
+    let id = repo.resolve_revision(&format!("{}^{{commit}}", start.unwrap_or("HEAD")))?;
+    // The branch is only created once the working tree could be checked out.
+    repo.checkout_tree(&id, force)?;
+    switched.upstream = repo.create_branch(name, start, false, None)?;
+    let message = format!(
+        "checkout: moving from {} to {}",
+        head_description(repo),
+        name
+    );
+    update_head(repo, Some(&ref_name), &id, &message)?;
+    switched.id = Some(id);
+    Ok(switched)
+}
+
+/// Makes the index and the working tree match the commit `id` and points `HEAD` at
+/// `branch`, or detaches it at `id` when `branch` is `None`. `target` is what was asked
+/// for, and is used in the reflog message.
+fn checkout_commit(
+    repo: &Repository,
+    branch: Option<&str>,
+    target: &str,
+    id: &ObjectId,
+    force: bool,
+) -> Result<Switched> {
+    let old_head = repo.resolve_ref("HEAD");
+    let old_branch = repo.current_branch();
+    let mut switched = Switched {
+        branch: branch.map(str::to_string),
+        id: Some(*id),
+        already_on: false,
+        created: false,
+        upstream: None,
+        left_detached: None,
+    };
+    if branch.is_some() && old_branch.as_deref() == branch {
+        if force {
+            repo.checkout_tree(id, true)?;
+        }
+        switched.already_on = true;
+        return Ok(switched);
+    }
+
+    repo.checkout_tree(id, force)?;
+    let message = format!(
+        "checkout: moving from {} to {}",
+        head_description(repo),
+        target
+    );
+    let ref_name = branch.map(|branch| format!("{}{}", refs::HEADS_PREFIX, branch));
+    update_head(repo, ref_name.as_deref(), id, &message)?;
+
+    if old_branch.is_none() {
+        switched.left_detached = old_head.filter(|old_head| old_head != id);
+    }
+    Ok(switched)
+}
+
+/// Names what `HEAD` is on for a reflog message: the current branch, or the full id of
+/// the commit it is detached at.
+fn head_description(repo: &Repository) -> String {
+    repo.current_branch()
+        .or_else(|| repo.resolve_ref("HEAD").map(|id| id.to_hex()))
+        .unwrap_or_else(|| "HEAD".to_string())
+}
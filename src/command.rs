@@ -1,57 +1,1776 @@
-use crate::utils;
-use std::fs;
+use regex::Regex;
+use revy::checkout::Switched;
+use revy::commit::{Commit, Signature};
+use revy::config::{self, Config, ConfigEntry, ConfigLevel};
+use revy::fsck::Problem;
+use revy::hash::HashAlgorithm;
+use revy::log::{Graph, LogEntry, LogOptions};
+use revy::object::{Metadata, Object, ObjectType};
+use revy::object_id::DEFAULT_ABBREV_LEN;
+use revy::reflog;
+use revy::refs::{self, Expected};
+use revy::revision::{self, Revision};
+use revy::status::Change;
+use revy::tag::Tag;
+use revy::tree::{FileMode, Tree};
+use revy::utils;
+use revy::worktree;
+use revy::{ObjectId, Repository, Result, RevyError};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 
-pub fn setup_revy(repository_name: Option<&str>) {
-    let current_repo_initiation_path = utils::fetch_path_for_repository(repository_name);
+/// Initialize a repository in the directory `repository_name`, or the current directory.
+/// `revy_dir` overrides where the `.revy` directory itself goes.
+pub fn setup_revy(
+    repository_name: Option<&str>,
+    revy_dir: Option<&Path>,
+    object_format: HashAlgorithm,
+) -> Result<()> {
+    let current_repo_initiation_path = match revy_dir {
+        Some(revy_dir) => revy_dir.to_string_lossy().to_string(),
+        None => utils::fetch_path_for_repository(repository_name),
+    };
 
     if utils::check_if_directory_exists(&current_repo_initiation_path) {
         println!(
             "Reinitialized existing Revy repository in {}",
             &current_repo_initiation_path
         );
-        return;
+        return Ok(());
     }
 
-    utils::initialize_repository(&current_repo_initiation_path);
+    let branch = utils::initial_branch()?;
+    utils::initialize_repository(&current_repo_initiation_path, object_format, &branch)?;
 
     println!(
         "Initialized empty Revy repository in {}",
         current_repo_initiation_path
     );
+    Ok(())
+}
+
+/// Create and store a commit object for `tree_hash` with the given parents.
+///
+/// The tree and every parent must already exist in the object database and be of the
+/// right type. The message gets a trailing newline, as git does for `-m`.
+pub fn commit_tree(
+    repo: &Repository,
+    tree_hash: &str,
+    parents: Vec<String>,
+    message: &str,
+) -> Result<Object> {
+    let tree = repo.read_object(&repo.resolve_revision(tree_hash)?)?;
+    if !matches!(tree.kind, ObjectType::Tree) {
+        return Err(RevyError::InvalidArgument(format!(
+            "{} is not a valid 'tree' object",
+            tree_hash
+        )));
+    }
+
+    let mut parent_hashes = Vec::new();
+    for parent in &parents {
+        let parent_object = repo.read_object(&repo.resolve_revision(parent)?)?;
+        if !matches!(parent_object.kind, ObjectType::Commit) {
+            return Err(RevyError::InvalidArgument(format!(
+                "{} is not a valid 'commit' object",
+                parent
+            )));
+        }
+        parent_hashes.push(parent_object.hash);
+    }
+
+    let mut message = message.to_string();
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    let commit = Commit::new(
+        tree.hash,
+        parent_hashes,
+        Signature::for_repository(repo, "AUTHOR")?,
+        Signature::for_repository(repo, "COMMITTER")?,
+        message,
+    );
+    repo.write_object(ObjectType::Commit, Metadata::Commit(commit))
+}
+
+/// Create the tag `name` pointing at `target`.
+///
+/// Without a message a lightweight tag is made, i.e. a ref pointing straight at the target.
+/// With a message an annotated tag object is stored first and the ref points at it.
+pub fn create_tag(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    if !refs::is_valid_ref_name(name) {
+        return Err(RevyError::InvalidArgument(format!(
+            "'{}' is not a valid tag name",
+            name
+        )));
+    }
+
+    let ref_name = format!("{}{}", refs::TAGS_PREFIX, name);
+    if !force && repo.read_ref(&ref_name).is_some() {
+        return Err(RevyError::BadRef(format!("tag '{}' already exists", name)));
+    }
+
+    let target_hash = repo.resolve_revision(target)?;
+    let target_object = repo.read_object(&target_hash)?;
+
+    let tag_hash = match message {
+        Some(message) => {
+            let mut message = message.to_string();
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
+            let tag = Tag::new(
+                target_hash,
+                target_object.kind,
+                name.to_string(),
+                Signature::for_repository(repo, "COMMITTER")?,
+                message,
+            );
+            repo.write_object(ObjectType::Tag, Metadata::Tag(tag))?.hash
+        }
+        None => target_hash,
+    };
+
+    repo.write_ref(&ref_name, &tag_hash)
+}
+
+/// Delete the tag `name`, returning the hash it pointed at.
+pub fn delete_tag(repo: &Repository, name: &str) -> Result<ObjectId> {
+    let ref_name = format!("{}{}", refs::TAGS_PREFIX, name);
+    let old_hash = repo
+        .resolve_ref(&ref_name)
+        .ok_or_else(|| RevyError::BadRef(format!("tag '{}' not found.", name)))?;
+    repo.delete_ref(&ref_name, Expected::Value(old_hash))?;
+    Ok(old_hash)
+}
+
+/// List tag names, optionally filtered by a glob `pattern`.
+pub fn list_tags(repo: &Repository, pattern: Option<&str>) -> Result<Vec<String>> {
+    let pattern = match pattern {
+        Some(pattern) => Some(glob::Pattern::new(pattern).map_err(|err| {
+            RevyError::InvalidArgument(format!("invalid pattern '{}': {}", pattern, err))
+        })?),
+        None => None,
+    };
+
+    Ok(repo
+        .list_refs(refs::TAGS_PREFIX)
+        .into_iter()
+        .filter_map(|ref_name| {
+            ref_name
+                .strip_prefix(refs::TAGS_PREFIX)
+                .map(|name| name.to_string())
+        })
+        .filter(|name| match &pattern {
+            Some(pattern) => pattern.matches(name),
+            None => true,
+        })
+        .collect())
+}
+
+/// List the branches matching `patterns`, or all of them, marking the current one with `*`.
+/// A detached `HEAD` is listed first.
+///
+/// With `verbose` each branch also shows its commit and subject, and how far it is ahead of
+/// and behind its upstream; above 1 the upstream's name is shown too.
+pub fn list_branches<W: Write>(
+    repo: &Repository,
+    patterns: &[String],
+    verbose: u8,
+    out: &mut W,
+) -> Result<()> {
+    // Each branch as (is current, label, commit, upstream).
+    let mut branches = Vec::new();
+    if let (None, Some(head), true) = (
+        repo.read_symbolic_ref("HEAD"),
+        repo.resolve_ref("HEAD"),
+        patterns.is_empty(),
+    ) {
+        let label = format!(
+            "(HEAD detached at {})",
+            repo.abbreviate(&head, DEFAULT_ABBREV_LEN)
+        );
+        branches.push((true, label, head, None));
+    }
+    for branch in repo.list_branches(patterns)? {
+        branches.push((branch.is_current, branch.name, branch.id, branch.upstream));
+    }
+
+    let width = branches
+        .iter()
+        .map(|(_current, label, _id, _upstream)| label.len())
+        .max()
+        .unwrap_or(0);
+    for (is_current, label, id, upstream) in &branches {
+        let marker = if *is_current { '*' } else { ' ' };
+        if verbose == 0 {
+            writeln!(out, "{} {}", marker, label).map_err(write_error)?;
+            continue;
+        }
+        let subject = match repo.read_object(id)?.metadata {
+            Metadata::Commit(commit) => commit.summary().to_string(),
+            _ => String::new(),
+        };
+        writeln!(
+            out,
+            "{} {:<width$} {} {}{}",
+            marker,
+            label,
+            repo.abbreviate(id, DEFAULT_ABBREV_LEN),
+            tracking_info(repo, upstream.as_deref(), id, verbose > 1)?,
+            subject,
+            width = width
+        )
+        .map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// Describes how a branch at `id` compares to its `upstream` for `branch -v`, like
+/// `[ahead 1, behind 2] `, with the upstream's name first when `show_name` is set. The
+/// upstream is `gone` when its ref no longer exists.
+fn tracking_info(
+    repo: &Repository,
+    upstream: Option<&str>,
+    id: &ObjectId,
+    show_name: bool,
+) -> Result<String> {
+    let Some(upstream) = upstream else {
+        return Ok(String::new());
+    };
+    let counts = match repo.resolve_ref(upstream) {
+        None => "gone".to_string(),
+        Some(upstream_id) => match revision::ahead_behind(repo, id, &upstream_id)? {
+            (0, 0) => String::new(),
+            (ahead, 0) => format!("ahead {}", ahead),
+            (0, behind) => format!("behind {}", behind),
+            (ahead, behind) => format!("ahead {}, behind {}", ahead, behind),
+        },
+    };
+    let name = refs::short_name(upstream);
+    Ok(match (show_name, counts.is_empty()) {
+        (false, true) => String::new(),
+        (false, false) => format!("[{}] ", counts),
+        (true, true) => format!("[{}] ", name),
+        (true, false) => format!("[{}: {}] ", name, counts),
+    })
+}
+
+/// Report where a switch or checkout left `HEAD`, like git: on stderr, apart from the
+/// tracking set up for a new branch, which goes to `out`.
+pub fn report_switch<W: Write>(repo: &Repository, switched: &Switched, out: &mut W) -> Result<()> {
+    if let (Some(branch), Some(upstream)) = (&switched.branch, &switched.upstream) {
+        writeln!(
+            out,
+            "branch '{}' set up to track '{}'.",
+            branch,
+            refs::short_name(upstream)
+        )
+        .map_err(write_error)?;
+    }
+    if let Some(old_head) = &switched.left_detached {
+        eprintln!(
+            "Previous HEAD position was {}",
+            commit_line(repo, old_head)?
+        );
+    }
+    match (&switched.branch, &switched.id) {
+        (Some(branch), _) if switched.already_on => eprintln!("Already on '{}'", branch),
+        (Some(branch), _) if switched.created => {
+            eprintln!("Switched to a new branch '{}'", branch)
+        }
+        (Some(branch), _) => eprintln!("Switched to branch '{}'", branch),
+        (None, Some(id)) => eprintln!("HEAD is now at {}", commit_line(repo, id)?),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Formats the commit `id` as its abbreviated id and subject, like `1a2b3c4 Fix things`.
+fn commit_line(repo: &Repository, id: &ObjectId) -> Result<String> {
+    let subject = match repo.read_object(id)?.metadata {
+        Metadata::Commit(commit) => commit.summary().to_string(),
+        _ => String::new(),
+    };
+    Ok(format!(
+        "{} {}",
+        repo.abbreviate(id, DEFAULT_ABBREV_LEN),
+        subject
+    ))
+}
+
+/// Write the raw content of the object `name` to `out`, asserting it is of type `kind`.
+///
+/// Annotated tags are peeled until an object of the requested type is found, and a commit
+/// to its tree when a tree is asked for, as `git cat-file <type> <object>` does.
+pub fn cat_file_raw<W: Write>(
+    repo: &Repository,
+    name: &str,
+    kind: ObjectType,
+    out: &mut W,
+) -> Result<()> {
+    let mut hash = repo.resolve_revision(name)?;
+    loop {
+        let object_kind = repo.open_object(&hash)?.kind;
+        if object_kind == kind {
+            break;
+        }
+        match repo.read_object(&hash)?.metadata {
+            Metadata::Tag(tag) => hash = tag.object,
+            Metadata::Commit(commit) if kind == ObjectType::Tree => hash = commit.tree,
+            _ => return Err(RevyError::InvalidArgument(format!("{}: bad file", name))),
+        }
+    }
+
+    let mut stream = repo.open_object(&hash)?;
+    std::io::copy(&mut stream, out).map_err(write_error)?;
+    Ok(())
+}
+
+/// Answer `cat-file --batch` / `--batch-check` queries.
+///
+/// Object names are read from stdin one per line, or every loose object is listed when
+/// `all_objects` is set. Each object gets a `<id> <type> <size>` line, followed by its
+/// content and a newline when `contents` is set. Names that don't resolve are reported as
+/// `<name> missing` (or `<name> ambiguous`) and processing continues.
+pub fn cat_file_batch(repo: &Repository, contents: bool, all_objects: bool) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+
+    if all_objects {
+        for hash in repo.list_objects()? {
+            write_batch_entry(repo, &mut out, &hash, contents)?;
+        }
+    } else {
+        for line in std::io::stdin().lock().lines() {
+            let line = line.map_err(|err| RevyError::io("Failed to read from stdin", err))?;
+            let name = line.trim();
+            match repo.resolve_revision(name) {
+                Ok(hash) if repo.has_object(&hash) => {
+                    write_batch_entry(repo, &mut out, &hash, contents)?;
+                }
+                Err(RevyError::AmbiguousObject { .. }) => {
+                    writeln!(out, "{} ambiguous", name).map_err(write_error)?;
+                }
+                _ => writeln!(out, "{} missing", name).map_err(write_error)?,
+            }
+            // Scripts often feed one name at a time and wait for the answer.
+            out.flush().map_err(write_error)?;
+        }
+    }
+
+    out.flush().map_err(write_error)
+}
+
+fn write_batch_entry<W: Write>(
+    repo: &Repository,
+    out: &mut W,
+    hash: &ObjectId,
+    contents: bool,
+) -> Result<()> {
+    let mut stream = repo.open_object(hash)?;
+    writeln!(out, "{} {} {}", hash, stream.kind.as_str(), stream.size).map_err(write_error)?;
+    if contents {
+        std::io::copy(&mut stream, out).map_err(write_error)?;
+        writeln!(out).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// What `ls-tree` shows and how it walks the tree.
+pub struct LsTreeOptions {
+    /// Recurse into subtrees.
+    pub recursive: bool,
+    /// Show only tree entries.
+    pub dirs_only: bool,
+    /// Show tree entries even when recursing into them.
+    pub show_trees: bool,
+    /// Show the size of blob entries.
+    pub long: bool,
+    /// Show only the paths.
+    pub name_only: bool,
+    /// Only list entries matching these paths.
+    pub paths: Vec<String>,
+}
+
+/// List the contents of the tree named by `name` (a tree, or a commit or tag that peels to
+/// one) to `out`, in `ls-tree` format.
+pub fn ls_tree<W: Write>(
+    repo: &Repository,
+    name: &str,
+    options: &LsTreeOptions,
+    out: &mut W,
+) -> Result<()> {
+    let tree = repo.read_tree(&repo.resolve_revision(name)?)?;
+    ls_tree_walk(repo, &tree, "", options, out)
+}
+
+fn ls_tree_walk<W: Write>(
+    repo: &Repository,
+    tree: &Tree,
+    prefix: &str,
+    options: &LsTreeOptions,
+    out: &mut W,
+) -> Result<()> {
+    for node in &tree.data {
+        let path = format!("{}{}", prefix, node.name());
+        let is_tree = node.mode() == FileMode::Directory;
+        let (matched, leads_to_match) = match_ls_tree_path(&path, is_tree, &options.paths);
+
+        let shown = if is_tree {
+            (matched && (!options.recursive || options.show_trees || options.dirs_only))
+                || (leads_to_match && options.show_trees)
+        } else {
+            matched && !options.dirs_only
+        };
+
+        if shown {
+            let line = if options.name_only {
+                path.clone()
+            } else if options.long {
+                let size = match node.mode() {
+                    FileMode::Directory | FileMode::Gitlink => "-".to_string(),
+                    _ => repo.open_object(node.hash())?.size.to_string(),
+                };
+                format!(
+                    "{} {} {} {:>7}\t{}",
+                    node.mode().as_u32_str(),
+                    node.mode().as_str(),
+                    node.hash(),
+                    size,
+                    path
+                )
+            } else {
+                node.as_ls_tree_line(&path)
+            };
+            writeln!(out, "{}", line).map_err(write_error)?;
+        }
+
+        if is_tree && (leads_to_match || (matched && options.recursive)) {
+            let subtree = repo.read_tree(node.hash())?;
+            ls_tree_walk(repo, &subtree, &format!("{}/", path), options, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Matches an entry path against `ls-tree` path arguments.
+///
+/// Returns whether the entry itself is selected, and whether it is a directory that has to
+/// be entered to reach a selected path. A path argument with a trailing slash selects the
+/// contents of the directory rather than the directory entry.
+fn match_ls_tree_path(path: &str, is_tree: bool, paths: &[String]) -> (bool, bool) {
+    if paths.is_empty() {
+        return (true, false);
+    }
+
+    let mut matched = false;
+    let mut leads_to_match = false;
+    for pathspec in paths {
+        let trimmed = pathspec.trim_end_matches('/');
+        if path == trimmed {
+            if pathspec.ends_with('/') && is_tree {
+                leads_to_match = true;
+            } else {
+                matched = true;
+            }
+        } else if path.starts_with(&format!("{}/", trimmed)) {
+            matched = true;
+        } else if is_tree && trimmed.starts_with(&format!("{}/", path)) {
+            leads_to_match = true;
+        }
+    }
+    (matched, leads_to_match)
+}
+
+/// Stage the files matched by `pathspecs` in the index.
+///
+/// A directory adds every file under it that isn't ignored, and stages the removal of
+/// tracked files under it that were deleted. A path that no longer exists but is tracked
+/// stages its removal. Paths with glob characters are matched against the whole working
+/// tree. Naming an ignored file fails unless `force` is set.
+pub fn add(repo: &Repository, pathspecs: &[PathBuf], force: bool) -> Result<()> {
+    let mut index = repo.read_index()?;
+    let patterns = worktree::ignore_patterns(repo)?;
+
+    for pathspec in pathspecs {
+        let path = worktree::relative_path(repo, pathspec)?;
+        let full_path = repo.work_tree().join(&path);
+        let exists = full_path.symlink_metadata().is_ok();
+        let is_directory = path.is_empty()
+            || (full_path
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_dir())
+                && !full_path.join(utils::REPO_FOLDER_NAME).is_dir());
+
+        if is_directory {
+            let prefix = directory_prefix(&path);
+            let deleted: Vec<String> = index
+                .entries()
+                .iter()
+                .filter(|entry| entry.path.starts_with(&prefix))
+                .filter(|entry| {
+                    repo.work_tree()
+                        .join(&entry.path)
+                        .symlink_metadata()
+                        .is_err()
+                })
+                .map(|entry| entry.path.clone())
+                .collect();
+            for path in deleted {
+                index.remove(&path);
+            }
+            for file in worktree::list_files(repo, &path)? {
+                index.add(worktree::entry_for(repo, &file, true)?);
+            }
+        } else if exists {
+            if !force && index.get(&path).is_none() && worktree::is_ignored(&path, &patterns) {
+                return Err(RevyError::InvalidArgument(format!(
+                    "The following paths are ignored by one of your .revyignore files:\n{}\n\
+                     hint: Use -f if you really want to add them.",
+                    path
+                )));
+            }
+            index.add(worktree::entry_for(repo, &path, true)?);
+        } else if index.get(&path).is_some() {
+            index.remove(&path);
+        } else if !index.remove_dir(&path).is_empty() {
+            // Every tracked file under a deleted directory is staged for removal.
+        } else if is_glob(&path) {
+            let pattern = glob::Pattern::new(&path).map_err(|err| {
+                RevyError::InvalidArgument(format!("invalid pathspec '{}': {}", path, err))
+            })?;
+            let files: Vec<String> = worktree::list_files(repo, "")?
+                .into_iter()
+                .filter(|file| pattern.matches(file))
+                .collect();
+            if files.is_empty() {
+                return Err(pathspec_error(pathspec));
+            }
+            for file in files {
+                index.add(worktree::entry_for(repo, &file, true)?);
+            }
+        } else {
+            return Err(pathspec_error(pathspec));
+        }
+    }
+
+    repo.write_index(&index)
+}
+
+/// Remove the files matched by `pathspecs` from the index and, unless `cached` is set,
+/// from the working tree, printing each removed path.
+///
+/// Directories are only removed with `recursive`. Unless `force` is set, like git, files
+/// whose working tree content differs from the index, or whose staged content differs from
+/// `HEAD`, are only removed from the index with `cached`, and not at all if both differ, so
+/// no change is lost.
+pub fn rm(
+    repo: &Repository,
+    pathspecs: &[PathBuf],
+    cached: bool,
+    recursive: bool,
+    force: bool,
+) -> Result<()> {
+    let mut index = repo.read_index()?;
+
+    let mut removed = BTreeSet::new();
+    for pathspec in pathspecs {
+        let path = worktree::relative_path(repo, pathspec)?;
+        if index.get(&path).is_some() {
+            removed.insert(path);
+            continue;
+        }
+
+        let prefix = directory_prefix(&path);
+        let matched: Vec<String> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.path.starts_with(&prefix))
+            .map(|entry| entry.path.clone())
+            .collect();
+        if matched.is_empty() {
+            return Err(pathspec_error(pathspec));
+        }
+        if !recursive {
+            return Err(RevyError::InvalidArgument(format!(
+                "not removing '{}' recursively without -r",
+                pathspec.display()
+            )));
+        }
+        removed.extend(matched);
+    }
+
+    if !force {
+        let status = repo.status()?;
+        let staged: HashSet<&str> = status
+            .staged
+            .iter()
+            .map(|(path, _change)| path.as_str())
+            .collect();
+        // A file already gone from the working tree has nothing left to lose.
+        let modified: HashSet<&str> = status
+            .unstaged
+            .iter()
+            .filter(|(_path, change)| *change != Change::Deleted)
+            .map(|(path, _change)| path.as_str())
+            .collect();
+        for path in &removed {
+            let (staged, modified) = (
+                staged.contains(path.as_str()),
+                modified.contains(path.as_str()),
+            );
+            let problem = match (staged, modified) {
+                (true, true) => "has staged content different from both the file and the HEAD",
+                (true, false) if !cached => "has changes staged in the index",
+                (false, true) if !cached => "has local modifications",
+                _ => continue,
+            };
+            let hint = match (staged, modified) {
+                (true, true) => "use -f to force removal",
+                _ => "use --cached to keep the file, or -f to force removal",
+            };
+            return Err(RevyError::InvalidArgument(format!(
+                "the following file {}:\n    {}\n({})",
+                problem, path, hint
+            )));
+        }
+    }
+
+    for path in &removed {
+        index.remove(path);
+        println!("rm '{}'", path);
+        if !cached {
+            remove_work_tree_file(repo, path)?;
+        }
+    }
+    repo.write_index(&index)
+}
+
+/// Deletes the working tree file `path`, and the directories it leaves empty.
+fn remove_work_tree_file(repo: &Repository, path: &str) -> Result<()> {
+    let full_path = repo.work_tree().join(path);
+    match std::fs::remove_file(&full_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(RevyError::io(format!("Failed to remove '{}'", path), err)),
+    }
+
+    let mut parent = full_path.parent();
+    while let Some(dir) = parent {
+        if dir == repo.work_tree() || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+/// Returns the prefix the paths of entries under the directory `path` start with.
+fn directory_prefix(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+fn pathspec_error(pathspec: &Path) -> RevyError {
+    RevyError::InvalidArgument(format!(
+        "pathspec '{}' did not match any files",
+        pathspec.display()
+    ))
+}
+
+/// Update, or with `delete` remove, the ref `name`, following symbolic refs when `deref` is
+/// set.
+///
+/// `new_value` and `old_value` are revisions. The update only happens if the ref currently
+/// points at `old_value`; an all zero `old_value` means the ref must not exist yet, and an
+/// all zero `new_value` deletes the ref. `message` is the reason recorded in the reflog.
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    new_value: Option<&str>,
+    old_value: Option<&str>,
+    delete: bool,
+    deref: bool,
+    message: &str,
+) -> Result<()> {
+    let name = if deref {
+        repo.follow_symbolic_refs(name)?
+    } else {
+        name.to_string()
+    };
+    let expected = match old_value {
+        None => Expected::Any,
+        Some(value) if is_null_value(value) => Expected::Missing,
+        Some(value) => Expected::Value(repo.resolve_revision(value)?),
+    };
+
+    match new_value {
+        Some(value) if !delete && !is_null_value(value) => {
+            let id = repo.resolve_revision(value)?;
+            repo.update_ref(&name, &id, expected, message)
+        }
+        Some(_value) if !delete => repo.delete_ref(&name, expected),
+        _ if delete => repo.delete_ref(&name, expected),
+        _ => Err(RevyError::InvalidArgument(format!(
+            "update-ref needs a new value for '{}'",
+            name
+        ))),
+    }
+}
+
+/// Check if `value` is the all zero id (or empty), which stands for "no object".
+fn is_null_value(value: &str) -> bool {
+    value.chars().all(|c| c == '0')
+}
+
+/// Read, set or delete the symbolic ref `name`.
+///
+/// With a `target`, `name` is pointed at it. Otherwise the ref `name` points at is printed,
+/// shortened to its branch or tag name with `short`. Returns `false` when reading a ref that
+/// isn't symbolic with `quiet`, so the caller can exit with status 1 without a message.
+pub fn symbolic_ref(
+    repo: &Repository,
+    name: &str,
+    target: Option<&str>,
+    short: bool,
+    quiet: bool,
+    delete: bool,
+) -> Result<bool> {
+    if delete {
+        if name == "HEAD" {
+            return Err(RevyError::InvalidArgument(
+                "deleting 'HEAD' is not allowed".to_string(),
+            ));
+        }
+        if repo.read_symbolic_ref(name).is_none() {
+            if quiet {
+                return Ok(false);
+            }
+            return Err(RevyError::BadRef(format!(
+                "Cannot delete {}, not a symbolic ref",
+                name
+            )));
+        }
+        repo.delete_ref(name, Expected::Any)?;
+        return Ok(true);
+    }
+    if let Some(target) = target {
+        repo.write_symbolic_ref(name, target)?;
+        return Ok(true);
+    }
+
+    match repo.read_symbolic_ref(name) {
+        Some(target) if short => println!("{}", refs::short_name(&target)),
+        Some(target) => println!("{}", target),
+        None if quiet => return Ok(false),
+        None => {
+            return Err(RevyError::BadRef(format!(
+                "ref {} is not a symbolic ref",
+                name
+            )))
+        }
+    }
+    Ok(true)
+}
+
+/// What `show-ref` lists and how.
+pub struct ShowRefOptions {
+    /// Only show branches; combined with `tags`, branches and tags.
+    pub heads: bool,
+    /// Only show tags; combined with `heads`, branches and tags.
+    pub tags: bool,
+    /// Also show `HEAD`.
+    pub head: bool,
+    /// Show only the object ids, without the ref names.
+    pub hash_only: bool,
+    /// Also show what annotated tags point at, as `<ref>^{}`.
+    pub dereference: bool,
+    /// Treat the patterns as exact ref names, each of which must exist.
+    pub verify: bool,
+    /// Print nothing, only report through the result whether refs were found.
+    pub quiet: bool,
+    /// Only show refs whose name is a pattern or ends with `/<pattern>`.
+    pub patterns: Vec<String>,
+}
+
+/// List refs and the objects they point at to `out`, one `<id> <ref>` per line.
+///
+/// Returns whether any ref was shown, since `show-ref` exits with status 1 when none was.
+pub fn show_ref<W: Write>(
+    repo: &Repository,
+    options: &ShowRefOptions,
+    out: &mut W,
+) -> Result<bool> {
+    let mut names = Vec::new();
+    if options.verify {
+        for name in &options.patterns {
+            let exists =
+                (name == "HEAD" || name.starts_with("refs/")) && repo.resolve_ref(name).is_some();
+            if !exists {
+                if options.quiet {
+                    return Ok(false);
+                }
+                return Err(RevyError::BadRef(format!("'{}' - not a valid ref", name)));
+            }
+            names.push(name.clone());
+        }
+    } else {
+        if options.head {
+            names.push("HEAD".to_string());
+        }
+        names.extend(repo.list_refs("refs/").into_iter().filter(|name| {
+            let kind_matches = (!options.heads && !options.tags)
+                || (options.heads && name.starts_with(refs::HEADS_PREFIX))
+                || (options.tags && name.starts_with(refs::TAGS_PREFIX));
+            let pattern_matches = options.patterns.is_empty()
+                || options
+                    .patterns
+                    .iter()
+                    .any(|pattern| name == pattern || name.ends_with(&format!("/{}", pattern)));
+            kind_matches && pattern_matches
+        }));
+    }
+
+    let mut found = false;
+    for name in names {
+        let id = match repo.resolve_ref(&name) {
+            Some(id) => id,
+            None => continue,
+        };
+        found = true;
+        if options.quiet {
+            continue;
+        }
+        write_show_ref_line(out, &id, &name, options.hash_only)?;
+        if options.dereference {
+            if let Some(peeled) = peel_tag(repo, &name, &id)? {
+                write_show_ref_line(out, &peeled, &format!("{}^{{}}", name), options.hash_only)?;
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn write_show_ref_line<W: Write>(
+    out: &mut W,
+    id: &ObjectId,
+    name: &str,
+    hash_only: bool,
+) -> Result<()> {
+    if hash_only {
+        writeln!(out, "{}", id).map_err(write_error)
+    } else {
+        writeln!(out, "{} {}", id, name).map_err(write_error)
+    }
+}
+
+/// Returns what the ref `name`, pointing at `id`, peels to if `id` is an annotated tag,
+/// following tags of tags.
+fn peel_tag(repo: &Repository, name: &str, id: &ObjectId) -> Result<Option<ObjectId>> {
+    if let Some(peeled) = repo.packed_peeled(name) {
+        return Ok(Some(peeled));
+    }
+    let mut current = *id;
+    let mut peeled = None;
+    while repo.open_object(&current)?.kind == ObjectType::Tag {
+        match repo.read_object(&current)?.metadata {
+            Metadata::Tag(tag) => current = tag.object,
+            _ => break,
+        }
+        peeled = Some(current);
+    }
+    Ok(peeled)
+}
+
+/// The format `for-each-ref` uses without `--format`.
+pub const DEFAULT_REF_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/// A piece of a `for-each-ref` format.
+enum FormatPart {
+    Literal(String),
+    /// `%(name)`, `%(name:short)`, or with `*` the same field of the object a tag points at.
+    Field {
+        name: String,
+        short: bool,
+        deref: bool,
+    },
+}
+
+const REF_FIELDS: &[&str] = &[
+    "refname",
+    "objectname",
+    "objecttype",
+    "objectsize",
+    "subject",
+    "symref",
+];
+
+/// Parses a `for-each-ref` format, with `%%` for a literal `%` and `%xx` for a hex escaped
+/// byte.
+fn parse_ref_format(format: &str) -> Result<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = format;
+    while let Some(position) = rest.find('%') {
+        literal.push_str(&rest[..position]);
+        rest = &rest[position + 1..];
+
+        if let Some(after) = rest.strip_prefix('%') {
+            literal.push('%');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('(') {
+            let end = after.find(')').ok_or_else(|| {
+                RevyError::InvalidArgument(format!("malformed format string {}", format))
+            })?;
+            let atom = &after[..end];
+            rest = &after[end + 1..];
+
+            let (deref, atom) = match atom.strip_prefix('*') {
+                Some(atom) => (true, atom),
+                None => (false, atom),
+            };
+            let (name, short) = match atom.split_once(':') {
+                Some((name, "short")) => (name, true),
+                Some(_modifier) => {
+                    return Err(RevyError::InvalidArgument(format!(
+                        "unrecognized %({}) argument",
+                        atom
+                    )))
+                }
+                None => (atom, false),
+            };
+            if !REF_FIELDS.contains(&name) {
+                return Err(RevyError::InvalidArgument(format!(
+                    "unknown field name: {}",
+                    name
+                )));
+            }
+            if !literal.is_empty() {
+                parts.push(FormatPart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(FormatPart::Field {
+                name: name.to_string(),
+                short,
+                deref,
+            });
+        } else if let Some(byte) = rest
+            .get(..2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            literal.push(byte as char);
+            rest = &rest[2..];
+        } else {
+            literal.push('%');
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Check if the ref `name` matches a `for-each-ref` pattern: as a glob, or literally, either
+/// entirely or up to a `/`.
+fn ref_matches(name: &str, pattern: &str) -> bool {
+    if is_glob(pattern) {
+        return glob::Pattern::new(pattern).is_ok_and(|glob| glob.matches(name));
+    }
+    let pattern = pattern.trim_end_matches('/');
+    name == pattern
+        || name
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The fields of the object a ref points at that `for-each-ref` can show.
+struct RefObject {
+    id: ObjectId,
+    kind: ObjectType,
+    size: usize,
+    subject: String,
+    /// The object an annotated tag points at.
+    target: Option<Box<RefObject>>,
+}
+
+impl RefObject {
+    /// Reads the object `id`, and the object it points at if it is a tag and `deref` is set.
+    fn load(repo: &Repository, id: &ObjectId, deref: bool) -> Result<RefObject> {
+        let object = repo.read_object(id)?;
+        let (subject, target) = match &object.metadata {
+            Metadata::Commit(commit) => (commit.summary().to_string(), None),
+            Metadata::Tag(tag) => {
+                let subject = tag.message.lines().next().unwrap_or("").to_string();
+                let target = if deref {
+                    Some(Box::new(RefObject::load(repo, &tag.object, false)?))
+                } else {
+                    None
+                };
+                (subject, target)
+            }
+            _ => (String::new(), None),
+        };
+        Ok(RefObject {
+            id: *id,
+            kind: object.kind,
+            size: object.size,
+            subject,
+            target,
+        })
+    }
+}
+
+/// List refs matching `patterns` (all refs when empty) to `out`, formatted with `format`,
+/// stopping after `count` refs if given.
+pub fn for_each_ref<W: Write>(
+    repo: &Repository,
+    format: &str,
+    patterns: &[String],
+    count: Option<usize>,
+    out: &mut W,
+) -> Result<()> {
+    let parts = parse_ref_format(format)?;
+    let deref = parts
+        .iter()
+        .any(|part| matches!(part, FormatPart::Field { deref: true, .. }));
+
+    let names = repo.list_refs("refs/").into_iter().filter(|name| {
+        patterns.is_empty() || patterns.iter().any(|pattern| ref_matches(name, pattern))
+    });
+    for name in names.take(count.unwrap_or(usize::MAX)) {
+        let id = match repo.resolve_ref(&name) {
+            Some(id) => id,
+            None => continue,
+        };
+        let object = RefObject::load(repo, &id, deref)?;
+
+        let mut line = String::new();
+        for part in &parts {
+            match part {
+                FormatPart::Literal(text) => line.push_str(text),
+                FormatPart::Field {
+                    name: field,
+                    short,
+                    deref,
+                } => {
+                    let object = match (deref, &object.target) {
+                        (false, _) => &object,
+                        (true, Some(target)) => target,
+                        (true, None) => continue,
+                    };
+                    let value = match field.as_str() {
+                        "refname" if *short => refs::short_name(&name).to_string(),
+                        "refname" => name.clone(),
+                        "objectname" if *short => object.id.short(),
+                        "objectname" => object.id.to_hex(),
+                        "objecttype" => object.kind.as_str().to_string(),
+                        "objectsize" => object.size.to_string(),
+                        "subject" => object.subject.clone(),
+                        "symref" => match repo.read_symbolic_ref(&name) {
+                            Some(target) if *short => refs::short_name(&target).to_string(),
+                            Some(target) => target,
+                            None => String::new(),
+                        },
+                        _ => unreachable!("fields are checked when the format is parsed"),
+                    };
+                    line.push_str(&value);
+                }
+            }
+        }
+        writeln!(out, "{}", line).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// How `status` prints its report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// Sections with hints, for people.
+    Long,
+    /// One `XY path` line per changed path, relative to the current directory.
+    Short,
+    /// Like `Short`, but with paths relative to the root of the working tree, so the output
+    /// doesn't depend on where it is run from.
+    Porcelain,
+}
+
+/// Print the differences between `HEAD`, the index and the working tree.
+pub fn status(repo: &Repository, format: StatusFormat) -> Result<()> {
+    let status = repo.status()?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    let cwd = match format {
+        StatusFormat::Porcelain => String::new(),
+        _ => worktree::relative_path(repo, Path::new(".")).unwrap_or_default(),
+    };
+    let display = |path: &str| relative_to(&cwd, path);
+
+    if format != StatusFormat::Long {
+        let mut codes: BTreeMap<&str, [char; 2]> = BTreeMap::new();
+        for (path, change) in &status.staged {
+            codes.entry(path).or_insert([' '; 2])[0] = change.code();
+        }
+        for (path, change) in &status.unstaged {
+            codes.entry(path).or_insert([' '; 2])[1] = change.code();
+        }
+        for (path, [staged, unstaged]) in codes {
+            writeln!(out, "{}{} {}", staged, unstaged, display(path)).map_err(write_error)?;
+        }
+        for path in &status.untracked {
+            writeln!(out, "?? {}", display(path)).map_err(write_error)?;
+        }
+        return out.flush().map_err(write_error);
+    }
+
+    match repo.read_symbolic_ref("HEAD") {
+        Some(target) => {
+            let branch = target.strip_prefix(refs::HEADS_PREFIX).unwrap_or(&target);
+            writeln!(out, "On branch {}", branch).map_err(write_error)?;
+        }
+        None => {
+            let head = status.head.map(|id| id.to_hex()).unwrap_or_default();
+            writeln!(out, "HEAD detached at {}", &head[..head.len().min(7)])
+                .map_err(write_error)?;
+        }
+    }
+    if status.head.is_none() {
+        writeln!(out, "\nNo commits yet").map_err(write_error)?;
+    }
+
+    if !status.staged.is_empty() {
+        writeln!(out, "\nChanges to be committed:").map_err(write_error)?;
+        if status.head.is_none() {
+            writeln!(out, "  (use \"revy rm --cached <file>...\" to unstage)")
+                .map_err(write_error)?;
+        }
+        for (path, change) in &status.staged {
+            writeln!(out, "\t{:<12}{}", change.label(), display(path)).map_err(write_error)?;
+        }
+    }
+    if !status.unstaged.is_empty() {
+        writeln!(out, "\nChanges not staged for commit:").map_err(write_error)?;
+        writeln!(
+            out,
+            "  (use \"revy add/rm <file>...\" to update what will be committed)"
+        )
+        .map_err(write_error)?;
+        for (path, change) in &status.unstaged {
+            writeln!(out, "\t{:<12}{}", change.label(), display(path)).map_err(write_error)?;
+        }
+    }
+    if !status.untracked.is_empty() {
+        writeln!(out, "\nUntracked files:").map_err(write_error)?;
+        writeln!(
+            out,
+            "  (use \"revy add <file>...\" to include in what will be committed)"
+        )
+        .map_err(write_error)?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", display(path)).map_err(write_error)?;
+        }
+    }
+
+    writeln!(out).map_err(write_error)?;
+    let summary = if !status.staged.is_empty() {
+        None
+    } else if !status.unstaged.is_empty() {
+        Some("no changes added to commit (use \"revy add\")")
+    } else if !status.untracked.is_empty() {
+        Some("nothing added to commit but untracked files present (use \"revy add\" to track)")
+    } else if status.head.is_none() {
+        Some("nothing to commit (create/copy files and use \"revy add\" to track)")
+    } else {
+        Some("nothing to commit, working tree clean")
+    };
+    if let Some(summary) = summary {
+        writeln!(out, "{}", summary).map_err(write_error)?;
+    }
+    out.flush().map_err(write_error)
+}
+
+/// Returns the working tree path `path` relative to the working tree directory `dir`.
+fn relative_to(dir: &str, path: &str) -> String {
+    let dir: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+    let parts: Vec<&str> = path.split('/').collect();
+    let common = dir
+        .iter()
+        .zip(&parts)
+        .take_while(|(dir_part, part)| dir_part == part)
+        .count();
+
+    let mut relative = vec![".."; dir.len() - common];
+    relative.extend(&parts[common..]);
+    relative.join("/")
+}
+
+/// What `log` shows and how, as given on the command line.
+pub struct LogCommandOptions {
+    /// Stop after this many commits.
+    pub max_count: Option<usize>,
+    /// Only commits newer than this date, e.g. `2.weeks.ago` or `2024-01-31`.
+    pub since: Option<String>,
+    /// Only commits older than this date.
+    pub until: Option<String>,
+    /// Only commits whose author matches this regular expression.
+    pub author: Option<String>,
+    /// Only commits whose message has a line matching this regular expression.
+    pub grep: Option<String>,
+    /// Show each commit as its abbreviated id and subject.
+    pub oneline: bool,
+    /// `oneline`, `medium`, or a string of placeholders, optionally after `format:` or
+    /// `tformat:`.
+    pub format: Option<String>,
+    /// Draw the history beside the commits.
+    pub graph: bool,
+}
+
+/// Show the commits reachable from `revisions`, or from `HEAD` without any, newest first.
+/// With `pathspecs` only the commits that changed one of them are shown.
+pub fn log<W: Write>(
+    repo: &Repository,
+    revisions: &[String],
+    pathspecs: &[PathBuf],
+    options: &LogCommandOptions,
+    out: &mut W,
+) -> Result<()> {
+    let parse_date = |date: &str| {
+        utils::parse_date(date, utils::now())
+            .ok_or_else(|| RevyError::InvalidArgument(format!("invalid date '{}'", date)))
+    };
+    let parse_regex = |pattern: &str| {
+        Regex::new(pattern).map_err(|err| {
+            RevyError::InvalidArgument(format!("invalid regex '{}': {}", pattern, err))
+        })
+    };
+    let filter = LogOptions {
+        max_count: options.max_count,
+        since: options.since.as_deref().map(parse_date).transpose()?,
+        until: options.until.as_deref().map(parse_date).transpose()?,
+        author: options.author.as_deref().map(parse_regex).transpose()?,
+        grep: options.grep.as_deref().map(parse_regex).transpose()?,
+        paths: pathspecs
+            .iter()
+            .map(|path| worktree::relative_path(repo, path))
+            .collect::<Result<Vec<String>>>()?,
+        topo_order: options.graph,
+    };
+    let format = match (options.oneline, options.format.as_deref()) {
+        (true, _) | (false, Some("oneline")) => LogFormat::Oneline,
+        (false, None | Some("medium")) => LogFormat::Medium,
+        (false, Some(format)) => match format
+            .strip_prefix("format:")
+            .or_else(|| format.strip_prefix("tformat:"))
+        {
+            Some(custom) => LogFormat::Custom(custom),
+            None if format.contains('%') => LogFormat::Custom(format),
+            None => {
+                return Err(RevyError::InvalidArgument(format!(
+                    "invalid --format: '{}'",
+                    format
+                )))
+            }
+        },
+    };
+
+    let mut wanted = Vec::new();
+    if revisions.is_empty() {
+        let head = repo.resolve_ref("HEAD").ok_or_else(|| {
+            let branch = repo
+                .follow_symbolic_refs("HEAD")
+                .unwrap_or_else(|_err| "HEAD".to_string());
+            RevyError::BadRef(format!(
+                "your current branch '{}' does not have any commits yet",
+                refs::short_name(&branch)
+            ))
+        })?;
+        wanted.push(Revision::Include(head));
+    }
+    for revision in revisions {
+        wanted.extend(revision::resolve_range(repo, revision)?);
+    }
+
+    let entries = revy::log::log(repo, &wanted, &filter)?;
+    let mut graph = Graph::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let text = match format {
+            LogFormat::Oneline => format!(
+                "{} {}\n",
+                repo.abbreviate(&entry.id, DEFAULT_ABBREV_LEN),
+                entry.commit.subject()
+            ),
+            LogFormat::Medium => entry.commit.format_medium(&entry.id),
+            LogFormat::Custom(format) => {
+                format!("{}\n", expand_log_format(repo, entry, format))
+            }
+        };
+        // The default format separates commits with a blank line.
+        let separator = matches!(format, LogFormat::Medium) && index > 0;
+
+        if !options.graph {
+            if separator {
+                writeln!(out).map_err(write_error)?;
+            }
+            out.write_all(text.as_bytes()).map_err(write_error)?;
+            continue;
+        }
+        for line in graph.draw(&entry.id, &entry.parents, &text, separator) {
+            writeln!(out, "{}", line).map_err(write_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// How `log` prints each commit.
+enum LogFormat<'a> {
+    /// The abbreviated id and the subject.
+    Oneline,
+    /// Author, date and the whole message, like `git log`.
+    Medium,
+    /// A string of `%` placeholders.
+    Custom(&'a str),
+}
+
+/// Replaces the `%` placeholders of a `log --format` string with the fields of `entry`.
+/// Unknown placeholders are kept as they are.
+fn expand_log_format(repo: &Repository, entry: &LogEntry, format: &str) -> String {
+    let commit = &entry.commit;
+    let mut text = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let two = rest.get(..2).unwrap_or("");
+        let (value, used) = match (rest.get(..1).unwrap_or(""), two) {
+            ("H", _) => (entry.id.to_hex(), 1),
+            ("h", _) => (repo.abbreviate(&entry.id, DEFAULT_ABBREV_LEN), 1),
+            ("T", _) => (commit.tree.to_hex(), 1),
+            ("t", _) => (repo.abbreviate(&commit.tree, DEFAULT_ABBREV_LEN), 1),
+            ("P", _) => (join_ids(&commit.parents, |id| id.to_hex()), 1),
+            ("p", _) => (
+                join_ids(&commit.parents, |id| {
+                    repo.abbreviate(id, DEFAULT_ABBREV_LEN)
+                }),
+                1,
+            ),
+            ("s", _) => (commit.subject(), 1),
+            ("b", _) => (commit.body().to_string(), 1),
+            ("n", _) => ("\n".to_string(), 1),
+            ("%", _) => ("%".to_string(), 1),
+            (_, "an") => (commit.author.name.clone(), 2),
+            (_, "ae") => (commit.author.email.clone(), 2),
+            (_, "ad") => (
+                utils::format_date(commit.author.timestamp, commit.author.tz_offset),
+                2,
+            ),
+            (_, "cn") => (commit.committer.name.clone(), 2),
+            (_, "ce") => (commit.committer.email.clone(), 2),
+            (_, "cd") => (
+                utils::format_date(commit.committer.timestamp, commit.committer.tz_offset),
+                2,
+            ),
+            _ => ("%".to_string(), 0),
+        };
+        text.push_str(&value);
+        rest = &rest[used..];
+    }
+    text.push_str(rest);
+    text
+}
+
+fn join_ids(ids: &[ObjectId], format: impl Fn(&ObjectId) -> String) -> String {
+    ids.iter().map(format).collect::<Vec<String>>().join(" ")
 }
 
-pub fn generate_sha1_hash(file_path: String) -> Result<String, String> {
-    // Objectives:
+/// How `rev-parse` prints what it resolves.
+pub struct RevParseOptions {
+    /// Require exactly one revision that resolves to an object.
+    pub verify: bool,
+    /// With `verify`, fail without a message.
+    pub quiet: bool,
+    /// Abbreviate ids to at least this many digits.
+    pub short: Option<usize>,
+    /// Print the short name of the ref each argument refers to instead of an id.
+    pub abbrev_ref: bool,
+    /// Print the full name of the ref each argument refers to instead of an id.
+    pub symbolic_full_name: bool,
+}
 
-    /*
-        - Check if the file exists
-        - Read the contents of the file
-        - Get the file size
-        - Create a string with the following format: "blob {file_size}\0{file_contents}"
-        - Generate a hash of the file contents
-        - Return the hash
-    */
+/// Resolve each of `args`, which may be ranges, and print the ids they stand for, one per
+/// line, with excluded ones prefixed by `^`.
+///
+/// Returns false when `verify` and `quiet` are set and the argument doesn't resolve, so
+/// the caller can exit with status 1 without a message.
+pub fn rev_parse<W: Write>(
+    repo: &Repository,
+    args: &[String],
+    options: &RevParseOptions,
+    out: &mut W,
+) -> Result<bool> {
+    if options.verify {
+        let resolved = match args {
+            [arg] => repo.resolve_revision(arg).ok(),
+            _ => None,
+        };
+        return match resolved {
+            Some(id) => {
+                write_rev_parse_line(repo, "", &id, options, out)?;
+                Ok(true)
+            }
+            None if options.quiet => Ok(false),
+            None => Err(RevyError::InvalidArgument(
+                "Needed a single revision".to_string(),
+            )),
+        };
+    }
 
-    let is_a_valid_file = utils::check_if_directory_exists(&file_path);
-    if !is_a_valid_file {
-        return Err(format!(
-            "fatal: could not open '{}' for reading: No such file or directory",
-            file_path
-        ));
+    for arg in args {
+        if options.abbrev_ref || options.symbolic_full_name {
+            // Arguments that aren't refs print nothing, as in git.
+            let name = if arg == "@" { "HEAD" } else { arg.as_str() };
+            if let Some(full_name) = repo.expand_ref_name(name) {
+                let full_name = repo.follow_symbolic_refs(&full_name)?;
+                match options.abbrev_ref {
+                    true => writeln!(out, "{}", refs::short_name(&full_name)),
+                    false => writeln!(out, "{}", full_name),
+                }
+                .map_err(write_error)?;
+            }
+            continue;
+        }
+        for revision in revision::resolve_range(repo, arg)? {
+            match revision {
+                Revision::Include(id) => write_rev_parse_line(repo, "", &id, options, out)?,
+                Revision::Exclude(id) => write_rev_parse_line(repo, "^", &id, options, out)?,
+            }
+        }
     }
+    Ok(true)
+}
 
-    let file_contents = match fs::read_to_string(&file_path) {
-        Ok(contents) => contents,
-        Err(err) => return Err(err.to_string()),
+fn write_rev_parse_line<W: Write>(
+    repo: &Repository,
+    prefix: &str,
+    id: &ObjectId,
+    options: &RevParseOptions,
+    out: &mut W,
+) -> Result<()> {
+    let id = match options.short {
+        Some(len) => repo.abbreviate(id, len),
+        None => id.to_hex(),
     };
+    writeln!(out, "{}{}", prefix, id).map_err(write_error)
+}
+
+/// How long `reflog expire` keeps entries unless `--expire` or `gc.reflogExpire` say
+/// otherwise.
+const DEFAULT_REFLOG_EXPIRE: &str = "90.days.ago";
 
-    let file_meta_data = match fs::metadata(&file_path) {
-        Ok(meta_data) => meta_data,
-        Err(err) => return Err(err.to_string()),
+/// Returns the full name of the ref whose reflog `name` means: a bare `@` or nothing is
+/// the current branch, `HEAD` is `HEAD` even before it has a commit, and anything else is
+/// expanded like a revision.
+fn reflog_ref_name(repo: &Repository, name: &str) -> Result<String> {
+    match name {
+        "" | "@" => repo.follow_symbolic_refs("HEAD"),
+        "HEAD" => Ok(name.to_string()),
+        name => repo
+            .expand_ref_name(name)
+            .ok_or_else(|| RevyError::BadRef(format!("not a valid ref: '{}'", name))),
+    }
+}
+
+/// Print the reflog of the ref `name`, newest entry first, as `<id> <name>@{<n>}: <reason>`.
+pub fn reflog_show<W: Write>(repo: &Repository, name: &str, out: &mut W) -> Result<()> {
+    let full_name = reflog_ref_name(repo, name)?;
+    for (index, entry) in repo.reflog(&full_name)?.iter().rev().enumerate() {
+        writeln!(
+            out,
+            "{} {}@{{{}}}: {}",
+            entry.new.short(),
+            name,
+            index,
+            entry.message
+        )
+        .map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// Drop reflog entries older than `expire`, a date like `30.days.ago`, from the reflogs of
+/// `names`, or of every ref with `all`. `never` keeps everything and `all` or `now` drops
+/// everything.
+pub fn reflog_expire(
+    repo: &Repository,
+    names: &[String],
+    all: bool,
+    expire: Option<&str>,
+) -> Result<()> {
+    let expire = expire
+        .or_else(|| repo.config().get("gc.reflogexpire"))
+        .unwrap_or(DEFAULT_REFLOG_EXPIRE);
+    let before = match expire {
+        "never" | "false" => return Ok(()),
+        "all" => i64::MAX,
+        date => utils::parse_date(date, utils::now())
+            .ok_or_else(|| RevyError::InvalidArgument(format!("invalid expiry date '{}'", date)))?,
     };
 
-    let data_to_write = format!("blob {}\0{}", file_meta_data.len(), file_contents);
-    let generated_hash = utils::generate_sha1(&data_to_write);
+    let names = match all {
+        true => reflog::list_reflogs(repo),
+        false => names
+            .iter()
+            .map(|name| reflog_ref_name(repo, name))
+            .collect::<Result<Vec<String>>>()?,
+    };
+    for name in names {
+        reflog::expire_reflog(repo, &name, before)?;
+    }
+    Ok(())
+}
+
+/// Delete the reflog entries named like `main@{2}`. The entries are deleted one at a time,
+/// so deleting `HEAD@{1}` twice deletes what were the second and third newest entries.
+pub fn reflog_delete(repo: &Repository, entries: &[String], rewrite: bool) -> Result<()> {
+    for entry in entries {
+        let (name, index) = reflog::split_reflog_suffix(entry)
+            .and_then(|(name, index)| Some((name, index.parse::<usize>().ok()?)))
+            .ok_or_else(|| {
+                RevyError::InvalidArgument(format!("not a reflog entry: '{}'", entry))
+            })?;
+        let full_name = reflog_ref_name(repo, name)?;
+        reflog::delete_reflog_entry(repo, &full_name, index, rewrite)?;
+    }
+    Ok(())
+}
+
+/// Which config files `config` reads, or the one it writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigScope {
+    /// Every level when reading, the repository's file when writing.
+    Cascade,
+    Level(ConfigLevel),
+    File(PathBuf),
+}
+
+/// How `config get` interprets the value it prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    Path,
+}
+
+impl ConfigType {
+    pub fn from_str(name: &str) -> Option<ConfigType> {
+        match name {
+            "bool" => Some(ConfigType::Bool),
+            "int" => Some(ConfigType::Int),
+            "path" => Some(ConfigType::Path),
+            _ => None,
+        }
+    }
+}
+
+fn read_config(repo: Option<&Repository>, scope: &ConfigScope) -> Result<Config> {
+    let revy_dir = repo.map(|repo| repo.revy_dir());
+    match scope {
+        ConfigScope::Cascade => Config::load(revy_dir),
+        ConfigScope::Level(level) => Config::from_file(&config_path(revy_dir, *level)?, *level),
+        ConfigScope::File(path) => Config::from_file(path, ConfigLevel::Local),
+    }
+}
+
+fn config_path(revy_dir: Option<&Path>, level: ConfigLevel) -> Result<PathBuf> {
+    level.path(revy_dir).ok_or_else(|| match level {
+        ConfigLevel::Local => RevyError::InvalidArgument(
+            "--local can only be used inside a revy repository".to_string(),
+        ),
+        _ => {
+            RevyError::InvalidArgument(format!("unable to find the {} config file", level.as_str()))
+        }
+    })
+}
+
+fn config_file(repo: Option<&Repository>, scope: &ConfigScope) -> Result<PathBuf> {
+    let revy_dir = repo.map(|repo| repo.revy_dir());
+    match scope {
+        ConfigScope::Cascade => config_path(revy_dir, ConfigLevel::Local),
+        ConfigScope::Level(level) => config_path(revy_dir, *level),
+        ConfigScope::File(path) => Ok(path.clone()),
+    }
+}
+
+/// Print the value of the config key `name`, or with `all` every value it has.
+///
+/// Returns false when the key isn't set, which the command line reports as exit code 1.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error for a malformed key name, and a `BadConfig` error if
+/// a file is malformed or a value doesn't have the type asked for.
+pub fn config_get<W: Write>(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    all: bool,
+    kind: Option<ConfigType>,
+    show_origin: bool,
+    out: &mut W,
+) -> Result<bool> {
+    if !name.contains('.') {
+        return Err(RevyError::InvalidArgument(format!(
+            "key does not contain a section: {}",
+            name
+        )));
+    }
+    let config = read_config(repo, scope)?;
+    let entries: Vec<&ConfigEntry> = match config.get_entry(name) {
+        Some(last) if !all => vec![last],
+        Some(last) => config
+            .entries()
+            .iter()
+            .filter(|entry| entry.name() == last.name())
+            .collect(),
+        None => return Ok(false),
+    };
+
+    for entry in entries {
+        let value = match kind {
+            None => entry.value.clone().unwrap_or_default(),
+            Some(ConfigType::Bool) => config::parse_bool(entry.value.as_deref())
+                .ok_or_else(|| {
+                    RevyError::BadConfig(format!(
+                        "bad boolean config value '{}' for '{}'",
+                        entry.value.as_deref().unwrap_or(""),
+                        name
+                    ))
+                })?
+                .to_string(),
+            Some(ConfigType::Int) => {
+                let value = entry.value.as_deref().unwrap_or("");
+                config::parse_int(value)
+                    .ok_or_else(|| {
+                        RevyError::BadConfig(format!(
+                            "bad numeric config value '{}' for '{}'",
+                            value, name
+                        ))
+                    })?
+                    .to_string()
+            }
+            Some(ConfigType::Path) => config::expand_path(entry.value.as_deref().unwrap_or(""))
+                .display()
+                .to_string(),
+        };
+        if show_origin {
+            write!(out, "file:{}\t", entry.origin.display()).map_err(write_error)?;
+        }
+        writeln!(out, "{}", value).map_err(write_error)?;
+    }
+    Ok(true)
+}
+
+/// Print every config entry as `name=value`, in the order the files were read.
+pub fn config_list<W: Write>(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    show_origin: bool,
+    out: &mut W,
+) -> Result<()> {
+    for entry in read_config(repo, scope)?.entries() {
+        if show_origin {
+            write!(out, "file:{}\t", entry.origin.display()).map_err(write_error)?;
+        }
+        match &entry.value {
+            Some(value) => writeln!(out, "{}={}", entry.name(), value),
+            None => writeln!(out, "{}", entry.name()),
+        }
+        .map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// Set the config key `name` to `value` in the file `scope` names, or with `add` add it as
+/// one more value.
+pub fn config_set(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    value: &str,
+    add: bool,
+) -> Result<()> {
+    config::set_value(&config_file(repo, scope)?, name, value, add)
+}
+
+/// Remove the config key `name` from the file `scope` names, or with `all` every value of
+/// it. Returns false when the key isn't set there.
+pub fn config_unset(
+    repo: Option<&Repository>,
+    scope: &ConfigScope,
+    name: &str,
+    all: bool,
+) -> Result<bool> {
+    Ok(config::unset_value(&config_file(repo, scope)?, name, all)? > 0)
+}
+
+/// Check the object database and print what is wrong with it, git style.
+///
+/// Missing, dangling and unreachable objects are printed to stdout and errors to stderr.
+/// Unreachable objects are only listed when `show_unreachable` is set, and replace the
+/// dangling ones then.
+///
+/// # Errors
+///
+/// Returns a `CorruptObject` error when any problem was found, so the exit code tells a
+/// broken repository apart from a healthy one.
+pub fn fsck(repo: &Repository, show_unreachable: bool, show_dangling: bool) -> Result<()> {
+    let report = repo.fsck()?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    for problem in &report.problems {
+        match problem {
+            Problem::Missing { .. } => writeln!(out, "{}", problem).map_err(write_error)?,
+            _ => eprintln!("{}", problem),
+        }
+    }
+    if show_unreachable {
+        for (kind, id) in &report.unreachable {
+            writeln!(out, "unreachable {} {}", kind.as_str(), id).map_err(write_error)?;
+        }
+    } else if show_dangling {
+        for (kind, id) in &report.dangling {
+            writeln!(out, "dangling {} {}", kind.as_str(), id).map_err(write_error)?;
+        }
+    }
+    out.flush().map_err(write_error)?;
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(RevyError::malformed(format!(
+            "fsck found {} problem(s)",
+            report.problems.len()
+        )))
+    }
+}
 
-    Ok(generated_hash)
+/// Wraps a failure to write command output.
+pub(crate) fn write_error(err: std::io::Error) -> RevyError {
+    RevyError::io("Failed to write to stdout", err)
 }
@@ -1,25 +1,545 @@
-struct Commit {
-    pub id: String,
+use flate2::read::ZlibDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Result, RevyError};
+use crate::object::{Metadata, Object, ObjectType};
+use crate::object_id::ObjectId;
+use crate::refs::Expected;
+use crate::repository::Repository;
+use crate::utils;
+
+/// An identity line as it appears in commits and tags:
+/// `Name <email> <epoch seconds> <+hhmm>`.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    /// Offset from UTC in minutes.
+    pub tz_offset: i32,
+}
+
+impl Signature {
+    /// Creates a new `Signature` with the given identity and time.
+    pub fn new(name: String, email: String, timestamp: i64, tz_offset: i32) -> Signature {
+        Signature {
+            name,
+            email,
+            timestamp,
+            tz_offset,
+        }
+    }
+
+    /// Builds a signature for `role` (`"AUTHOR"` or `"COMMITTER"`) from the environment.
+    ///
+    /// Reads `REVY_<role>_NAME`, `REVY_<role>_EMAIL` and `REVY_<role>_DATE`, the date being
+    /// `<epoch seconds> <+hhmm>`. Missing values fall back to `$USER` and the current UTC time.
+    pub fn from_env(role: &str) -> Result<Signature> {
+        Signature::from_identity(role, |_key| None)
+    }
+
+    /// Like `from_env`, but an identity missing from the environment is read from the
+    /// `author` or `committer` section of the config of `repo`, then from `user`, before
+    /// falling back to `$USER`.
+    pub fn for_repository(repo: &Repository, role: &str) -> Result<Signature> {
+        let section = role.to_lowercase();
+        Signature::from_identity(role, |key| {
+            let config = repo.config();
+            config
+                .get(&format!("{}.{}", section, key))
+                .or_else(|| config.get(&format!("user.{}", key)))
+                .map(str::to_string)
+        })
+    }
+
+    /// Builds a signature for `role` from the environment, then `config(key)` for `name` and
+    /// `email`.
+    fn from_identity(role: &str, config: impl Fn(&str) -> Option<String>) -> Result<Signature> {
+        let name = std::env::var(format!("REVY_{}_NAME", role))
+            .ok()
+            .or_else(|| config("name"))
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        let email = std::env::var(format!("REVY_{}_EMAIL", role))
+            .ok()
+            .or_else(|| config("email"))
+            .unwrap_or_else(|| format!("{}@localhost", name));
+
+        let (timestamp, tz_offset) = match std::env::var(format!("REVY_{}_DATE", role)) {
+            Ok(date) => Signature::parse_date(&date).map_err(RevyError::InvalidArgument)?,
+            Err(_err) => {
+                // A clock set before 1970 is treated as the epoch.
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                (now.as_secs() as i64, 0)
+            }
+        };
+
+        Ok(Signature::new(name, email, timestamp, tz_offset))
+    }
+
+    /// Parses `<epoch seconds> <+hhmm>` into a timestamp and an offset in minutes.
+    fn parse_date(date: &str) -> std::result::Result<(i64, i32), String> {
+        let mut parts = date.split_whitespace();
+        let timestamp = parts
+            .next()
+            .and_then(|timestamp| timestamp.parse::<i64>().ok())
+            .ok_or_else(|| format!("Invalid date: {}", date))?;
+        let tz_offset = match parts.next() {
+            Some(offset) => Signature::parse_offset(offset)
+                .ok_or_else(|| format!("Invalid timezone offset: {}", offset))?,
+            None => 0,
+        };
+        Ok((timestamp, tz_offset))
+    }
+
+    /// Parses a `+hhmm` / `-hhmm` timezone offset into minutes.
+    fn parse_offset(offset: &str) -> Option<i32> {
+        if offset.len() != 5 || !offset.is_ascii() {
+            return None;
+        }
+        let sign = match &offset[..1] {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        let hours = offset[1..3].parse::<i32>().ok()?;
+        let minutes = offset[3..5].parse::<i32>().ok()?;
+        Some(sign * (hours * 60 + minutes))
+    }
+
+    /// Parses a signature line body, e.g. `Jane Doe <jane@example.com> 1700000000 +0100`.
+    pub fn parse(line: &str) -> Result<Signature> {
+        let email_start = line
+            .find('<')
+            .ok_or_else(|| RevyError::malformed(format!("malformed signature: {}", line)))?;
+        let email_end = line
+            .rfind('>')
+            .filter(|end| *end > email_start)
+            .ok_or_else(|| RevyError::malformed(format!("malformed signature: {}", line)))?;
+
+        let name = line[..email_start].trim_end().to_string();
+        let email = line[email_start + 1..email_end].to_string();
+        let (timestamp, tz_offset) =
+            Signature::parse_date(line[email_end + 1..].trim()).map_err(RevyError::malformed)?;
+
+        Ok(Signature::new(name, email, timestamp, tz_offset))
+    }
+
+    /// Returns the timezone offset formatted as `+hhmm`.
+    pub fn offset_str(&self) -> String {
+        let sign = if self.tz_offset < 0 { '-' } else { '+' };
+        let minutes = self.tz_offset.abs();
+        format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+    }
+
+    /// Returns the signature as it is written inside an object.
+    pub fn as_str(&self) -> String {
+        format!(
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.timestamp,
+            self.offset_str()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers revy does not interpret (e.g. `gpgsig`), kept so they survive a round trip.
+    pub extra_headers: Vec<(String, String)>,
     pub message: String,
-    pub author: String,
-    pub date: String,
 }
 
 impl Commit {
-    pub fn new(id: String, message: String, author: String, date: String) -> Commit {
+    /// Creates a new `Commit` pointing at `tree` with the given parents.
+    pub fn new(
+        tree: ObjectId,
+        parents: Vec<ObjectId>,
+        author: Signature,
+        committer: Signature,
+        message: String,
+    ) -> Commit {
         Commit {
-            id,
-            message,
+            tree,
+            parents,
             author,
-            date,
+            committer,
+            extra_headers: Vec::new(),
+            message,
+        }
+    }
+
+    /// Parses a `Commit` from a buffered `ZlibDecoder` reader positioned after the object header.
+    pub fn parse_commit(mut decoded_reader: BufReader<ZlibDecoder<File>>) -> Result<Commit> {
+        let mut buffer = Vec::new();
+        if decoded_reader.read_to_end(&mut buffer).is_err() {
+            return Err(RevyError::malformed("failed to decompress commit"));
         }
+        Commit::from_bytes(&buffer)
     }
 
-    pub fn print_commit(&self) {
-        println!("commit {}", self.id);
-        println!("Author: {}", self.author);
-        println!("Date: {}", self.date);
-        println!();
-        println!("    {}", self.message);
+    /// Parses a `Commit` from the raw object body.
+    ///
+    /// The body is a list of `key value` header lines, where a line starting with a space
+    /// continues the previous value, followed by an empty line and the message. Bytes that
+    /// aren't UTF-8, as in a message in another `encoding`, are replaced, so the stored bytes
+    /// are what `cat-file -p` shows.
+    pub fn from_bytes(data: &[u8]) -> Result<Commit> {
+        let content = String::from_utf8_lossy(data);
+        let (headers, message) = match content.split_once("\n\n") {
+            Some((headers, message)) => (headers, message),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers: Vec<(String, String)> = Vec::new();
+
+        for line in headers.lines() {
+            if let Some(continuation) = line.strip_prefix(' ') {
+                match extra_headers.last_mut() {
+                    Some((_key, value)) => {
+                        value.push('\n');
+                        value.push_str(continuation);
+                    }
+                    None => {
+                        return Err(RevyError::malformed(
+                            "commit header continuation without a header",
+                        ))
+                    }
+                }
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').ok_or_else(|| {
+                RevyError::malformed(format!("malformed commit header: {}", line))
+            })?;
+            match key {
+                "tree" => tree = Some(Commit::parse_id(value)?),
+                "parent" => parents.push(Commit::parse_id(value)?),
+                "author" => author = Some(Signature::parse(value)?),
+                "committer" => committer = Some(Signature::parse(value)?),
+                _ => extra_headers.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.ok_or_else(|| RevyError::malformed("commit is missing a tree"))?,
+            parents,
+            author: author.ok_or_else(|| RevyError::malformed("commit is missing an author"))?,
+            committer: committer
+                .ok_or_else(|| RevyError::malformed("commit is missing a committer"))?,
+            extra_headers,
+            message: message.to_string(),
+        })
+    }
+
+    /// Parses an object id header value, reporting a bad one as a corrupt commit.
+    fn parse_id(value: &str) -> Result<ObjectId> {
+        ObjectId::from_hex(value)
+            .map_err(|_err| RevyError::malformed(format!("invalid object id '{}'", value)))
+    }
+
+    /// Converts the commit to the byte vector stored in the object database.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.as_bytes_with_message(self.message.as_bytes())
+    }
+
+    /// Like `as_bytes`, with `message` in place of the commit's own, for a message that
+    /// isn't UTF-8.
+    fn as_bytes_with_message(&self, message: &[u8]) -> Vec<u8> {
+        let mut contents = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            contents.push_str(&format!("parent {}\n", parent));
+        }
+        contents.push_str(&format!("author {}\n", self.author.as_str()));
+        contents.push_str(&format!("committer {}\n", self.committer.as_str()));
+        for (key, value) in &self.extra_headers {
+            contents.push_str(&format!("{} {}\n", key, value.replace('\n', "\n ")));
+        }
+        contents.push('\n');
+        let mut contents = contents.into_bytes();
+        contents.extend_from_slice(message);
+        contents
+    }
+
+    /// Returns the first line of the commit message.
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+
+    /// Returns the first paragraph of the message joined into one line, the way `%s`
+    /// shows it.
+    pub fn subject(&self) -> String {
+        self.message
+            .lines()
+            .skip_while(|line| line.trim().is_empty())
+            .take_while(|line| !line.trim().is_empty())
+            .map(str::trim)
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// Returns the message after the subject paragraph and the blank lines following it.
+    pub fn body(&self) -> &str {
+        let mut rest = self.message.trim_start_matches('\n');
+        while let Some((line, after)) = rest.split_once('\n') {
+            if line.trim().is_empty() {
+                return after.trim_start_matches('\n');
+            }
+            rest = after;
+        }
+        ""
+    }
+
+    /// Returns the commit in the layout `log` shows by default: the id, the parents of a
+    /// merge, the author and date, then the message indented by four spaces.
+    pub fn format_medium(&self, hash: &ObjectId) -> String {
+        let mut text = format!("commit {}\n", hash);
+        if self.parents.len() > 1 {
+            let parents: Vec<String> = self.parents.iter().map(ObjectId::short).collect();
+            text.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        text.push_str(&format!(
+            "Author: {} <{}>\nDate:   {}\n\n",
+            self.author.name,
+            self.author.email,
+            utils::format_date(self.author.timestamp, self.author.tz_offset)
+        ));
+        for line in self.message.trim_end().lines() {
+            text.push_str(&format!("    {}\n", line));
+        }
+        text
+    }
+}
+
+/// What `commit_index` records.
+#[derive(Debug, Clone, Default)]
+pub struct CommitOptions {
+    /// The paragraphs of the message. Without any the message is written in an editor,
+    /// starting from the message of the amended commit.
+    pub messages: Vec<String>,
+    /// Replace `HEAD` instead of adding a commit on top of it, keeping its parents, author
+    /// and extra headers.
+    pub amend: bool,
+    /// Record a commit even if it has the same tree as its parent.
+    pub allow_empty: bool,
+}
+
+/// A commit `commit_index` recorded.
+#[derive(Debug, Clone)]
+pub struct NewCommit {
+    pub id: ObjectId,
+    /// The ref moved to the commit: the current branch, or `HEAD` when it is detached.
+    pub ref_name: String,
+    /// Whether the commit has no parents.
+    pub is_root: bool,
+    /// The first line of the message.
+    pub summary: String,
+}
+
+/// Headers that only hold for the commit they are in, so an amended commit drops them.
+const SIGNATURE_HEADERS: &[&str] = &["gpgsig", "gpgsig-sha256"];
+
+/// Records the index as a new commit on top of `HEAD`, advancing the current branch, or
+/// creating it for the first commit.
+///
+/// With `amend` the message of the amended commit is kept byte for byte when it is edited,
+/// along with its `encoding`; a message given in `options` is UTF-8 and drops the header.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if there is nothing to amend, the commit would record
+/// the same tree as its parent without `allow_empty`, the editor fails or the message is
+/// empty, and a `BadRef` error if the branch moved meanwhile.
+pub(crate) fn commit_index(repo: &Repository, options: &CommitOptions) -> Result<NewCommit> {
+    let head_ref = repo.follow_symbolic_refs("HEAD")?;
+    let head = repo.resolve_ref("HEAD");
+    let amended = match (options.amend, head) {
+        (true, Some(head)) => Some(read_commit_and_message(repo, &head)?),
+        (true, None) => {
+            return Err(RevyError::InvalidArgument(
+                "You have nothing to amend.".to_string(),
+            ))
+        }
+        (false, _) => None,
+    };
+
+    let tree = repo.write_tree()?;
+    let parents = match &amended {
+        Some((commit, _message)) => commit.parents.clone(),
+        None => head.into_iter().collect(),
+    };
+    if !options.amend && !options.allow_empty {
+        let unchanged = match parents.first() {
+            Some(parent) => match repo.read_object(parent)?.metadata {
+                Metadata::Commit(parent) => parent.tree == tree,
+                _ => false,
+            },
+            None => repo.read_index()?.entries().is_empty(),
+        };
+        if unchanged {
+            return Err(RevyError::InvalidArgument(
+                "nothing to commit (use \"revy add\" to stage changes, or --allow-empty)"
+                    .to_string(),
+            ));
+        }
+    }
+
+    let (author, mut extra_headers, template) = match amended {
+        Some((commit, message)) => (commit.author, commit.extra_headers, message),
+        None => (
+            Signature::for_repository(repo, "AUTHOR")?,
+            Vec::new(),
+            Vec::new(),
+        ),
+    };
+    extra_headers.retain(|(key, _value)| !SIGNATURE_HEADERS.contains(&key.as_str()));
+    let message = if options.messages.is_empty() {
+        cleanup_message(&edit_message(repo, &template)?, true)
+    } else {
+        extra_headers.retain(|(key, _value)| key != "encoding");
+        cleanup_message(options.messages.join("\n\n").as_bytes(), false)
+    };
+    if message.is_empty() {
+        return Err(RevyError::InvalidArgument(
+            "Aborting commit due to empty commit message.".to_string(),
+        ));
+    }
+
+    let commit = Commit {
+        tree,
+        parents,
+        author,
+        committer: Signature::for_repository(repo, "COMMITTER")?,
+        extra_headers,
+        message: String::from_utf8_lossy(&message).into_owned(),
+    };
+    let id = Object::save_raw(
+        repo,
+        ObjectType::Commit,
+        &commit.as_bytes_with_message(&message),
+    )?;
+
+    // Only move the branch if nobody else moved it since HEAD was read.
+    let expected = match head {
+        Some(head) => Expected::Value(head),
+        None => Expected::Missing,
+    };
+    let is_root = commit.parents.is_empty();
+    let reason = match (options.amend, is_root) {
+        (true, _) => "commit (amend)",
+        (false, true) => "commit (initial)",
+        (false, false) => "commit",
+    };
+    let summary = commit.summary().to_string();
+    repo.update_ref(
+        &head_ref,
+        &id,
+        expected,
+        &format!("{}: {}", reason, summary),
+    )?;
+    Ok(NewCommit {
+        id,
+        ref_name: head_ref,
+        is_root,
+        summary,
+    })
+}
+
+/// Reads the commit `id` along with its message as stored, which parsing it would decode.
+fn read_commit_and_message(repo: &Repository, id: &ObjectId) -> Result<(Commit, Vec<u8>)> {
+    let mut reader = repo.open_object(id)?;
+    if !matches!(reader.kind, ObjectType::Commit) {
+        return Err(RevyError::InvalidArgument(format!(
+            "HEAD {} is not a commit",
+            id
+        )));
+    }
+    let mut data = Vec::with_capacity(reader.size);
+    reader
+        .read_to_end(&mut data)
+        .map_err(|_err| RevyError::corrupt(id, "failed to decompress commit"))?;
+    let commit = Commit::from_bytes(&data).map_err(|err| err.with_object_id(id))?;
+    let message = match data.windows(2).position(|pair| pair == b"\n\n") {
+        Some(end) => data.split_off(end + 2),
+        None => Vec::new(),
+    };
+    Ok((commit, message))
+}
+
+/// Lets the user write a commit message in their editor, starting from `template`, and
+/// returns what they saved.
+///
+/// The editor is `$REVY_EDITOR`, the `core.editor` config, `$VISUAL` or `$EDITOR`, falling
+/// back to `vi`. It is run through the shell, so it may include arguments.
+fn edit_message(repo: &Repository, template: &[u8]) -> Result<Vec<u8>> {
+    let path = repo.revy_dir().join("COMMIT_EDITMSG");
+    let mut contents = template.to_vec();
+    contents.extend_from_slice(
+        b"\n# Please enter the commit message for your changes. Lines starting\n\
+          # with '#' will be ignored, and an empty message aborts the commit.\n",
+    );
+    std::fs::write(&path, contents)
+        .map_err(|err| RevyError::io(format!("Failed to write {}", path.display()), err))?;
+
+    let editor = std::env::var("REVY_EDITOR")
+        .ok()
+        .or_else(|| repo.config().get("core.editor").map(str::to_string))
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_string());
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .map_err(|err| RevyError::io(format!("unable to start editor '{}'", editor), err))?;
+    if !status.success() {
+        return Err(RevyError::InvalidArgument(format!(
+            "There was a problem with the editor '{}'.",
+            editor
+        )));
+    }
+
+    std::fs::read(&path)
+        .map_err(|err| RevyError::io(format!("Failed to read {}", path.display()), err))
+}
+
+/// Tidies a commit message like git does: trailing whitespace and leading and trailing
+/// blank lines go, runs of blank lines become one, and with `strip_comments` lines starting
+/// with `#` are dropped. A non empty result ends with a newline. The message is handled as
+/// bytes, so one in another encoding comes through unchanged.
+fn cleanup_message(message: &[u8], strip_comments: bool) -> Vec<u8> {
+    let mut cleaned = Vec::new();
+    let mut pending_blank = false;
+    for line in message.split(|&byte| byte == b'\n') {
+        if strip_comments && line.starts_with(b"#") {
+            continue;
+        }
+        let line = line.trim_ascii_end();
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push(b'\n');
+            pending_blank = false;
+        }
+        cleaned.extend_from_slice(line);
+        cleaned.push(b'\n');
     }
+    cleaned
 }
@@ -0,0 +1,716 @@
+//! git-style configuration files.
+//!
+//! A file is made of `[section]` and `[section "subsection"]` headers followed by
+//! `key = value` lines. Section and key names are case insensitive, subsections are not.
+//! Values may be quoted, use `\"`, `\\`, `\n`, `\t` and `\b` escapes and continue on the
+//! next line after a trailing backslash; `#` and `;` start comments. A key without `=`
+//! is a boolean set to true. A key may be given several times to hold several values.
+//! `include.path` reads another file at the point it appears, relative to the including one.
+//!
+//! Lookups cascade over three files, later ones overriding earlier ones: the system file
+//! (`/etc/revyconfig`, or `$REVY_CONFIG_SYSTEM`; skipped when `$REVY_CONFIG_NOSYSTEM` is
+//! set), the user's `~/.revyconfig` (or `$REVY_CONFIG_GLOBAL`) and the repository's
+//! `.revy/config`.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RevyError};
+use crate::lockfile::LockFile;
+
+/// Where the system wide config lives unless `$REVY_CONFIG_SYSTEM` says otherwise.
+pub const SYSTEM_CONFIG: &str = "/etc/revyconfig";
+
+/// Bound on nested includes, so a file including itself can't loop forever.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Which of the cascading config files a value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigLevel {
+    System,
+    Global,
+    Local,
+}
+
+impl ConfigLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigLevel::System => "system",
+            ConfigLevel::Global => "global",
+            ConfigLevel::Local => "local",
+        }
+    }
+
+    /// Returns the file the level is stored in. The local file needs the repository's
+    /// `.revy` directory, and the global one a home directory.
+    pub fn path(&self, revy_dir: Option<&Path>) -> Option<PathBuf> {
+        match self {
+            ConfigLevel::System => Some(
+                std::env::var_os("REVY_CONFIG_SYSTEM")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(SYSTEM_CONFIG)),
+            ),
+            ConfigLevel::Global => std::env::var_os("REVY_CONFIG_GLOBAL")
+                .map(PathBuf::from)
+                .or_else(|| home_dir().map(|home| home.join(".revyconfig"))),
+            ConfigLevel::Local => revy_dir.map(|revy_dir| revy_dir.join("config")),
+        }
+    }
+}
+
+/// A single `key = value` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    /// The section name, lowercased.
+    pub section: String,
+    pub subsection: Option<String>,
+    /// The key name, lowercased.
+    pub key: String,
+    /// `None` for a key without `=`, which is a boolean set to true.
+    pub value: Option<String>,
+    /// The file the line is in, which may have been included from a file of `level`.
+    pub origin: PathBuf,
+    pub level: ConfigLevel,
+}
+
+impl ConfigEntry {
+    /// Returns the full name of the key, e.g. `remote.origin.url`.
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+
+    fn matches(&self, name: &Key) -> bool {
+        self.section == name.section && self.subsection == name.subsection && self.key == name.key
+    }
+
+    fn in_section(&self, name: &Key) -> bool {
+        self.section == name.section && self.subsection == name.subsection
+    }
+}
+
+/// A key name split into its parts: `section.key` or `section.subsection.key`.
+#[derive(Debug)]
+struct Key {
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    /// The key as it was spelled, which is how new lines are written.
+    spelling: String,
+}
+
+impl Key {
+    fn parse(name: &str) -> Result<Key> {
+        let invalid = || RevyError::InvalidArgument(format!("invalid key: {}", name));
+        let (section, rest) = name.split_once('.').ok_or_else(|| {
+            RevyError::InvalidArgument(format!("key does not contain a section: {}", name))
+        })?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection.to_string()), key),
+            None => (None, rest),
+        };
+
+        let valid_section = !section.is_empty()
+            && section
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        let valid_key = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_section || !valid_key || subsection.as_deref().is_some_and(|s| s.contains('\n')) {
+            return Err(invalid());
+        }
+
+        Ok(Key {
+            section: section.to_lowercase(),
+            subsection,
+            key: key.to_lowercase(),
+            spelling: key.to_string(),
+        })
+    }
+}
+
+/// The entries of a single file, with the lines each spans, for editing it in place.
+struct ParsedFile {
+    entries: Vec<(ConfigEntry, Range<usize>)>,
+    /// Section headers as `(section, subsection, line)`.
+    headers: Vec<(String, Option<String>, usize)>,
+}
+
+/// Config values from one or more files, in the order they were read, so the last value of
+/// a key is the one that applies.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Reads the system, global and, given the `.revy` directory, the repository config.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadConfig` error if a file is malformed. Missing files are skipped.
+    pub fn load(revy_dir: Option<&Path>) -> Result<Config> {
+        let mut config = Config::default();
+        for level in [ConfigLevel::System, ConfigLevel::Global, ConfigLevel::Local] {
+            if level == ConfigLevel::System && std::env::var_os("REVY_CONFIG_NOSYSTEM").is_some() {
+                continue;
+            }
+            if let Some(path) = level.path(revy_dir) {
+                config
+                    .entries
+                    .extend(Config::from_file(&path, level)?.entries);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads a single config file, and the files it includes. A missing file is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadConfig` error if the file is malformed, or an I/O error if it exists
+    /// but can't be read.
+    pub fn from_file(path: &Path, level: ConfigLevel) -> Result<Config> {
+        let mut config = Config::default();
+        config.read_file(path, level, 0)?;
+        Ok(config)
+    }
+
+    fn read_file(&mut self, path: &Path, level: ConfigLevel, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(RevyError::BadConfig(format!(
+                "exceeded maximum include depth ({}) while including {}",
+                MAX_INCLUDE_DEPTH,
+                path.display()
+            )));
+        }
+        let text = match read_text(path)? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        for (entry, _lines) in parse(&text, path, level)?.entries {
+            let include =
+                (entry.section == "include" && entry.subsection.is_none() && entry.key == "path")
+                    .then(|| entry.value.clone())
+                    .flatten();
+            self.entries.push(entry);
+
+            if let Some(include) = include {
+                let included = expand_path(&include);
+                let included = match path.parent() {
+                    Some(dir) if included.is_relative() => dir.join(included),
+                    _ => included,
+                };
+                self.read_file(&included, level, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every entry, in the order they were read.
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry that sets `name`: the last one.
+    pub fn get_entry(&self, name: &str) -> Option<&ConfigEntry> {
+        let key = Key::parse(name).ok()?;
+        self.entries.iter().rev().find(|entry| entry.matches(&key))
+    }
+
+    /// Returns the value of `name`. A key without `=` has an empty value.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_entry(name)
+            .map(|entry| entry.value.as_deref().unwrap_or(""))
+    }
+
+    /// Returns every value of the multi-valued key `name`, in order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        match Key::parse(name) {
+            Ok(key) => self
+                .entries
+                .iter()
+                .filter(|entry| entry.matches(&key))
+                .map(|entry| entry.value.as_deref().unwrap_or(""))
+                .collect(),
+            Err(_err) => Vec::new(),
+        }
+    }
+
+    /// Returns the value of `name` as a boolean.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadConfig` error if the value is not a boolean.
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>> {
+        self.get_entry(name)
+            .map(|entry| {
+                parse_bool(entry.value.as_deref()).ok_or_else(|| {
+                    RevyError::BadConfig(format!(
+                        "bad boolean config value '{}' for '{}'",
+                        entry.value.as_deref().unwrap_or(""),
+                        name
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the value of `name` as an integer, which may have a `k`, `m` or `g` suffix.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadConfig` error if the value is not an integer.
+    pub fn get_int(&self, name: &str) -> Result<Option<i64>> {
+        self.get(name)
+            .map(|value| {
+                parse_int(value).ok_or_else(|| {
+                    RevyError::BadConfig(format!(
+                        "bad numeric config value '{}' for '{}'",
+                        value, name
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the value of `name` as a path, with a leading `~/` expanded to the home
+    /// directory.
+    pub fn get_path(&self, name: &str) -> Option<PathBuf> {
+        self.get(name).map(expand_path)
+    }
+}
+
+/// Parses a boolean the way git does: `true`, `yes`, `on`, `1` and a key without `=` are
+/// true, `false`, `no`, `off`, `0` and the empty string false.
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let value = match value {
+        Some(value) => value.to_lowercase(),
+        None => return Some(true),
+    };
+    match value.as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses an integer with an optional `k`, `m` or `g` suffix, as powers of 1024.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_path(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Reads the file at `path`, or `None` if it doesn't exist.
+fn read_text(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(RevyError::io(
+            format!("Failed to read {}", path.display()),
+            err,
+        )),
+    }
+}
+
+/// Parses the text of a config file read from `origin`, without following includes.
+fn parse(text: &str, origin: &Path, level: ConfigLevel) -> Result<ParsedFile> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut parsed = ParsedFile {
+        entries: Vec::new(),
+        headers: Vec::new(),
+    };
+    let mut section: Option<(String, Option<String>)> = None;
+
+    let mut number = 0;
+    while number < lines.len() {
+        let line = lines[number].trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            number += 1;
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, subsection, rest) =
+                parse_header(header).ok_or_else(|| bad_line(number, origin))?;
+            let rest = rest.trim_start();
+            if !(rest.is_empty() || rest.starts_with('#') || rest.starts_with(';')) {
+                return Err(bad_line(number, origin));
+            }
+            parsed
+                .headers
+                .push((name.clone(), subsection.clone(), number));
+            section = Some((name, subsection));
+            number += 1;
+            continue;
+        }
+
+        let (name, subsection) = section.clone().ok_or_else(|| bad_line(number, origin))?;
+        let key_len = line
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(line.len());
+        let key = &line[..key_len];
+        if !key.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) {
+            return Err(bad_line(number, origin));
+        }
+
+        let start = number;
+        let rest = line[key_len..].trim_start();
+        let value = if let Some(value) = rest.strip_prefix('=') {
+            Some(parse_value(&lines, &mut number, value).ok_or_else(|| bad_line(number, origin))?)
+        } else if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+            None
+        } else {
+            return Err(bad_line(number, origin));
+        };
+        number += 1;
+
+        parsed.entries.push((
+            ConfigEntry {
+                section: name,
+                subsection,
+                key: key.to_lowercase(),
+                value,
+                origin: origin.to_path_buf(),
+                level,
+            },
+            start..number,
+        ));
+    }
+    Ok(parsed)
+}
+
+fn bad_line(number: usize, origin: &Path) -> RevyError {
+    RevyError::BadConfig(format!(
+        "bad config line {} in file {}",
+        number + 1,
+        origin.display()
+    ))
+}
+
+/// Parses the rest of a section header after `[`, returning the lowercased section name,
+/// the subsection and what follows the `]`.
+fn parse_header(header: &str) -> Option<(String, Option<String>, &str)> {
+    let name_len = header
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+        .unwrap_or(header.len());
+    let name = &header[..name_len];
+    let rest = &header[name_len..];
+
+    if let Some(rest) = rest.strip_prefix(']') {
+        if name.is_empty() {
+            return None;
+        }
+        // The deprecated `[section.subsection]` form, whose subsection is lowercased.
+        return Some(match name.split_once('.') {
+            Some((section, subsection)) => (
+                section.to_lowercase(),
+                Some(subsection.to_lowercase()),
+                rest,
+            ),
+            None => (name.to_lowercase(), None, rest),
+        });
+    }
+
+    let quoted = rest.trim_start().strip_prefix('"')?;
+    if name.is_empty() || name.contains('.') {
+        return None;
+    }
+    let mut subsection = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((position, c)) = chars.next() {
+        match c {
+            '\\' => subsection.push(chars.next()?.1),
+            '"' => {
+                let rest = quoted[position + 1..].strip_prefix(']')?;
+                return Some((name.to_lowercase(), Some(subsection), rest));
+            }
+            _ => subsection.push(c),
+        }
+    }
+    None
+}
+
+/// Parses a value starting with `value` on line `number`, moving `number` to the last line
+/// of the value when it continues over several lines.
+fn parse_value(lines: &[&str], number: &mut usize, value: &str) -> Option<String> {
+    let mut parsed = String::new();
+    let mut in_quotes = false;
+    // Unquoted whitespace at the end of the value is dropped, so it is only added once
+    // something follows it.
+    let mut pending_space = String::new();
+    let mut chars = value.trim_start().chars().peekable();
+
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None if in_quotes => return None,
+            None => break,
+        };
+        match c {
+            '"' => {
+                parsed.push_str(&std::mem::take(&mut pending_space));
+                in_quotes = !in_quotes;
+            }
+            '#' | ';' if !in_quotes => break,
+            ' ' | '\t' if !in_quotes => {
+                if !parsed.is_empty() {
+                    pending_space.push(c);
+                }
+            }
+            '\\' => {
+                let escaped = match chars.next() {
+                    // A backslash at the end of the line continues the value.
+                    None => {
+                        *number += 1;
+                        chars = lines.get(*number)?.chars().peekable();
+                        continue;
+                    }
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some(c @ ('"' | '\\')) => c,
+                    Some(_other) => return None,
+                };
+                parsed.push_str(&std::mem::take(&mut pending_space));
+                parsed.push(escaped);
+            }
+            _ => {
+                parsed.push_str(&std::mem::take(&mut pending_space));
+                parsed.push(c);
+            }
+        }
+    }
+    Some(parsed)
+}
+
+/// Formats `value` so it reads back unchanged, quoting it when needed.
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with([' ', '\t'])
+        || value.ends_with([' ', '\t'])
+        || value.contains(['#', ';']);
+    let mut quoted = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{8}' => quoted.push_str("\\b"),
+            _ => quoted.push(c),
+        }
+    }
+    if needs_quotes {
+        format!("\"{}\"", quoted)
+    } else {
+        quoted
+    }
+}
+
+/// Sets `name` to `value` in the config file at `path`, creating the file if needed.
+///
+/// An existing value is replaced in place; with `add` a new value is added after the others
+/// instead, for multi-valued keys. Comments and formatting of the rest of the file are kept.
+///
+/// # Errors
+///
+/// Returns an error if `name` is invalid, the file is malformed or locked, or the key has
+/// several values and `add` isn't set.
+pub fn set_value(path: &Path, name: &str, value: &str, add: bool) -> Result<()> {
+    let key = Key::parse(name)?;
+    let mut lock = LockFile::acquire(path)?;
+    let text = read_text(path)?.unwrap_or_default();
+    let parsed = parse(&text, path, ConfigLevel::Local)?;
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+
+    let new_line = format!("\t{} = {}", key.spelling, quote_value(value));
+    let existing: Vec<&Range<usize>> = parsed
+        .entries
+        .iter()
+        .filter(|(entry, _lines)| entry.matches(&key))
+        .map(|(_entry, lines)| lines)
+        .collect();
+
+    match existing.as_slice() {
+        [range] if !add => {
+            lines.splice((*range).clone(), [new_line]);
+        }
+        [_first, _second, ..] if !add => {
+            return Err(RevyError::InvalidArgument(format!(
+                "cannot overwrite multiple values of {} with a single value\n\
+                 hint: use --add to add another value, or unset --all first",
+                name
+            )));
+        }
+        _ => {
+            let last_in_section = parsed
+                .entries
+                .iter()
+                .filter(|(entry, _lines)| entry.in_section(&key))
+                .map(|(_entry, lines)| lines.end)
+                .max();
+            let header = parsed
+                .headers
+                .iter()
+                .rfind(|(section, subsection, _line)| {
+                    *section == key.section && *subsection == key.subsection
+                })
+                .map(|(_section, _subsection, line)| line + 1);
+            match last_in_section.or(header) {
+                Some(position) => lines.insert(position, new_line),
+                None => {
+                    lines.push(format_header(&key.section, key.subsection.as_deref()));
+                    lines.push(new_line);
+                }
+            }
+        }
+    }
+
+    lock.write_all(format!("{}\n", lines.join("\n")).as_bytes())?;
+    lock.commit()
+}
+
+/// Renames the section `old` to `new` in the config file at `path`, e.g. `branch.topic` to
+/// `branch.feature`, or removes it with all of its keys when `new` is `None`. Returns
+/// whether the file had the section.
+///
+/// # Errors
+///
+/// Returns an error if a name has no section, or the file is malformed or locked.
+pub fn rename_section(path: &Path, old: &str, new: Option<&str>) -> Result<bool> {
+    let split = |name: &str| -> Result<(String, Option<String>)> {
+        match name.split_once('.') {
+            Some((section, subsection)) => {
+                Ok((section.to_lowercase(), Some(subsection.to_string())))
+            }
+            None if !name.is_empty() => Ok((name.to_lowercase(), None)),
+            None => Err(RevyError::InvalidArgument(format!(
+                "invalid section name: {}",
+                name
+            ))),
+        }
+    };
+    let (section, subsection) = split(old)?;
+    let new = new.map(split).transpose()?;
+
+    let mut lock = LockFile::acquire(path)?;
+    let text = match read_text(path)? {
+        Some(text) => text,
+        None => return Ok(false),
+    };
+    let parsed = parse(&text, path, ConfigLevel::Local)?;
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+
+    // A section runs from its header to the next one; walking backwards keeps the line
+    // numbers of the headers still to visit valid.
+    let mut found = false;
+    for (index, (name, sub, start)) in parsed.headers.iter().enumerate().rev() {
+        if *name != section || *sub != subsection {
+            continue;
+        }
+        found = true;
+        match &new {
+            Some((new_section, new_subsection)) => {
+                lines[*start] = format_header(new_section, new_subsection.as_deref());
+            }
+            None => {
+                let end = parsed
+                    .headers
+                    .get(index + 1)
+                    .map_or(lines.len(), |(_section, _subsection, line)| *line);
+                lines.drain(*start..end);
+            }
+        }
+    }
+    if !found {
+        return Ok(false);
+    }
+
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    lock.write_all(contents.as_bytes())?;
+    lock.commit()?;
+    Ok(true)
+}
+
+/// Formats a section header, quoting the subsection.
+fn format_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]", section),
+    }
+}
+
+/// Removes `name` from the config file at `path`, every value of it with `all`, and returns
+/// how many values were removed.
+///
+/// # Errors
+///
+/// Returns an error if `name` is invalid, the file is malformed or locked, or the key has
+/// several values and `all` isn't set.
+pub fn unset_value(path: &Path, name: &str, all: bool) -> Result<usize> {
+    let key = Key::parse(name)?;
+    let mut lock = LockFile::acquire(path)?;
+    let text = match read_text(path)? {
+        Some(text) => text,
+        None => return Ok(0),
+    };
+    let parsed = parse(&text, path, ConfigLevel::Local)?;
+
+    let existing: Vec<Range<usize>> = parsed
+        .entries
+        .into_iter()
+        .filter(|(entry, _lines)| entry.matches(&key))
+        .map(|(_entry, lines)| lines)
+        .collect();
+    if existing.len() > 1 && !all {
+        return Err(RevyError::InvalidArgument(format!(
+            "{} has multiple values\nhint: use --all to remove all of them",
+            name
+        )));
+    }
+    if existing.is_empty() {
+        return Ok(0);
+    }
+
+    let mut lines: Vec<&str> = text.lines().collect();
+    for range in existing.iter().rev() {
+        lines.drain(range.clone());
+    }
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    lock.write_all(contents.as_bytes())?;
+    lock.commit()?;
+    Ok(existing.len())
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+/// Errors that can occur while working with a revy repository.
+///
+/// Each variant maps to its own process exit code (see `exit_code`), so scripts can tell
+/// categories of failure apart:
+///
+/// | Code | Meaning                                       |
+/// |------|-----------------------------------------------|
+/// | 1    | A query answered "no" (e.g. `cat-file -e`)    |
+/// | 2    | Invalid arguments or an ambiguous object name |
+/// | 3    | A requested object does not exist             |
+/// | 4    | A ref is missing, malformed or can't be moved |
+/// | 5    | An object, the index or a config is corrupt   |
+/// | 6    | Reading or writing a file failed              |
+#[derive(Debug)]
+pub enum RevyError {
+    /// A filesystem operation failed. `context` says what revy was doing.
+    Io {
+        context: String,
+        source: std::io::Error,
+    },
+    /// An object exists but can't be decompressed or parsed.
+    CorruptObject { id: String, reason: String },
+    /// An object that was asked for is not in the object database.
+    MissingObject(String),
+    /// The index file can't be parsed.
+    CorruptIndex(String),
+    /// A config file can't be parsed, or holds a value of the wrong type.
+    BadConfig(String),
+    /// An abbreviated object id matches more than one object.
+    AmbiguousObject {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+    /// A ref can't be read, resolved or updated.
+    BadRef(String),
+    /// The user supplied an argument revy can't act on.
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, RevyError>;
+
+impl RevyError {
+    /// Creates an `Io` error describing what was being done when `source` happened.
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> RevyError {
+        RevyError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Creates a `CorruptObject` error for the object `id`.
+    pub fn corrupt(id: impl fmt::Display, reason: impl Into<String>) -> RevyError {
+        RevyError::CorruptObject {
+            id: id.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Creates a `CorruptObject` error for content whose object id isn't known yet, e.g. while
+    /// parsing. `with_object_id` fills it in once the caller knows which object it was.
+    pub fn malformed(reason: impl Into<String>) -> RevyError {
+        RevyError::corrupt("", reason)
+    }
+
+    /// Attaches the object id `id` to a `CorruptObject` error that doesn't have one yet.
+    pub fn with_object_id(self, id: impl fmt::Display) -> RevyError {
+        match self {
+            RevyError::CorruptObject {
+                id: existing,
+                reason,
+            } if existing.is_empty() => RevyError::corrupt(id, reason),
+            other => other,
+        }
+    }
+
+    /// Returns the exit code the command line reports this error with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RevyError::InvalidArgument(_) | RevyError::AmbiguousObject { .. } => 2,
+            RevyError::MissingObject(_) => 3,
+            RevyError::BadRef(_) => 4,
+            RevyError::CorruptObject { .. }
+            | RevyError::CorruptIndex(_)
+            | RevyError::BadConfig(_) => 5,
+            RevyError::Io { .. } => 6,
+        }
+    }
+}
+
+impl fmt::Display for RevyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevyError::Io { context, source } => write!(f, "{}: {}", context, source),
+            RevyError::CorruptObject { id, reason } if id.is_empty() => {
+                write!(f, "corrupt object: {}", reason)
+            }
+            RevyError::CorruptObject { id, reason } => {
+                write!(f, "object {} is corrupt: {}", id, reason)
+            }
+            RevyError::CorruptIndex(reason) => write!(f, "index file corrupt: {}", reason),
+            RevyError::BadConfig(message) => write!(f, "{}", message),
+            RevyError::MissingObject(name) => write!(f, "object {} not found", name),
+            RevyError::AmbiguousObject { prefix, candidates } => {
+                write!(f, "short object ID {} is ambiguous", prefix)?;
+                write!(f, "\nhint: The candidates are:")?;
+                for candidate in candidates {
+                    write!(f, "\nhint:   {}", candidate)?;
+                }
+                Ok(())
+            }
+            RevyError::BadRef(message) => write!(f, "{}", message),
+            RevyError::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RevyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RevyError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Integrity and connectivity checks for the object database, as run by `revy fsck`.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::io::Read;
+
+use crate::commit::Commit;
+use crate::error::{Result, RevyError};
+use crate::object::ObjectType;
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::tag::Tag;
+use crate::utils;
+
+/// Size of the chunks object content is read in while it is hashed.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Something wrong with the repository found by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The object can't be decompressed or parsed.
+    Corrupt { id: ObjectId, reason: String },
+    /// The content of the object hashes to a different id than its file name.
+    HashMismatch { id: ObjectId, actual: ObjectId },
+    /// An object refers to `id`, which is not in the object database.
+    Missing {
+        kind: ObjectType,
+        id: ObjectId,
+        referenced_by: ObjectId,
+    },
+    /// A tree has an entry git would reject, e.g. an unknown mode or a name with a `/`.
+    /// `code` is the name git's fsck uses for the same check.
+    BadTree {
+        id: ObjectId,
+        code: &'static str,
+        message: &'static str,
+    },
+    /// A ref holds something other than the id of an existing object.
+    BadRef { name: String, reason: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Corrupt { id, reason } => {
+                write!(f, "error: object {} is corrupt: {}", id, reason)
+            }
+            Problem::HashMismatch { id, actual } => {
+                write!(
+                    f,
+                    "error: hash mismatch for {} (content hashes to {})",
+                    id, actual
+                )
+            }
+            Problem::Missing { kind, id, .. } => write!(f, "missing {} {}", kind.as_str(), id),
+            Problem::BadTree { id, code, message } => {
+                write!(f, "error in tree {}: {}: {}", id, code, message)
+            }
+            Problem::BadRef { name, reason } => write!(f, "error: {}: {}", name, reason),
+        }
+    }
+}
+
+/// What `check` found.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Everything wrong with the repository, in the order it was found.
+    pub problems: Vec<Problem>,
+    /// Objects nothing points at, neither a ref nor another object.
+    pub dangling: Vec<(ObjectType, ObjectId)>,
+    /// Objects no ref leads to, including the dangling ones.
+    pub unreachable: Vec<(ObjectType, ObjectId)>,
+}
+
+impl FsckReport {
+    /// Check if no problems were found. Dangling and unreachable objects are not problems.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// An object that could be read, with the objects it refers to.
+struct Checked {
+    kind: ObjectType,
+    links: Vec<(ObjectType, ObjectId)>,
+}
+
+/// Checks every loose object in `repo` and how they connect to each other and to the refs.
+///
+/// Each object must decompress, have a valid header and content of the size it declares,
+/// hash to its file name and parse as its type. Objects referred to by commits, tags and
+/// trees must exist, and refs must point at existing objects.
+///
+/// # Errors
+///
+/// Returns an error only if the repository can't be read at all; anything wrong with its
+/// content ends up in the report.
+pub fn check(repo: &Repository) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    let present: BTreeSet<ObjectId> = repo.list_objects()?.into_iter().collect();
+    let mut objects = BTreeMap::new();
+    for id in &present {
+        if let Some(checked) = check_object(repo, id, &mut report.problems)? {
+            objects.insert(*id, checked);
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    let mut reported_missing = HashSet::new();
+    for (id, checked) in &objects {
+        for (kind, target) in &checked.links {
+            referenced.insert(*target);
+            if !present.contains(target) && reported_missing.insert(*target) {
+                report.problems.push(Problem::Missing {
+                    kind: *kind,
+                    id: *target,
+                    referenced_by: *id,
+                });
+            }
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending = ref_roots(repo, &present, &mut report.problems);
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(checked) = objects.get(&id) {
+            pending.extend(checked.links.iter().map(|(_kind, target)| *target));
+        }
+    }
+
+    for (id, checked) in &objects {
+        if reachable.contains(id) {
+            continue;
+        }
+        report.unreachable.push((checked.kind, *id));
+        if !referenced.contains(id) {
+            report.dangling.push((checked.kind, *id));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns the objects `HEAD` and the refs under `refs/` point at, reporting refs that
+/// don't hold the id of an existing object.
+fn ref_roots(
+    repo: &Repository,
+    present: &BTreeSet<ObjectId>,
+    problems: &mut Vec<Problem>,
+) -> Vec<ObjectId> {
+    let mut names = vec!["HEAD".to_string()];
+    names.extend(repo.list_refs("refs/"));
+
+    let mut roots = Vec::new();
+    for name in names {
+        let contents = match repo.read_ref(&name) {
+            Some(contents) => contents,
+            None => continue,
+        };
+        // Symbolic refs point at another ref, which is checked on its own. A HEAD whose
+        // branch has no commits yet is fine.
+        if contents.starts_with("ref: ") {
+            continue;
+        }
+        match ObjectId::from_hex(&contents) {
+            Ok(id) if present.contains(&id) => roots.push(id),
+            Ok(id) => problems.push(Problem::BadRef {
+                name,
+                reason: format!("invalid pointer {}", id),
+            }),
+            Err(_err) => problems.push(Problem::BadRef {
+                name,
+                reason: format!("invalid ref content '{}'", contents),
+            }),
+        }
+    }
+    roots
+}
+
+/// Reads and verifies the object `id`, returning what it refers to, or `None` if it is too
+/// broken to tell.
+fn check_object(
+    repo: &Repository,
+    id: &ObjectId,
+    problems: &mut Vec<Problem>,
+) -> Result<Option<Checked>> {
+    let mut stream = match repo.open_object(id) {
+        Ok(stream) => stream,
+        Err(RevyError::CorruptObject { reason, .. }) => {
+            return report_corrupt(problems, id, reason)
+        }
+        Err(err) => return Err(err),
+    };
+    let kind = stream.kind;
+
+    let mut hasher = repo.algorithm().hasher();
+    hasher.update(format!("{} {}\0", kind.as_str(), stream.size).as_bytes());
+
+    // Blobs can be large and nothing in them needs parsing, so only hash them.
+    let mut content = Vec::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut read_total = 0;
+    loop {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return report_corrupt(problems, id, format!("failed to decompress: {}", err))
+            }
+        };
+        hasher.update(&buffer[..read]);
+        if kind != ObjectType::Blob {
+            content.extend_from_slice(&buffer[..read]);
+        }
+        read_total += read;
+    }
+    if read_total != stream.size {
+        return report_corrupt(
+            problems,
+            id,
+            format!(
+                "header says {} bytes but the content has {}",
+                stream.size, read_total
+            ),
+        );
+    }
+
+    let actual = hasher.finalize();
+    if actual != *id {
+        problems.push(Problem::HashMismatch { id: *id, actual });
+    }
+
+    let links = match kind {
+        ObjectType::Blob => Vec::new(),
+        ObjectType::Tree => match check_tree(repo, id, &content, problems) {
+            Some(links) => links,
+            None => return Ok(None),
+        },
+        ObjectType::Commit => match Commit::from_bytes(&content) {
+            Ok(commit) => {
+                let mut links = vec![(ObjectType::Tree, commit.tree)];
+                links.extend(
+                    commit
+                        .parents
+                        .iter()
+                        .map(|parent| (ObjectType::Commit, *parent)),
+                );
+                links
+            }
+            Err(err) => return report_corrupt(problems, id, reason_of(err)),
+        },
+        ObjectType::Tag => match Tag::from_bytes(&content) {
+            Ok(tag) => vec![(tag.kind, tag.object)],
+            Err(err) => return report_corrupt(problems, id, reason_of(err)),
+        },
+    };
+
+    Ok(Some(Checked { kind, links }))
+}
+
+/// Checks the entries of the tree `id` and returns the objects they point at. Gitlinks
+/// point into another repository, so they are not followed.
+///
+/// Entries are parsed from the raw content rather than with `Tree::parse_tree`, which
+/// rejects the invalid modes this is meant to report.
+fn check_tree(
+    repo: &Repository,
+    id: &ObjectId,
+    content: &[u8],
+    problems: &mut Vec<Problem>,
+) -> Option<Vec<(ObjectType, ObjectId)>> {
+    let raw_len = repo.algorithm().raw_len();
+    let mut links = Vec::new();
+    let mut found: Vec<(&'static str, &'static str)> = Vec::new();
+    let mut names = HashSet::new();
+    let mut previous_key: Option<Vec<u8>> = None;
+
+    let mut rest = content;
+    while !rest.is_empty() {
+        let entry = rest.iter().position(|byte| *byte == 0).and_then(|nul| {
+            let (header, after) = (&rest[..nul], &rest[nul + 1..]);
+            let space = header.iter().position(|byte| *byte == b' ')?;
+            (after.len() >= raw_len).then(|| (&header[..space], &header[space + 1..], after))
+        });
+        let (mode, name, after) = match entry {
+            Some(entry) => entry,
+            None => {
+                problems.push(Problem::Corrupt {
+                    id: *id,
+                    reason: "truncated tree entry".to_string(),
+                });
+                return None;
+            }
+        };
+        let target = ObjectId::from_bytes(repo.algorithm(), &after[..raw_len])
+            .expect("entry hash has the algorithm's length");
+        rest = &after[raw_len..];
+
+        let is_tree = match mode {
+            b"40000" => {
+                links.push((ObjectType::Tree, target));
+                true
+            }
+            b"100644" | b"100755" | b"120000" => {
+                links.push((ObjectType::Blob, target));
+                false
+            }
+            b"160000" => false,
+            _ => {
+                found.push(("badFilemode", "contains bad file modes"));
+                false
+            }
+        };
+
+        if name.is_empty() {
+            found.push(("emptyName", "contains empty pathname"));
+        } else if name.contains(&b'/') {
+            found.push(("fullPathname", "contains full pathnames"));
+        } else if name == b"." {
+            found.push(("hasDot", "contains '.'"));
+        } else if name == b".." {
+            found.push(("hasDotdot", "contains '..'"));
+        } else if name.eq_ignore_ascii_case(utils::REPO_FOLDER_NAME.as_bytes()) {
+            found.push(("hasDotrevy", "contains '.revy'"));
+        }
+
+        let mut key = name.to_vec();
+        if is_tree {
+            key.push(b'/');
+        }
+        if !names.insert(name) {
+            found.push(("duplicateEntries", "contains duplicate file entries"));
+        } else if previous_key
+            .as_ref()
+            .is_some_and(|previous| *previous >= key)
+        {
+            found.push(("treeNotSorted", "not properly sorted"));
+        }
+        previous_key = Some(key);
+    }
+
+    let mut reported = HashSet::new();
+    for (code, message) in found {
+        if reported.insert(code) {
+            problems.push(Problem::BadTree {
+                id: *id,
+                code,
+                message,
+            });
+        }
+    }
+    Some(links)
+}
+
+/// Records that the object `id` is corrupt, which ends checking it.
+fn report_corrupt(
+    problems: &mut Vec<Problem>,
+    id: &ObjectId,
+    reason: String,
+) -> Result<Option<Checked>> {
+    problems.push(Problem::Corrupt { id: *id, reason });
+    Ok(None)
+}
+
+/// Returns the reason of a parse error, without the generic `corrupt object` prefix.
+fn reason_of(err: RevyError) -> String {
+    match err {
+        RevyError::CorruptObject { reason, .. } => reason,
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::config::{Config, ConfigLevel};
+use crate::error::{Result, RevyError};
+use crate::object_id::ObjectId;
+
+/// The hash function a repository names its objects with, recorded as
+/// `extensions.objectformat` in the repository config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Returns the name used for the algorithm in the config and on the command line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Creates a `HashAlgorithm` from its config name.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(name: &str) -> Option<HashAlgorithm> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Returns the length of a raw digest in bytes, as stored in tree entries.
+    pub fn raw_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    /// Returns the length of a digest in hex characters.
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    /// Returns the algorithm configured for the repository in `revy_dir`, defaulting to SHA-1
+    /// when the repository has no `objectformat` extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadConfig` error if the repository config can't be parsed or names an
+    /// unknown format, since objects written with the wrong algorithm would be unreadable.
+    pub fn for_repository(revy_dir: &Path) -> Result<HashAlgorithm> {
+        // Extensions only count in the repository's own config.
+        let config = Config::from_file(&revy_dir.join("config"), ConfigLevel::Local)?;
+        match config.get("extensions.objectformat") {
+            None => Ok(HashAlgorithm::Sha1),
+            Some(name) => HashAlgorithm::from_str(name).ok_or_else(|| {
+                RevyError::BadConfig(format!(
+                    "invalid value for 'extensions.objectformat': '{}'",
+                    name
+                ))
+            }),
+        }
+    }
+
+    /// Creates an incremental hasher for the algorithm.
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// Hashes `data` in one go.
+    pub fn digest(&self, data: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// An in-progress digest for one of the supported algorithms.
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Consumes the hasher and returns the digest as an object id.
+    pub fn finalize(self) -> ObjectId {
+        let (algorithm, digest) = match self {
+            Hasher::Sha1(hasher) => (HashAlgorithm::Sha1, hasher.finalize().to_vec()),
+            Hasher::Sha256(hasher) => (HashAlgorithm::Sha256, hasher.finalize().to_vec()),
+        };
+        ObjectId::from_bytes(algorithm, &digest).expect("digest length matches the algorithm")
+    }
+}
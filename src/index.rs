@@ -0,0 +1,367 @@
+//! The staging area, stored at `.revy/index` in git's index version 2 format.
+//!
+//! The file starts with a `DIRC` signature, the version and the number of entries. Each
+//! entry holds the stat data of the file when it was staged, its mode, blob id, flags and
+//! path, padded with NULs to a multiple of eight bytes. The file ends with a hash of
+//! everything before it.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::error::{Result, RevyError};
+use crate::hash::HashAlgorithm;
+use crate::lockfile::LockFile;
+use crate::object::{Metadata, ObjectType};
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::tree::{FileMode, Node, Tree};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const VERSION: u32 = 2;
+
+/// Size of the fixed part of an entry before the object id: ten 32-bit stat and mode fields.
+const ENTRY_STAT_SIZE: usize = 40;
+const NAME_LENGTH_MASK: u16 = 0x0fff;
+const STAGE_MASK: u16 = 0x3000;
+const STAGE_SHIFT: u16 = 12;
+const EXTENDED_FLAG: u16 = 0x4000;
+
+/// The file system metadata recorded for an entry, used to tell whether the file may have
+/// changed since it was staged without hashing it again. Git truncates every field to 32 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    /// Collects the stat data from the metadata of a file, which should come from
+    /// `symlink_metadata` so symbolic links are described rather than followed.
+    pub fn from_metadata(metadata: &fs::Metadata) -> StatData {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            StatData {
+                ctime: metadata.ctime() as u32,
+                ctime_nsec: metadata.ctime_nsec() as u32,
+                mtime: metadata.mtime() as u32,
+                mtime_nsec: metadata.mtime_nsec() as u32,
+                dev: metadata.dev() as u32,
+                ino: metadata.ino() as u32,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                size: metadata.len() as u32,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            StatData {
+                mtime: mtime.as_secs() as u32,
+                mtime_nsec: mtime.subsec_nanos(),
+                size: metadata.len() as u32,
+                ..StatData::default()
+            }
+        }
+    }
+}
+
+/// A file in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    pub mode: FileMode,
+    pub id: ObjectId,
+    /// The merge stage; 0 for a normal entry.
+    pub stage: u8,
+    /// The path relative to the root of the working tree, with `/` separators.
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// Creates a stage 0 entry for `path`.
+    pub fn new(path: String, mode: FileMode, id: ObjectId, stat: StatData) -> IndexEntry {
+        IndexEntry {
+            stat,
+            mode,
+            id,
+            stage: 0,
+            path,
+        }
+    }
+}
+
+/// The entries of the index, kept sorted by path.
+#[derive(Debug, Default, Clone)]
+pub struct Index {
+    entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Returns the entries, sorted by path.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry for `path`, if it is staged.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path)
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Stages `entry`, replacing the entry for the same path.
+    ///
+    /// A file and a directory can't share a path, so staging `a/b` drops a file `a`, and
+    /// staging `a` drops everything under `a/`.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let mut parent = entry.path.as_str();
+        while let Some((dir, _name)) = parent.rsplit_once('/') {
+            self.remove(dir);
+            parent = dir;
+        }
+        self.remove_dir(&entry.path);
+
+        match self.position(&entry.path) {
+            Ok(position) => self.entries[position] = entry,
+            Err(position) => self.entries.insert(position, entry),
+        }
+    }
+
+    /// Removes the entry for `path`, returning it if there was one.
+    pub fn remove(&mut self, path: &str) -> Option<IndexEntry> {
+        match self.position(path) {
+            Ok(position) => Some(self.entries.remove(position)),
+            Err(_position) => None,
+        }
+    }
+
+    /// Removes every entry under the directory `dir`, returning them.
+    pub fn remove_dir(&mut self, dir: &str) -> Vec<IndexEntry> {
+        let prefix = format!("{}/", dir);
+        let (removed, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| entry.path.starts_with(&prefix));
+        self.entries = kept;
+        removed
+    }
+
+    fn position(&self, path: &str) -> std::result::Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| entry.path.as_bytes().cmp(path.as_bytes()))
+    }
+
+    /// Parses an index file. Extensions are skipped, as revy doesn't use any of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CorruptIndex` error if the file is truncated, has a bad signature, version
+    /// or checksum, or holds entries revy can't represent.
+    pub fn from_bytes(data: &[u8], algorithm: HashAlgorithm) -> Result<Index> {
+        let corrupt = |reason: &str| RevyError::CorruptIndex(reason.to_string());
+
+        let raw_len = algorithm.raw_len();
+        if data.len() < 12 + raw_len {
+            return Err(corrupt("file is too short"));
+        }
+        let (body, checksum) = data.split_at(data.len() - raw_len);
+        if algorithm.digest(body).as_bytes() != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+        if &body[..4] != SIGNATURE {
+            return Err(corrupt("bad signature"));
+        }
+        let version = read_u32(body, 4);
+        if version != VERSION {
+            return Err(RevyError::CorruptIndex(format!(
+                "unsupported index version {}",
+                version
+            )));
+        }
+        let count = read_u32(body, 8) as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut offset = 12;
+        for _ in 0..count {
+            let fixed_size = ENTRY_STAT_SIZE + raw_len + 2;
+            if offset + fixed_size > body.len() {
+                return Err(corrupt("truncated entry"));
+            }
+            let field = |index: usize| read_u32(body, offset + index * 4);
+            let stat = StatData {
+                ctime: field(0),
+                ctime_nsec: field(1),
+                mtime: field(2),
+                mtime_nsec: field(3),
+                dev: field(4),
+                ino: field(5),
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+            };
+            let mode = format!("{:o}", field(6))
+                .parse::<u32>()
+                .ok()
+                .and_then(|mode| FileMode::from_u32(mode).ok())
+                .filter(|mode| *mode != FileMode::Directory)
+                .ok_or_else(|| corrupt("invalid entry mode"))?;
+            let id_offset = offset + ENTRY_STAT_SIZE;
+            let id = ObjectId::from_bytes(algorithm, &body[id_offset..id_offset + raw_len])?;
+            let flags =
+                u16::from_be_bytes([body[id_offset + raw_len], body[id_offset + raw_len + 1]]);
+            if flags & EXTENDED_FLAG != 0 {
+                return Err(corrupt("extended flags are not allowed in version 2"));
+            }
+
+            // The name length field saturates, so the path always runs to the next NUL.
+            let path_offset = offset + fixed_size;
+            let path_len = body[path_offset..]
+                .iter()
+                .position(|byte| *byte == 0)
+                .ok_or_else(|| corrupt("unterminated path"))?;
+            let path = std::str::from_utf8(&body[path_offset..path_offset + path_len])
+                .map_err(|_err| corrupt("path is not valid UTF-8"))?
+                .to_string();
+
+            entries.push(IndexEntry {
+                stat,
+                mode,
+                id,
+                stage: ((flags & STAGE_MASK) >> STAGE_SHIFT) as u8,
+                path,
+            });
+            offset += entry_size(fixed_size, path_len);
+        }
+
+        if !entries
+            .windows(2)
+            .all(|pair| pair[0].path.as_bytes() <= pair[1].path.as_bytes())
+        {
+            return Err(corrupt("entries are not sorted"));
+        }
+        Ok(Index { entries })
+    }
+
+    /// Serializes the index, including the trailing checksum.
+    pub fn as_bytes(&self, algorithm: HashAlgorithm) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(SIGNATURE);
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            let start = data.len();
+            let stat = &entry.stat;
+            let mode = u32::from_str_radix(entry.mode.as_u32_str(), 8)
+                .expect("file modes are octal numbers");
+            for field in [
+                stat.ctime,
+                stat.ctime_nsec,
+                stat.mtime,
+                stat.mtime_nsec,
+                stat.dev,
+                stat.ino,
+                mode,
+                stat.uid,
+                stat.gid,
+                stat.size,
+            ] {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+            data.extend_from_slice(entry.id.as_bytes());
+
+            let name_length = entry.path.len().min(NAME_LENGTH_MASK as usize) as u16;
+            let flags = ((entry.stage as u16) << STAGE_SHIFT) & STAGE_MASK | name_length;
+            data.extend_from_slice(&flags.to_be_bytes());
+            data.extend_from_slice(entry.path.as_bytes());
+
+            let fixed_size = ENTRY_STAT_SIZE + algorithm.raw_len() + 2;
+            data.resize(start + entry_size(fixed_size, entry.path.len()), 0);
+        }
+
+        let checksum = algorithm.digest(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        data
+    }
+
+    /// Reads the index of `repo`. A repository without an index file has an empty index.
+    pub(crate) fn load(repo: &Repository) -> Result<Index> {
+        let path = repo.index_path();
+        match fs::read(&path) {
+            Ok(data) => Index::from_bytes(&data, repo.algorithm()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Index::default()),
+            Err(err) => Err(RevyError::io(
+                format!("Failed to read {}", path.display()),
+                err,
+            )),
+        }
+    }
+
+    /// Writes the index of `repo`, through `index.lock` so a concurrent writer fails instead
+    /// of being overwritten and readers never see a half written file.
+    pub(crate) fn save(&self, repo: &Repository) -> Result<()> {
+        let mut lock = LockFile::acquire(&repo.index_path())?;
+        lock.write_all(&self.as_bytes(repo.algorithm()))?;
+        lock.commit()
+    }
+
+    /// Stores the staged content as tree objects, returning the id of the root tree.
+    pub(crate) fn write_tree(&self, repo: &Repository) -> Result<ObjectId> {
+        let entries: Vec<&IndexEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .collect();
+        write_tree_level(repo, &entries, "")
+    }
+}
+
+/// Writes the tree for the entries under `prefix`, which all have paths starting with it.
+fn write_tree_level(repo: &Repository, entries: &[&IndexEntry], prefix: &str) -> Result<ObjectId> {
+    let mut nodes = Vec::new();
+    let mut directories: BTreeMap<&str, Vec<&IndexEntry>> = BTreeMap::new();
+    for entry in entries {
+        let rest = &entry.path[prefix.len()..];
+        match rest.split_once('/') {
+            Some((directory, _rest)) => directories.entry(directory).or_default().push(entry),
+            None => nodes.push(Node::new(entry.mode, rest.to_string(), entry.id)),
+        }
+    }
+
+    for (directory, children) in directories {
+        let child_prefix = format!("{}{}/", prefix, directory);
+        let id = write_tree_level(repo, &children, &child_prefix)?;
+        nodes.push(Node::new(FileMode::Directory, directory.to_string(), id));
+    }
+
+    let tree = Tree::new(nodes)?;
+    Ok(repo
+        .write_object(ObjectType::Tree, Metadata::Tree(tree))?
+        .hash)
+}
+
+/// Returns the size of an entry with a `path_len` byte path: the fixed fields, the path and
+/// one to eight NULs to pad it to a multiple of eight bytes.
+fn entry_size(fixed_size: usize, path_len: usize) -> usize {
+    (fixed_size + path_len + 8) & !7
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
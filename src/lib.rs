@@ -0,0 +1,32 @@
+//! Revy is a small version control system that stores its data in git's object format.
+//!
+//! The library is built around [`Repository`], a handle on a working tree and its `.revy`
+//! directory. Objects are read, written and resolved through it without printing anything,
+//! so other tools can embed revy instead of running the `revy` binary.
+
+pub mod blob;
+pub mod branch;
+pub mod checkout;
+pub mod commit;
+pub mod config;
+pub mod error;
+pub mod fsck;
+pub mod hash;
+pub mod index;
+mod lockfile;
+pub mod log;
+pub mod object;
+pub mod object_id;
+pub mod reflog;
+pub mod refs;
+pub mod repository;
+pub mod revision;
+pub mod status;
+pub mod tag;
+pub mod tree;
+pub mod utils;
+pub mod worktree;
+
+pub use error::{Result, RevyError};
+pub use object_id::ObjectId;
+pub use repository::Repository;
@@ -0,0 +1,101 @@
+//! Lock files, git's way of updating a file atomically and keeping concurrent writers out.
+//!
+//! To update `<path>`, a writer creates `<path>.lock` exclusively, writes the new content to
+//! it and renames it over `<path>`. A second writer fails to create the lock instead of
+//! overwriting the first one's change, and readers never see a half written file.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RevyError};
+
+/// A held `<path>.lock`. It is removed when dropped without being committed, so an error
+/// part way through an update leaves `<path>` as it was.
+#[derive(Debug)]
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Takes the lock on `path`, creating missing parent directories.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lock is already held, or can't be created.
+    pub(crate) fn acquire(path: &Path) -> Result<LockFile> {
+        let mut lock_name = path.file_name().unwrap_or_default().to_os_string();
+        lock_name.push(".lock");
+        let lock_path = path.with_file_name(lock_name);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| {
+                RevyError::io(format!("Unable to create '{}'", lock_path.display()), err)
+            })?;
+        }
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|err| {
+                RevyError::io(format!("Unable to create '{}'", lock_path.display()), err)
+            })?;
+
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Appends `data` to the new content.
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .expect("the lock is open until it is committed");
+        file.write_all(data).map_err(|err| {
+            RevyError::io(format!("Failed to write {}", self.lock_path.display()), err)
+        })
+    }
+
+    /// Replaces the locked file with what was written, releasing the lock.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        let file = self
+            .file
+            .as_ref()
+            .expect("the lock is open until it is committed");
+        file.sync_all()
+            .and_then(|_| fs::rename(&self.lock_path, &self.path))
+            .map_err(|err| {
+                RevyError::io(format!("Failed to write {}", self.path.display()), err)
+            })?;
+        self.file = None;
+        Ok(())
+    }
+
+    /// Deletes the locked file, releasing the lock.
+    pub(crate) fn commit_removal(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(RevyError::io(
+                format!("Failed to remove {}", self.path.display()),
+                err,
+            )),
+        }
+        // Dropping `self` releases the lock.
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Once committed the lock was renamed away, and the path may already hold another
+        // writer's lock, so only a lock that is still open is removed.
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Walking the history for `log`: which commits to show, in which order, and how to draw
+//! the lines between them.
+//!
+//! Commits are listed newest first by committer date, reading the history only as far as
+//! needed, which is git's default order. Path limiting needs the whole history first, and
+//! then a commit is also never listed before one that has it as a parent, like git's
+//! `--date-order`. `topo_order` instead follows each line of history down to where it forks
+//! off before going on with the next, like `--topo-order`.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
+
+use regex::Regex;
+
+use crate::commit::Commit;
+use crate::error::{Result, RevyError};
+use crate::object::Metadata;
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::revision::{self, Revision};
+
+/// Which of the walked commits `log` shows.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Stop after this many commits.
+    pub max_count: Option<usize>,
+    /// Only commits committed at or after this time, in seconds since the epoch.
+    pub since: Option<i64>,
+    /// Only commits committed at or before this time, in seconds since the epoch.
+    pub until: Option<i64>,
+    /// Only commits whose author, as `Name <email>`, matches this.
+    pub author: Option<Regex>,
+    /// Only commits with a line of their message that matches this.
+    pub grep: Option<Regex>,
+    /// Only commits that changed one of these paths, relative to the root of the working
+    /// tree. A directory stands for everything below it.
+    pub paths: Vec<String>,
+    /// Keep each line of history together instead of interleaving commits by date, which
+    /// is the order `--graph` draws best.
+    pub topo_order: bool,
+}
+
+/// A commit `log` shows.
+#[derive(Debug)]
+pub struct LogEntry {
+    pub id: ObjectId,
+    pub commit: Commit,
+    /// The nearest shown ancestors along each parent, which is what a graph connects the
+    /// commit to when commits in between are filtered out. Without path limiting or
+    /// `topo_order` the history isn't read in full, and these are the commit's own parents
+    /// outside the excluded history.
+    pub parents: Vec<ObjectId>,
+}
+
+/// Lists the commits reachable from the included `revisions` but not from the excluded
+/// ones that match `options`, newest first.
+///
+/// # Errors
+///
+/// Returns an error if a commit or tree along the way can't be read, or a revision isn't a
+/// commit.
+pub fn log(
+    repo: &Repository,
+    revisions: &[Revision],
+    options: &LogOptions,
+) -> Result<Vec<LogEntry>> {
+    let mut excluded = BTreeSet::new();
+    let mut queue: VecDeque<ObjectId> = VecDeque::new();
+    for revision in revisions {
+        if let Revision::Exclude(id) = revision {
+            queue.push_back(peel_to_commit(repo, id)?);
+        }
+    }
+    while let Some(id) = queue.pop_front() {
+        if excluded.insert(id) {
+            queue.extend(read_commit(repo, &id)?.parents);
+        }
+    }
+
+    let mut tips = Vec::new();
+    for revision in revisions {
+        if let Revision::Include(id) = revision {
+            tips.push(peel_to_commit(repo, id)?);
+        }
+    }
+    if options.paths.is_empty() && !options.topo_order {
+        return walk_by_date(repo, &tips, &excluded, options);
+    }
+
+    // Read every wanted commit, counting how many of them have each one as a parent.
+    let mut commits = BTreeMap::new();
+    let mut children: BTreeMap<ObjectId, usize> = BTreeMap::new();
+    let mut queue: VecDeque<ObjectId> = tips.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        if excluded.contains(&id) || commits.contains_key(&id) {
+            continue;
+        }
+        let commit = read_commit(repo, &id)?;
+        for parent in commit
+            .parents
+            .iter()
+            .filter(|parent| !excluded.contains(parent))
+        {
+            *children.entry(*parent).or_default() += 1;
+            queue.push_back(*parent);
+        }
+        commits.insert(id, commit);
+    }
+
+    // Emit a commit none of the remaining ones has as a parent, until none are left: the
+    // newest one, or for `topo_order` the one that became ready last.
+    let priority = |id: &ObjectId, sequence: i64| match options.topo_order {
+        true => (0, sequence),
+        false => (commits[id].committer.timestamp, -sequence),
+    };
+    let mut order = Vec::new();
+    let mut ready = BinaryHeap::new();
+    let mut sequence = 0;
+    let mut started = BTreeSet::new();
+    let mut starts: Vec<ObjectId> = tips
+        .iter()
+        .filter(|tip| commits.contains_key(tip) && !children.contains_key(tip))
+        .filter(|tip| started.insert(**tip))
+        .copied()
+        .collect();
+    // The newest start goes first either way, so for `topo_order` it is pushed last.
+    starts.sort_by_key(|tip| Reverse(commits[tip].committer.timestamp));
+    if options.topo_order {
+        starts.reverse();
+    }
+    for tip in starts {
+        ready.push((priority(&tip, sequence), tip));
+        sequence += 1;
+    }
+    while let Some((_priority, id)) = ready.pop() {
+        order.push(id);
+        for parent in &commits[&id].parents {
+            let Some(remaining) = children.get_mut(parent) else {
+                continue;
+            };
+            *remaining -= 1;
+            if *remaining == 0 {
+                ready.push((priority(parent, sequence), *parent));
+                sequence += 1;
+            }
+        }
+    }
+
+    // A commit that left the paths as one of its parents had them is hidden, and the
+    // history goes on through that parent only, so side branches merged without touching
+    // the paths disappear.
+    let mut shown = BTreeSet::new();
+    let mut simplified = BTreeMap::new();
+    for id in &order {
+        let commit = &commits[id];
+        let treesame = match options.paths.is_empty() {
+            true => None,
+            false => treesame_parent(repo, commit, &options.paths)?,
+        };
+        if let Some(parent) = treesame {
+            simplified.insert(*id, parent);
+        } else if matches(repo, commit, options)? {
+            shown.insert(*id);
+        }
+    }
+
+    // Parents come after their children, so walking backwards rewrites parents first.
+    let mut rewritten: BTreeMap<ObjectId, Vec<ObjectId>> = BTreeMap::new();
+    for id in order.iter().rev() {
+        let mut parents = Vec::new();
+        let followed = match simplified.get(id) {
+            Some(parent) => std::slice::from_ref(parent),
+            None => &commits[id].parents,
+        };
+        for parent in followed {
+            let nearest = match (shown.contains(parent), rewritten.get(parent)) {
+                (true, _) => vec![*parent],
+                (false, Some(nearest)) => nearest.clone(),
+                (false, None) => Vec::new(),
+            };
+            for parent in nearest {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+        rewritten.insert(*id, parents);
+    }
+
+    let mut entries = Vec::new();
+    for id in order.into_iter().filter(|id| shown.contains(id)) {
+        if options.max_count.is_some_and(|max| entries.len() >= max) {
+            break;
+        }
+        if let Some(commit) = commits.remove(&id) {
+            entries.push(LogEntry {
+                id,
+                commit,
+                parents: rewritten.remove(&id).unwrap_or_default(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Lists the commits reachable from `tips` but not in `excluded` that match `options`,
+/// always taking the newest commit reached so far next. Commits are only read once the walk
+/// reaches them, so it stops as soon as `max_count` commits are found.
+fn walk_by_date(
+    repo: &Repository,
+    tips: &[ObjectId],
+    excluded: &BTreeSet<ObjectId>,
+    options: &LogOptions,
+) -> Result<Vec<LogEntry>> {
+    let mut queue = DateQueue::default();
+    for tip in tips.iter().filter(|tip| !excluded.contains(tip)) {
+        queue.reach(repo, tip)?;
+    }
+
+    let mut entries = Vec::new();
+    while let Some((id, commit)) = queue.pop() {
+        if options.max_count.is_some_and(|max| entries.len() >= max) {
+            break;
+        }
+        let parents: Vec<ObjectId> = commit
+            .parents
+            .iter()
+            .filter(|parent| !excluded.contains(parent))
+            .copied()
+            .collect();
+        for parent in &parents {
+            queue.reach(repo, parent)?;
+        }
+        if matches(repo, &commit, options)? {
+            entries.push(LogEntry {
+                id,
+                commit,
+                parents,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The commits a walk by date has reached but not yet taken, newest first. Among commits
+/// of the same date the one reached first goes first.
+#[derive(Default)]
+struct DateQueue {
+    order: BinaryHeap<((i64, i64), ObjectId)>,
+    commits: BTreeMap<ObjectId, Commit>,
+    reached: BTreeSet<ObjectId>,
+}
+
+impl DateQueue {
+    /// Reads and queues the commit `id`, unless it was reached before.
+    fn reach(&mut self, repo: &Repository, id: &ObjectId) -> Result<()> {
+        if !self.reached.insert(*id) {
+            return Ok(());
+        }
+        let commit = read_commit(repo, id)?;
+        let sequence = self.reached.len() as i64;
+        self.order
+            .push(((commit.committer.timestamp, -sequence), *id));
+        self.commits.insert(*id, commit);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<(ObjectId, Commit)> {
+        let (_priority, id) = self.order.pop()?;
+        let commit = self.commits.remove(&id)?;
+        Some((id, commit))
+    }
+}
+
+fn read_commit(repo: &Repository, id: &ObjectId) -> Result<Commit> {
+    match repo.read_object(id)?.metadata {
+        Metadata::Commit(commit) => Ok(commit),
+        _ => Err(RevyError::InvalidArgument(format!(
+            "{} is not a commit",
+            id
+        ))),
+    }
+}
+
+fn peel_to_commit(repo: &Repository, id: &ObjectId) -> Result<ObjectId> {
+    repo.resolve_revision(&format!("{}^{{commit}}", id))
+}
+
+/// Check if `commit` passes the filters of `options`, other than having changed the paths
+/// compared to a parent, which `treesame_parent` checks.
+fn matches(repo: &Repository, commit: &Commit, options: &LogOptions) -> Result<bool> {
+    let timestamp = commit.committer.timestamp;
+    if options.since.is_some_and(|since| timestamp < since)
+        || options.until.is_some_and(|until| timestamp > until)
+    {
+        return Ok(false);
+    }
+    if let Some(author) = &options.author {
+        let identity = format!("{} <{}>", commit.author.name, commit.author.email);
+        if !author.is_match(&identity) {
+            return Ok(false);
+        }
+    }
+    if let Some(grep) = &options.grep {
+        // Each line is matched on its own, so `^` and `$` anchor to lines as in git.
+        if !commit.message.lines().any(|line| grep.is_match(line)) {
+            return Ok(false);
+        }
+    }
+    // A root commit changed the paths only if it added them.
+    if options.paths.is_empty() || !commit.parents.is_empty() {
+        return Ok(true);
+    }
+    for path in &options.paths {
+        if revision::tree_entry(repo, &commit.tree, path)?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the first parent of `commit` that has the same entries at `paths`, if any.
+fn treesame_parent(
+    repo: &Repository,
+    commit: &Commit,
+    paths: &[String],
+) -> Result<Option<ObjectId>> {
+    let entries = |tree: &ObjectId| -> Result<Vec<Option<ObjectId>>> {
+        paths
+            .iter()
+            .map(|path| revision::tree_entry(repo, tree, path))
+            .collect()
+    };
+    let own = entries(&commit.tree)?;
+    for parent in &commit.parents {
+        if entries(&read_commit(repo, parent)?.tree)? == own {
+            return Ok(Some(*parent));
+        }
+    }
+    Ok(None)
+}
+
+/// Draws the history as ASCII art beside the commits, one lane per line of history that
+/// is still waiting for its next commit.
+#[derive(Debug, Default)]
+pub struct Graph {
+    lanes: Vec<ObjectId>,
+    /// The last line joining lanes drawn for the previous commit, if there was one.
+    last_connector: Option<String>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::default()
+    }
+
+    /// Returns the lines of `text`, the commit `id`, with the graph drawn before them.
+    /// `parents` should be the rewritten parents of a `LogEntry`. With `separated` the
+    /// commit is preceded by a blank line, as between commits in the default format.
+    ///
+    /// Like git, the lines joining the lanes before the commit to the lanes after it go
+    /// beside the lines following the first, and on their own once the text runs out.
+    pub fn draw(
+        &mut self,
+        id: &ObjectId,
+        parents: &[ObjectId],
+        text: &str,
+        separated: bool,
+    ) -> Vec<String> {
+        let waiting = self.lanes.len();
+        let column = match self.lanes.iter().position(|lane| lane == id) {
+            Some(column) => column,
+            None => {
+                self.lanes.push(*id);
+                self.lanes.len() - 1
+            }
+        };
+        let old_width = self.lanes.len();
+
+        // Where each old lane continues: the commit's lane into all of its parents, other
+        // lanes waiting for this commit into its first parent, and the rest into themselves.
+        let mut lanes: Vec<ObjectId> = Vec::new();
+        let mut edges = Vec::new();
+        for (index, lane) in self.lanes.iter().enumerate() {
+            if index == column {
+                for parent in parents {
+                    edges.push((index, place(&mut lanes, parent)));
+                }
+            } else if lane == id {
+                if let Some(parent) = parents.first() {
+                    edges.push((index, place(&mut lanes, parent)));
+                }
+            } else {
+                edges.push((index, place(&mut lanes, lane)));
+            }
+        }
+
+        // A lane that was moving left on the line just above and keeps going is already
+        // drawn leaning on the commit line.
+        let mut commit_line = lanes_line(old_width, Some(column)).into_bytes();
+        if let (Some(last), false) = (&self.last_connector, separated) {
+            for (from, to) in &edges {
+                if *from != column && to < from && last.as_bytes().get(from * 2 + 1) == Some(&b'/')
+                {
+                    commit_line[from * 2] = b'/';
+                }
+            }
+        }
+
+        // Every line is as wide as the wider side, so the text stays aligned.
+        let width = old_width.max(lanes.len()) * 2;
+        let pad = |line: &str| format!("{:width$}", line, width = width);
+        let connectors = connector_lines(&edges);
+        let padding = pad(&lanes_line(lanes.len(), None));
+
+        let mut lines = Vec::new();
+        if separated {
+            lines.push(pad(&lanes_line(waiting, None)));
+        }
+        let mut prefixes = std::iter::once(String::from_utf8_lossy(&commit_line).into_owned())
+            .chain(connectors.iter().cloned());
+        let mut text_lines = text.lines();
+        loop {
+            match (prefixes.next(), text_lines.next()) {
+                (Some(prefix), line) => {
+                    lines.push(format!("{}{}", pad(&prefix), line.unwrap_or("")))
+                }
+                (None, Some(line)) => lines.push(format!("{}{}", padding, line)),
+                (None, None) => break,
+            }
+        }
+        self.last_connector = connectors.last().cloned();
+        self.lanes = lanes;
+        lines
+    }
+}
+
+/// Returns the lane waiting for `target`, adding one at the end if there is none.
+fn place(lanes: &mut Vec<ObjectId>, target: &ObjectId) -> usize {
+    match lanes.iter().position(|lane| lane == target) {
+        Some(position) => position,
+        None => {
+            lanes.push(*target);
+            lanes.len() - 1
+        }
+    }
+}
+
+/// Returns `| | |` for `count` lanes, with a `*` in lane `commit`, and a trailing space.
+fn lanes_line(count: usize, commit: Option<usize>) -> String {
+    (0..count)
+        .map(|lane| if Some(lane) == commit { "* " } else { "| " })
+        .collect()
+}
+
+/// Draws lanes moving from the column of each edge's start to the column of its end, one
+/// column per line, with `\` and `/`. Nothing is drawn when no lane moves.
+fn connector_lines(edges: &[(usize, usize)]) -> Vec<String> {
+    let mut positions: Vec<usize> = edges.iter().map(|(from, _to)| *from).collect();
+    let mut lines = Vec::new();
+    while positions
+        .iter()
+        .zip(edges)
+        .any(|(position, (_from, to))| position != to)
+    {
+        let width = positions
+            .iter()
+            .chain(edges.iter().map(|(_from, to)| to))
+            .max();
+        let mut line = vec![b' '; width.map_or(0, |width| width * 2 + 2)];
+        for (position, (_from, to)) in positions.iter_mut().zip(edges) {
+            if *position < *to {
+                line[*position * 2 + 1] = b'\\';
+                *position += 1;
+            } else if *position > *to {
+                line[*position * 2 - 1] = b'/';
+                *position -= 1;
+            } else if line[*position * 2] == b' ' {
+                line[*position * 2] = b'|';
+            }
+        }
+        lines.push(String::from_utf8_lossy(&line).trim_end().to_string());
+    }
+    lines
+}
@@ -1,33 +1,85 @@
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
-mod blob;
+use clap::{ArgAction, Parser, Subcommand};
 mod command;
-mod object;
-mod tree;
-mod utils;
+use command::write_error;
+
+use revy::checkout::SwitchOptions;
+use revy::commit::CommitOptions;
+use revy::config::ConfigLevel;
+use revy::{hash, object, refs, Repository, Result, RevyError};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Run as if revy was started in this directory; may be given multiple times
+    #[arg(short = 'C', global = true, value_name = "PATH")]
+    directory: Vec<PathBuf>,
+
+    /// The path to the repository (the .revy directory), instead of searching for it.
+    /// Defaults to $REVY_DIR
+    #[arg(long, global = true, value_name = "PATH")]
+    revy_dir: Option<PathBuf>,
+
+    /// The root of the working tree. Defaults to $REVY_WORK_TREE
+    #[arg(long, global = true, value_name = "PATH")]
+    work_tree: Option<PathBuf>,
+
     #[command(subcommand)]
     commnds: Command,
 }
 
+/// Where the repository is, as given on the command line or in the environment.
+struct Location {
+    revy_dir: Option<PathBuf>,
+    work_tree: Option<PathBuf>,
+}
+
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Intialize the revy repository
     Init {
         /// The name of the new repository
         name: Option<String>,
+
+        /// The hash algorithm used to name objects (sha1 or sha256)
+        #[arg(long, default_value = "sha1", value_parser = parse_object_format)]
+        object_format: hash::HashAlgorithm,
     },
-    /// Print the contents of a file
+    /// Print the contents, type or size of objects
     CatFile {
         /// Definne if the output should be pretty
         #[arg(short, long)]
         pretty_print: bool,
-        /// The hash of the object to display
-        hash: String,
+
+        /// Show the object type
+        #[arg(short = 't')]
+        show_type: bool,
+
+        /// Show the object size
+        #[arg(short = 's')]
+        show_size: bool,
+
+        /// Exit with zero status if the object exists and is valid, without printing anything
+        #[arg(short = 'e')]
+        exists: bool,
+
+        /// Print the id, type, size and contents of each object named on stdin
+        #[arg(long, conflicts_with = "batch_check")]
+        batch: bool,
+
+        /// Print the id, type and size of each object named on stdin
+        #[arg(long)]
+        batch_check: bool,
+
+        /// With --batch or --batch-check, process every object instead of reading stdin
+        #[arg(long)]
+        batch_all_objects: bool,
+
+        /// The object to display, optionally preceded by the type it must have
+        #[arg(num_args = 0..=2, value_names = ["TYPE", "HASH"])]
+        args: Vec<String>,
     },
 
     /// Create a hash of the object
@@ -39,15 +91,52 @@ enum Command {
         #[arg(short, long)]
         write: bool,
     },
+    /// List the contents of a tree object
     LsTree {
-        /// The hash of the object to display
+        /// The hash of the tree, or of a commit whose tree should be listed
         hash: String,
+
+        /// Only list the entries matching these paths
+        paths: Vec<String>,
+
         /// List only filenames (instead of the "long" output), one per line. Cannot be combined with --object-only.
         #[arg(long)]
         name_only: bool,
+
+        /// Recurse into subtrees
+        #[arg(short)]
+        recursive: bool,
+
+        /// Show only the named tree entries themselves, not their children
+        #[arg(short)]
+        dirs_only: bool,
+
+        /// Show tree entries even when going to recurse them
+        #[arg(short = 't')]
+        show_trees: bool,
+
+        /// Show the object size of blob entries
+        #[arg(short, long)]
+        long: bool,
     },
 
     WriteTree,
+    /// Record the staged changes as a new commit on the current branch
+    Commit {
+        /// The commit message; several are joined as separate paragraphs. Without one an
+        /// editor is opened
+        #[arg(short, long)]
+        message: Vec<String>,
+
+        /// Replace the tip of the current branch instead of adding a commit on top of it
+        #[arg(long)]
+        amend: bool,
+
+        /// Allow a commit that records the same tree as its parent
+        #[arg(long)]
+        allow_empty: bool,
+    },
+    /// Create a new commit object from a tree
     CommitTree {
         /// The commit message
         #[arg(short, long)]
@@ -56,54 +145,1079 @@ enum Command {
         /// The hash of the tree to commit
         hash: String,
 
-        /// The hash of the parent commit
+        /// The hash of a parent commit, may be given multiple times
+        #[arg(short, long)]
+        parent: Vec<String>,
+    },
+    /// Create, list or delete tags under refs/tags
+    Tag {
+        /// The name of the tag to create or delete, or a pattern when listing
+        name: Option<String>,
+
+        /// The object the new tag points at, defaults to HEAD
+        object: Option<String>,
+
+        /// Make an annotated tag object
+        #[arg(short, long)]
+        annotate: bool,
+
+        /// The message of an annotated tag, implies --annotate
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// List tags, optionally matching the given pattern
+        #[arg(short, long)]
+        list: bool,
+
+        /// Delete the named tag
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Replace an existing tag instead of failing
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// List, create, delete or rename branches
+    Branch {
+        /// Patterns when listing; `<name> [<start>]` to create a branch, `<name>...` to
+        /// delete, `[<old>] <new>` to rename, and `[<branch>]` for upstream changes
+        args: Vec<String>,
+
+        /// Delete the named branches, which must be merged
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Delete the named branches even if they are not merged
+        #[arg(short = 'D', conflicts_with = "delete")]
+        force_delete: bool,
+
+        /// Rename a branch
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+
+        /// Rename a branch even if the new name exists
+        #[arg(short = 'M', conflicts_with = "rename")]
+        force_rename: bool,
+
+        /// Reset an existing branch when creating one, or delete or rename regardless
+        #[arg(short, long)]
+        force: bool,
+
+        /// Show the commit and upstream status of each branch; twice for the upstream name
+        #[arg(short, long, action = ArgAction::Count)]
+        verbose: u8,
+
+        /// List branches, optionally matching the given patterns
+        #[arg(short, long)]
+        list: bool,
+
+        /// Make a new branch track its start point
+        #[arg(short, long, conflicts_with = "no_track")]
+        track: bool,
+
+        /// Don't make a new branch track its start point, even a remote-tracking branch
+        #[arg(long)]
+        no_track: bool,
+
+        /// Make the branch track this upstream branch
+        #[arg(short = 'u', long, value_name = "UPSTREAM")]
+        set_upstream_to: Option<String>,
+
+        /// Stop the branch from tracking an upstream branch
+        #[arg(long, conflicts_with = "set_upstream_to")]
+        unset_upstream: bool,
+    },
+    /// Switch to a branch, updating the index and the working tree
+    Switch {
+        /// The branch to switch to, or to create with -c, or a commit with --detach
+        branch: String,
+
+        /// Where to start a branch created with -c, `HEAD` by default
+        start_point: Option<String>,
+
+        /// Create the branch and switch to it
+        #[arg(short, long, conflicts_with = "detach")]
+        create: bool,
+
+        /// Detach HEAD at the commit instead of switching to a branch
+        #[arg(short, long)]
+        detach: bool,
+
+        /// Throw away local changes that are in the way
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+    },
+    /// Switch to a branch or detach HEAD at a commit, updating the index and working tree
+    Checkout {
+        /// The branch or commit to check out, or the start of a branch created with -b
+        target: Option<String>,
+
+        /// Create a branch with this name and switch to it
+        #[arg(short = 'b', value_name = "NEW_BRANCH", conflicts_with = "detach")]
+        new_branch: Option<String>,
+
+        /// Detach HEAD at the commit even if a branch is given
+        #[arg(long)]
+        detach: bool,
+
+        /// Throw away local changes that are in the way
         #[arg(short, long)]
-        parent: Option<String>,
+        force: bool,
+    },
+    /// Add file contents to the index
+    Add {
+        /// Files or directories to add, or glob patterns matched against the working tree
+        #[arg(required = true)]
+        pathspec: Vec<PathBuf>,
+
+        /// Allow adding otherwise ignored files
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Remove files from the working tree and from the index
+    Rm {
+        /// Files to remove
+        #[arg(required = true)]
+        pathspec: Vec<PathBuf>,
+
+        /// Only remove the files from the index, keeping them in the working tree
+        #[arg(long)]
+        cached: bool,
+
+        /// Allow recursive removal when a directory is given
+        #[arg(short)]
+        recursive: bool,
+
+        /// Remove files even if they have local modifications
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Update the object name stored in a ref safely
+    UpdateRef {
+        /// Delete the ref, after checking it holds the old value if one is given
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// Update the symbolic ref itself instead of the ref it points at
+        #[arg(long)]
+        no_deref: bool,
+
+        /// The reason for the update, recorded in the reflog
+        #[arg(short, value_name = "REASON", default_value = "")]
+        message: String,
+
+        /// The ref to update, e.g. refs/heads/main
+        name: String,
+
+        /// The new value, or with -d the value the ref must currently hold
+        new_value: Option<String>,
+
+        /// The value the ref must currently hold; all zeros means it must not exist
+        old_value: Option<String>,
+    },
+    /// Read, modify and delete symbolic refs
+    SymbolicRef {
+        /// Don't print an error when the ref is not symbolic, just exit with status 1
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Shorten the ref name, e.g. refs/heads/main to main
+        #[arg(long)]
+        short: bool,
+
+        /// Delete the symbolic ref
+        #[arg(short, long)]
+        delete: bool,
+
+        /// The symbolic ref, e.g. HEAD
+        name: String,
+
+        /// The ref it should point at
+        target: Option<String>,
+    },
+    /// List references and the objects they point at
+    ShowRef {
+        /// Only show branches
+        #[arg(long)]
+        heads: bool,
+
+        /// Only show tags
+        #[arg(long)]
+        tags: bool,
+
+        /// Show HEAD as well
+        #[arg(long)]
+        head: bool,
+
+        /// Only show the object names
+        #[arg(long)]
+        hash: bool,
+
+        /// Also show what annotated tags point at, as <ref>^{}
+        #[arg(short, long)]
+        dereference: bool,
+
+        /// Require the patterns to be exact ref names that exist
+        #[arg(long)]
+        verify: bool,
+
+        /// Print nothing, only set the exit status
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Only show refs named <pattern> or ending with /<pattern>
+        patterns: Vec<String>,
+    },
+    /// Output information on each ref
+    ForEachRef {
+        /// The format of each line, with fields like %(refname) and %(objectname:short)
+        #[arg(long, default_value = command::DEFAULT_REF_FORMAT)]
+        format: String,
+
+        /// Stop after showing this many refs
+        #[arg(long)]
+        count: Option<usize>,
+
+        /// Only show refs matching these globs or prefixes
+        patterns: Vec<String>,
+    },
+    /// Show the working tree status
+    Status {
+        /// Give the output in the short format
+        #[arg(short, long)]
+        short: bool,
+
+        /// Give the output in a stable, easy to parse format for scripts
+        #[arg(long, conflicts_with = "short")]
+        porcelain: bool,
+    },
+    /// Resolve revision expressions like HEAD~2, v1.0^{tree}, main:src or a..b to object ids
+    RevParse {
+        /// Check that exactly one revision is given and that it names an object
+        #[arg(long)]
+        verify: bool,
+
+        /// With --verify, exit with status 1 instead of printing an error
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Print unique abbreviations of the ids, at least this long
+        #[arg(long, value_name = "LENGTH", num_args = 0..=1, require_equals = true,
+                default_missing_value = "7")]
+        short: Option<usize>,
+
+        /// Print the short name of the ref each argument refers to, e.g. main for HEAD
+        #[arg(long, conflicts_with = "symbolic_full_name")]
+        abbrev_ref: bool,
+
+        /// Print the full name of the ref each argument refers to, e.g. refs/heads/main
+        #[arg(long)]
+        symbolic_full_name: bool,
+
+        /// The revisions or ranges to resolve
+        args: Vec<String>,
+    },
+    /// Show the commit history, newest first
+    Log {
+        /// Show each commit on one line, as its abbreviated id and subject
+        #[arg(long)]
+        oneline: bool,
+
+        /// How to show each commit: oneline, medium, or a format like "%h %an %s"
+        #[arg(long, alias = "pretty", value_name = "FORMAT")]
+        format: Option<String>,
+
+        /// Show at most this many commits
+        #[arg(short = 'n', long, value_name = "NUMBER")]
+        max_count: Option<usize>,
+
+        /// Only show commits more recent than a date, like 2.weeks.ago or 2024-01-31
+        #[arg(long, alias = "after", value_name = "DATE")]
+        since: Option<String>,
+
+        /// Only show commits older than a date
+        #[arg(long, alias = "before", value_name = "DATE")]
+        until: Option<String>,
+
+        /// Only show commits whose author matches a regular expression
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Only show commits whose message matches a regular expression
+        #[arg(long)]
+        grep: Option<String>,
+
+        /// Draw the history as a graph beside the commits
+        #[arg(long)]
+        graph: bool,
+
+        /// Where to start, HEAD by default; ranges like main..topic are accepted
+        revisions: Vec<String>,
+
+        /// Only show commits that changed these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show or manage the log of where refs pointed
+    #[command(args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[command(subcommand)]
+        action: Option<ReflogAction>,
+
+        /// The ref whose log to show, HEAD by default
+        name: Option<String>,
+    },
+    /// Get and set repository or global options
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Verify the connectivity and validity of the objects in the database
+    Fsck {
+        /// Print objects that exist but can't be reached from any ref
+        #[arg(long)]
+        unreachable: bool,
+
+        /// Don't print dangling objects
+        #[arg(long)]
+        no_dangling: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ReflogAction {
+    /// Show the log of a ref, newest entry first
+    Show {
+        /// The ref whose log to show
+        #[arg(default_value = "HEAD")]
+        name: String,
+    },
+    /// Drop old entries from reflogs
+    Expire {
+        /// Drop entries older than this date, e.g. 30.days.ago, or all of them with "all".
+        /// Defaults to gc.reflogExpire or 90 days
+        #[arg(long, value_name = "DATE")]
+        expire: Option<String>,
+
+        /// Process the reflogs of every ref
+        #[arg(long)]
+        all: bool,
+
+        /// The refs whose logs to expire
+        #[arg(required_unless_present = "all")]
+        names: Vec<String>,
+    },
+    /// Delete single entries, named like HEAD@{2}, from reflogs
+    Delete {
+        /// Fix up the old id of the entry after each deleted one, keeping the chain intact
+        #[arg(long)]
+        rewrite: bool,
+
+        /// The entries to delete
+        #[arg(required = true)]
+        entries: Vec<String>,
     },
 }
 
-fn main() -> std::io::Result<()> {
+#[derive(Debug, Subcommand)]
+enum ConfigAction {
+    /// Print the value of a key, exiting with status 1 if it isn't set
+    Get {
+        /// The key, e.g. user.name or remote.origin.url
+        name: String,
+
+        /// Print every value of a multi-valued key
+        #[arg(long)]
+        all: bool,
+
+        /// Check and normalize the value as a bool, an int or a path
+        #[arg(long = "type", value_name = "TYPE", value_parser = parse_config_type)]
+        kind: Option<command::ConfigType>,
+
+        /// Prefix each value with the file it comes from
+        #[arg(long)]
+        show_origin: bool,
+
+        #[command(flatten)]
+        scope: ConfigScopeArgs,
+    },
+    /// Set a key, replacing its value
+    Set {
+        /// The key, e.g. user.name
+        name: String,
+
+        /// The new value
+        value: String,
+
+        /// Add the value to the ones the key already has
+        #[arg(long)]
+        add: bool,
+
+        #[command(flatten)]
+        scope: ConfigScopeArgs,
+    },
+    /// Remove a key, exiting with status 1 if it isn't set
+    Unset {
+        /// The key, e.g. user.name
+        name: String,
+
+        /// Remove every value of a multi-valued key
+        #[arg(long)]
+        all: bool,
+
+        #[command(flatten)]
+        scope: ConfigScopeArgs,
+    },
+    /// List every key and its value
+    List {
+        /// Prefix each entry with the file it comes from
+        #[arg(long)]
+        show_origin: bool,
+
+        #[command(flatten)]
+        scope: ConfigScopeArgs,
+    },
+}
+
+/// Which config file `revy config` works on. Reads default to all of them and writes to
+/// the repository's.
+#[derive(Debug, clap::Args)]
+struct ConfigScopeArgs {
+    /// Use the system wide config file
+    #[arg(long, conflicts_with_all = ["global", "local", "file"])]
+    system: bool,
+
+    /// Use the per-user config file, ~/.revyconfig
+    #[arg(long, conflicts_with_all = ["local", "file"])]
+    global: bool,
+
+    /// Use the repository config file, .revy/config
+    #[arg(long, conflicts_with = "file")]
+    local: bool,
+
+    /// Use the given config file
+    #[arg(short, long, value_name = "PATH")]
+    file: Option<PathBuf>,
+}
+
+impl ConfigScopeArgs {
+    fn scope(self) -> command::ConfigScope {
+        match self {
+            ConfigScopeArgs { system: true, .. } => {
+                command::ConfigScope::Level(ConfigLevel::System)
+            }
+            ConfigScopeArgs { global: true, .. } => {
+                command::ConfigScope::Level(ConfigLevel::Global)
+            }
+            ConfigScopeArgs { local: true, .. } => command::ConfigScope::Level(ConfigLevel::Local),
+            ConfigScopeArgs {
+                file: Some(path), ..
+            } => command::ConfigScope::File(path),
+            _ => command::ConfigScope::Cascade,
+        }
+    }
+}
+
+fn parse_config_type(name: &str) -> std::result::Result<command::ConfigType, String> {
+    command::ConfigType::from_str(name).ok_or_else(|| format!("unknown type '{}'", name))
+}
+
+fn parse_object_format(name: &str) -> std::result::Result<hash::HashAlgorithm, String> {
+    hash::HashAlgorithm::from_str(name).ok_or_else(|| format!("unknown hash algorithm '{}'", name))
+}
+
+fn main() {
     let args = Args::parse();
+    if let Err(err) = run(args) {
+        // Output cut short by a pager or `head` is not worth complaining about.
+        if let RevyError::Io { source, .. } = &err {
+            if source.kind() == std::io::ErrorKind::BrokenPipe {
+                std::process::exit(0);
+            }
+        }
+        eprintln!("fatal: {}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// Opens the repository at `location`, or the one the current directory belongs to.
+///
+/// With a repository directory but no working tree the current directory is the working
+/// tree, as in git.
+fn open_repository(location: &Location) -> Result<Repository> {
+    let current_dir = std::env::current_dir()
+        .map_err(|err| RevyError::io("Failed to read the current directory", err))?;
+    match (&location.revy_dir, &location.work_tree) {
+        (Some(revy_dir), Some(work_tree)) => Repository::open_with_work_tree(revy_dir, work_tree),
+        (Some(revy_dir), None) => Repository::open_with_work_tree(revy_dir, current_dir),
+        (None, Some(work_tree)) => Repository::discover(current_dir)?.with_work_tree(work_tree),
+        (None, None) => Repository::discover(current_dir),
+    }
+}
+
+/// Like `open_repository`, but returns `None` when no repository was named and the current
+/// directory isn't inside one.
+fn find_repository(location: &Location) -> Result<Option<Repository>> {
+    if location.revy_dir.is_some() {
+        return open_repository(location).map(Some);
+    }
+    let current_dir = std::env::current_dir()
+        .map_err(|err| RevyError::io("Failed to read the current directory", err))?;
+    match (Repository::try_discover(current_dir)?, &location.work_tree) {
+        (Some(repo), Some(work_tree)) => repo.with_work_tree(work_tree).map(Some),
+        (repo, _) => Ok(repo),
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    // Each -C is relative to the previous one, and every other path to the last.
+    for directory in &args.directory {
+        std::env::set_current_dir(directory).map_err(|err| {
+            RevyError::io(format!("cannot change to '{}'", directory.display()), err)
+        })?;
+    }
+    let location = Location {
+        revy_dir: args
+            .revy_dir
+            .or_else(|| std::env::var_os("REVY_DIR").map(PathBuf::from)),
+        work_tree: args
+            .work_tree
+            .or_else(|| std::env::var_os("REVY_WORK_TREE").map(PathBuf::from)),
+    };
+
     match args.commnds {
-        Command::Init { name } => {
-            command::setup_revy(name.as_deref());
+        Command::Init {
+            name,
+            object_format,
+        } => {
+            command::setup_revy(name.as_deref(), location.revy_dir.as_deref(), object_format)?;
         }
-        Command::CatFile { pretty_print, hash } => {
-            if !pretty_print {
-                todo!()
+        Command::CatFile {
+            pretty_print,
+            show_type,
+            show_size,
+            exists,
+            batch,
+            batch_check,
+            batch_all_objects,
+            args,
+        } => {
+            let repo = open_repository(&location)?;
+            if batch || batch_check {
+                return command::cat_file_batch(&repo, batch, batch_all_objects);
             }
-            let object = object::Object::load_object_from_hash(&hash).unwrap();
-            object.print_object();
-        }
-        Command::HashObject { file_path, write } => {
-            let object = object::Object::create_blob(Path::new(&file_path).to_path_buf()).unwrap();
-            if write {
-                object.save_object();
+            if batch_all_objects {
+                return Err(RevyError::InvalidArgument(
+                    "--batch-all-objects requires --batch or --batch-check".to_string(),
+                ));
             }
-            println!("{}", object.hash);
-        }
-        Command::LsTree { name_only, hash } => {
-            if !name_only {
-                todo!()
+
+            let (kind, hash) = match args.as_slice() {
+                [hash] => (None, hash.clone()),
+                [kind, hash] => (Some(kind.clone()), hash.clone()),
+                _ => {
+                    return Err(RevyError::InvalidArgument(
+                        "cat-file needs an object".to_string(),
+                    ))
+                }
+            };
+
+            if let Some(kind) = kind {
+                let kind = object::ObjectType::from_str(&kind).ok_or_else(|| {
+                    RevyError::InvalidArgument(format!("invalid object type \"{}\"", kind))
+                })?;
+                let mut stdout = std::io::stdout().lock();
+                command::cat_file_raw(&repo, &hash, kind, &mut stdout)?;
+                return stdout.flush().map_err(write_error);
+            }
+
+            if exists {
+                let found = repo
+                    .resolve_revision(&hash)
+                    .map(|hash| repo.has_object(&hash))
+                    .unwrap_or(false);
+                std::process::exit(if found { 0 } else { 1 });
             }
 
-            let object = object::Object::load_object_from_hash(&hash).unwrap();
-            match object.metadata {
-                object::Metadata::Tree(tree) => tree.print_tree(),
-                _ => panic!("Object is not a tree"),
+            let hash = repo.resolve_revision(&hash)?;
+            let mut stream = repo.open_object(&hash)?;
+            if show_type {
+                println!("{}", stream.kind.as_str());
+            } else if show_size {
+                println!("{}", stream.size);
+            } else if !pretty_print {
+                return Err(RevyError::InvalidArgument(
+                    "cat-file needs one of -t, -s, -e, -p or a type".to_string(),
+                ));
+            } else if stream.kind == object::ObjectType::Tree {
+                repo.read_tree(&hash)?.print_pretty_tree()?;
+            } else {
+                // Other objects are shown as stored, so content that isn't UTF-8 is kept.
+                let mut stdout = std::io::stdout().lock();
+                let copied = std::io::copy(&mut stream, &mut stdout)
+                    .map_err(|err| RevyError::io(format!("Failed to read object {}", hash), err))?;
+                stdout.flush().map_err(write_error)?;
+                if copied as usize != stream.size {
+                    return Err(RevyError::corrupt(hash, "object is truncated"));
+                }
+            }
+        }
+        Command::HashObject { file_path, write } => {
+            let repo = open_repository(&location)?;
+            let hash = repo.hash_file(Path::new(&file_path), write)?;
+            println!("{}", hash);
+        }
+        Command::LsTree {
+            hash,
+            paths,
+            name_only,
+            recursive,
+            dirs_only,
+            show_trees,
+            long,
+        } => {
+            let repo = open_repository(&location)?;
+            let options = command::LsTreeOptions {
+                recursive,
+                dirs_only,
+                show_trees,
+                long,
+                name_only,
+                paths,
             };
+            let mut stdout = std::io::stdout().lock();
+            command::ls_tree(&repo, &hash, &options, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
         }
         Command::WriteTree => {
-            let object = object::Object::create_tree(None).unwrap();
-            println!("{}", object.hash);
+            let repo = open_repository(&location)?;
+            println!("{}", repo.write_tree()?);
+        }
+        Command::Commit {
+            message,
+            amend,
+            allow_empty,
+        } => {
+            let repo = open_repository(&location)?;
+            let options = CommitOptions {
+                messages: message,
+                amend,
+                allow_empty,
+            };
+            let commit = repo.commit(&options)?;
+            let branch = match commit.ref_name.as_str() {
+                "HEAD" => "detached HEAD",
+                name => refs::short_name(name),
+            };
+            let root = if commit.is_root { " (root-commit)" } else { "" };
+            println!(
+                "[{}{} {}] {}",
+                branch,
+                root,
+                commit.id.short(),
+                commit.summary
+            );
         }
         Command::CommitTree {
             hash,
             message,
             parent,
         } => {
-            println!("{} {} {:?}", hash, message, parent);
-            todo!();
+            let repo = open_repository(&location)?;
+            let object = command::commit_tree(&repo, &hash, parent, &message)?;
+            println!("{}", object.hash);
+        }
+        Command::Tag {
+            name,
+            object,
+            annotate,
+            message,
+            list,
+            delete,
+            force,
+        } => {
+            let repo = open_repository(&location)?;
+            match name {
+                Some(name) if delete => {
+                    let old_hash = command::delete_tag(&repo, &name)?;
+                    println!("Deleted tag '{}' (was {})", name, old_hash.short());
+                }
+                Some(name) if !list => {
+                    let target = object.unwrap_or_else(|| "HEAD".to_string());
+                    let message = match message {
+                        Some(message) => Some(message),
+                        None if annotate => {
+                            return Err(RevyError::InvalidArgument(
+                                "Annotated tags need a message (-m)".to_string(),
+                            ))
+                        }
+                        None => None,
+                    };
+                    command::create_tag(&repo, &name, &target, message.as_deref(), force)?;
+                }
+                pattern => {
+                    for tag in command::list_tags(&repo, pattern.as_deref())? {
+                        println!("{}", tag);
+                    }
+                }
+            }
+        }
+        Command::Branch {
+            args,
+            delete,
+            force_delete,
+            rename,
+            force_rename,
+            force,
+            verbose,
+            list,
+            track,
+            no_track,
+            set_upstream_to,
+            unset_upstream,
+        } => {
+            let repo = open_repository(&location)?;
+            let mut stdout = std::io::stdout().lock();
+            let branch = args.first().map(String::as_str);
+            let too_many = |what: &str| {
+                Err(RevyError::InvalidArgument(format!(
+                    "too many arguments {}",
+                    what
+                )))
+            };
+            if delete || force_delete {
+                if args.is_empty() {
+                    return Err(RevyError::InvalidArgument(
+                        "branch name required".to_string(),
+                    ));
+                }
+                for name in &args {
+                    let id = repo.delete_branch(name, force || force_delete)?;
+                    println!("Deleted branch {} (was {}).", name, id.short());
+                }
+            } else if rename || force_rename {
+                let force = force || force_rename;
+                match args.as_slice() {
+                    [new] => repo.rename_branch(None, new, force)?,
+                    [old, new] => repo.rename_branch(Some(old), new, force)?,
+                    [] => {
+                        return Err(RevyError::InvalidArgument(
+                            "branch name required".to_string(),
+                        ))
+                    }
+                    _ => return too_many("for a rename"),
+                }
+            } else if let Some(upstream) = set_upstream_to {
+                if args.len() > 1 {
+                    return too_many("to set upstream");
+                }
+                let (name, upstream) = repo.set_upstream(branch, &upstream)?;
+                println!(
+                    "branch '{}' set up to track '{}'.",
+                    name,
+                    refs::short_name(&upstream)
+                );
+            } else if unset_upstream {
+                if args.len() > 1 {
+                    return too_many("to unset upstream");
+                }
+                repo.unset_upstream(branch)?;
+            } else if list || args.is_empty() {
+                command::list_branches(&repo, &args, verbose, &mut stdout)?;
+            } else {
+                let track = match (track, no_track) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                };
+                let (name, start) = match args.as_slice() {
+                    [name] => (name, None),
+                    [name, start] => (name, Some(start.as_str())),
+                    _ => return too_many("to create a branch"),
+                };
+                if let Some(upstream) = repo.create_branch(name, start, force, track)? {
+                    println!(
+                        "branch '{}' set up to track '{}'.",
+                        name,
+                        refs::short_name(&upstream)
+                    );
+                }
+            }
+            stdout.flush().map_err(write_error)?;
+        }
+        Command::Switch {
+            branch,
+            start_point,
+            create,
+            detach,
+            force,
+        } => {
+            let repo = open_repository(&location)?;
+            if start_point.is_some() && !create {
+                return Err(RevyError::InvalidArgument(
+                    "a start point is only taken with -c".to_string(),
+                ));
+            }
+            let options = SwitchOptions {
+                create,
+                start: start_point,
+                detach,
+                force,
+            };
+            let switched = repo.switch_branch(&branch, &options)?;
+            let mut stdout = std::io::stdout().lock();
+            command::report_switch(&repo, &switched, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+        }
+        Command::Checkout {
+            target,
+            new_branch,
+            detach,
+            force,
+        } => {
+            let repo = open_repository(&location)?;
+            let switched = match (new_branch, target) {
+                (Some(name), start) => {
+                    let options = SwitchOptions {
+                        create: true,
+                        start,
+                        detach: false,
+                        force,
+                    };
+                    repo.switch_branch(&name, &options)?
+                }
+                (None, Some(target)) => repo.checkout(&target, detach, force)?,
+                (None, None) => {
+                    return Err(RevyError::InvalidArgument(
+                        "a branch or commit to check out is required".to_string(),
+                    ))
+                }
+            };
+            let mut stdout = std::io::stdout().lock();
+            command::report_switch(&repo, &switched, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+        }
+        Command::Add { pathspec, force } => {
+            let repo = open_repository(&location)?;
+            command::add(&repo, &pathspec, force)?;
+        }
+        Command::Rm {
+            pathspec,
+            cached,
+            recursive,
+            force,
+        } => {
+            let repo = open_repository(&location)?;
+            command::rm(&repo, &pathspec, cached, recursive, force)?;
+        }
+        Command::UpdateRef {
+            delete,
+            no_deref,
+            message,
+            name,
+            new_value,
+            old_value,
+        } => {
+            let repo = open_repository(&location)?;
+            // With -d the only value given is the old one.
+            let (new_value, old_value) = match delete {
+                true if old_value.is_some() => {
+                    return Err(RevyError::InvalidArgument(
+                        "update-ref -d takes at most one value".to_string(),
+                    ))
+                }
+                true => (None, new_value),
+                false => (new_value, old_value),
+            };
+            command::update_ref(
+                &repo,
+                &name,
+                new_value.as_deref(),
+                old_value.as_deref(),
+                delete,
+                !no_deref,
+                &message,
+            )?;
+        }
+        Command::SymbolicRef {
+            quiet,
+            short,
+            delete,
+            name,
+            target,
+        } => {
+            let repo = open_repository(&location)?;
+            if !command::symbolic_ref(&repo, &name, target.as_deref(), short, quiet, delete)? {
+                std::process::exit(1);
+            }
+        }
+        Command::ShowRef {
+            heads,
+            tags,
+            head,
+            hash,
+            dereference,
+            verify,
+            quiet,
+            patterns,
+        } => {
+            let repo = open_repository(&location)?;
+            let options = command::ShowRefOptions {
+                heads,
+                tags,
+                head,
+                hash_only: hash,
+                dereference,
+                verify,
+                quiet,
+                patterns,
+            };
+            let mut stdout = std::io::stdout().lock();
+            let found = command::show_ref(&repo, &options, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        Command::ForEachRef {
+            format,
+            count,
+            patterns,
+        } => {
+            let repo = open_repository(&location)?;
+            let mut stdout = std::io::stdout().lock();
+            command::for_each_ref(&repo, &format, &patterns, count, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+        }
+        Command::Status { short, porcelain } => {
+            let repo = open_repository(&location)?;
+            let format = if porcelain {
+                command::StatusFormat::Porcelain
+            } else if short {
+                command::StatusFormat::Short
+            } else {
+                command::StatusFormat::Long
+            };
+            command::status(&repo, format)?;
+        }
+        Command::RevParse {
+            verify,
+            quiet,
+            short,
+            abbrev_ref,
+            symbolic_full_name,
+            args,
+        } => {
+            let repo = open_repository(&location)?;
+            let options = command::RevParseOptions {
+                verify,
+                quiet,
+                short,
+                abbrev_ref,
+                symbolic_full_name,
+            };
+            let mut stdout = std::io::stdout().lock();
+            let found = command::rev_parse(&repo, &args, &options, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        Command::Log {
+            oneline,
+            format,
+            max_count,
+            since,
+            until,
+            author,
+            grep,
+            graph,
+            revisions,
+            paths,
+        } => {
+            let repo = open_repository(&location)?;
+            let options = command::LogCommandOptions {
+                max_count,
+                since,
+                until,
+                author,
+                grep,
+                oneline,
+                format,
+                graph,
+            };
+            let mut stdout = std::io::stdout().lock();
+            command::log(&repo, &revisions, &paths, &options, &mut stdout)?;
+            stdout.flush().map_err(write_error)?;
+        }
+        Command::Reflog { action, name } => {
+            let repo = open_repository(&location)?;
+            let action = action.unwrap_or(ReflogAction::Show {
+                name: name.unwrap_or_else(|| "HEAD".to_string()),
+            });
+            match action {
+                ReflogAction::Show { name } => {
+                    let mut stdout = std::io::stdout().lock();
+                    command::reflog_show(&repo, &name, &mut stdout)?;
+                    stdout.flush().map_err(write_error)?;
+                }
+                ReflogAction::Expire { expire, all, names } => {
+                    command::reflog_expire(&repo, &names, all, expire.as_deref())?;
+                }
+                ReflogAction::Delete { rewrite, entries } => {
+                    command::reflog_delete(&repo, &entries, rewrite)?;
+                }
+            }
+        }
+        Command::Config { action } => {
+            // Outside of a repository only the system and global files are read.
+            let repo = find_repository(&location)?;
+            let repo = repo.as_ref();
+            let mut stdout = std::io::stdout().lock();
+            let found = match action {
+                ConfigAction::Get {
+                    name,
+                    all,
+                    kind,
+                    show_origin,
+                    scope,
+                } => command::config_get(
+                    repo,
+                    &scope.scope(),
+                    &name,
+                    all,
+                    kind,
+                    show_origin,
+                    &mut stdout,
+                )?,
+                ConfigAction::Set {
+                    name,
+                    value,
+                    add,
+                    scope,
+                } => {
+                    command::config_set(repo, &scope.scope(), &name, &value, add)?;
+                    true
+                }
+                ConfigAction::Unset { name, all, scope } => {
+                    command::config_unset(repo, &scope.scope(), &name, all)?
+                }
+                ConfigAction::List { show_origin, scope } => {
+                    command::config_list(repo, &scope.scope(), show_origin, &mut stdout)?;
+                    true
+                }
+            };
+            stdout.flush().map_err(write_error)?;
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        Command::Fsck {
+            unreachable,
+            no_dangling,
+        } => {
+            let repo = open_repository(&location)?;
+            command::fsck(&repo, unreachable, !no_dangling)?;
         }
     }
     Ok(())
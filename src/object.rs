@@ -4,28 +4,44 @@ use flate2::Compression;
 use std::fs::read_dir;
 use std::fs::File;
 use std::io::Write;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::blob;
+use crate::commit;
+use crate::error::{Result, RevyError};
+use crate::hash::{HashAlgorithm, Hasher};
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::tag;
 use crate::tree;
-use crate::tree::Node;
-use crate::utils;
 
-#[derive(Debug)]
+/// Size of the chunks blob content is streamed in.
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Keeps temporary object file names unique within the process.
+static TEMP_OBJECT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ObjectType {
     Tree,
     Blob,
+    Commit,
+    Tag,
 }
 
 impl ObjectType {
     /// Returns the string representation of the `ObjectType`.
     ///
     /// # Returns
-    /// A string slice that represents the type of the object (`"tree"` or `"blob"`).
+    /// A string slice that represents the type of the object (`"tree"`, `"blob"`, `"commit"` or `"tag"`).
     pub fn as_str(&self) -> &'static str {
         match self {
             ObjectType::Tree => "tree",
             ObjectType::Blob => "blob",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
         }
     }
 
@@ -36,10 +52,13 @@ impl ObjectType {
     ///
     /// # Returns
     /// An `Option` containing the `ObjectType` if the string matches a known type, or `None` otherwise.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(mode: &str) -> Option<ObjectType> {
         match mode {
             "tree" => Some(ObjectType::Tree),
             "blob" => Some(ObjectType::Blob),
+            "commit" => Some(ObjectType::Commit),
+            "tag" => Some(ObjectType::Tag),
             _ => None,
         }
     }
@@ -49,33 +68,142 @@ impl ObjectType {
 pub enum Metadata {
     Tree(tree::Tree),
     Blob(blob::Blob),
+    Commit(commit::Commit),
+    Tag(tag::Tag),
+}
+
+/// A loose object opened for streaming; reading yields its decompressed content.
+pub struct ObjectReader {
+    pub kind: ObjectType,
+    pub size: usize,
+    reader: BufReader<ZlibDecoder<File>>,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
 }
 
 #[derive(Debug)]
 pub struct Object {
     pub kind: ObjectType,
-    pub hash: String,
+    pub hash: ObjectId,
     pub size: usize,
     pub metadata: Metadata,
 }
 
-#[allow(dead_code)]
 impl Object {
-    fn load_file_from_hash(hash: &str) -> Result<File, String> {
-        let current_repo_directory = utils::fetch_path_for_repository(None);
-        let current_working_directory = format!(
-            "{}/objects/{}/{}",
-            current_repo_directory,
-            &hash[..2],
-            &hash[2..]
-        );
-
-        let object_file = match File::open(&current_working_directory) {
+    /// Returns the path of the loose object file for `hash`.
+    fn object_path(repo: &Repository, hash: &ObjectId) -> PathBuf {
+        let hex = hash.to_hex();
+        repo.objects_dir().join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// Resolves a full or abbreviated hex object id by scanning the loose objects in
+    /// `objects/<first two characters>/`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `prefix` is not hex, is shorter than `MIN_ABBREV_LEN`, matches no
+    /// object, or matches several; the last error lists the candidates.
+    pub(crate) fn resolve_prefix(repo: &Repository, prefix: &str) -> Result<ObjectId> {
+        let prefix = prefix.to_lowercase();
+        let algorithm = repo.algorithm();
+        if !ObjectId::is_hex_prefix(&prefix) || prefix.len() > algorithm.hex_len() {
+            return Err(RevyError::InvalidArgument(format!(
+                "Not a valid object name: '{}'",
+                prefix
+            )));
+        }
+        if prefix.len() == algorithm.hex_len() {
+            return ObjectId::from_hex(&prefix);
+        }
+
+        let fanout_directory = repo.objects_dir().join(&prefix[..2]);
+        let mut candidates: Vec<ObjectId> = match read_dir(fanout_directory) {
+            Ok(entries) => entries
+                .filter_map(std::result::Result::ok)
+                .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+                .filter(|name| name.starts_with(&prefix[2..]))
+                .filter_map(|name| ObjectId::from_hex(&format!("{}{}", &prefix[..2], name)).ok())
+                .collect(),
+            Err(_err) => Vec::new(),
+        };
+        candidates.sort();
+
+        match candidates.len() {
+            0 => Err(RevyError::MissingObject(prefix)),
+            1 => Ok(candidates[0]),
+            _ => Err(RevyError::AmbiguousObject {
+                prefix,
+                candidates: candidates
+                    .iter()
+                    .map(|candidate| {
+                        let kind = match Object::read_header(repo, candidate) {
+                            Ok((kind, _size, _reader)) => kind.as_str(),
+                            Err(_err) => "bad object",
+                        };
+                        format!("{} {}", candidate, kind)
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Lists the ids of every loose object in the repository, sorted.
+    pub(crate) fn list_loose_objects(repo: &Repository) -> Result<Vec<ObjectId>> {
+        let objects_directory = repo.objects_dir();
+        let fanout_directories = read_dir(&objects_directory).map_err(|err| {
+            RevyError::io(
+                format!("Failed to read {}", objects_directory.display()),
+                err,
+            )
+        })?;
+
+        let mut hashes = Vec::new();
+        for fanout in fanout_directories.filter_map(std::result::Result::ok) {
+            let prefix = fanout.file_name().to_string_lossy().to_string();
+            if prefix.len() != 2 || !fanout.path().is_dir() {
+                continue;
+            }
+            let entries = read_dir(fanout.path()).map_err(|err| {
+                RevyError::io(format!("Failed to read {}", fanout.path().display()), err)
+            })?;
+            hashes.extend(
+                entries
+                    .filter_map(std::result::Result::ok)
+                    .filter_map(|entry| {
+                        ObjectId::from_hex(&format!(
+                            "{}{}",
+                            prefix,
+                            entry.file_name().to_string_lossy()
+                        ))
+                        .ok()
+                    }),
+            );
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Check if the loose object `hash` exists and has a readable header.
+    pub(crate) fn exists(repo: &Repository, hash: &ObjectId) -> bool {
+        Object::read_header(repo, hash).is_ok()
+    }
+
+    fn load_file_from_hash(repo: &Repository, hash: &ObjectId) -> Result<File> {
+        let object_path = Object::object_path(repo, hash);
+
+        let object_file = match File::open(&object_path) {
             Ok(file) => file,
-            Err(_err) => {
-                return Err(format!(
-                    "Failed to open object file: {}",
-                    &current_working_directory
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(RevyError::MissingObject(hash.to_string()));
+            }
+            Err(err) => {
+                return Err(RevyError::io(
+                    format!("Failed to open object file {}", object_path.display()),
+                    err,
                 ));
             }
         };
@@ -83,68 +211,119 @@ impl Object {
         Ok(object_file)
     }
 
-    pub fn new(kind: ObjectType, metadata: Metadata) -> Result<Object, ()> {
-        let content = match &metadata {
-            Metadata::Tree(tree) => tree.as_str().to_string(),
-            Metadata::Blob(blob) => blob.as_str().to_string(),
-        };
-
-        let size = match &metadata {
-            Metadata::Tree(tree) => tree.as_bytes().len(),
-            Metadata::Blob(blob) => blob.as_bytes().len(),
-        };
+    /// Creates an `Object` from `metadata`, naming it with `algorithm`. Nothing is stored;
+    /// `Repository::write_object` does that.
+    pub fn new(algorithm: HashAlgorithm, kind: ObjectType, metadata: Metadata) -> Result<Object> {
+        let content = Object::content_bytes(&metadata);
+        let hash = algorithm.digest(&Object::with_header(kind, content.len(), &content));
 
         let object = Object {
             kind,
-            hash: utils::generate_sha1(&content),
-            size: size as usize,
+            hash,
+            size: content.len(),
             metadata,
         };
 
         Ok(object)
     }
 
-    pub fn save_object(&self) {
-        let mut current_repo_directory = std::path::PathBuf::from(".");
-        current_repo_directory.push(format!(
-            ".revy/objects/{}/{}",
-            &self.hash[..2],
-            &self.hash[2..]
-        ));
-
-        if let Some(parent) = current_repo_directory.parent() {
-            if let Err(err) = std::fs::create_dir_all(parent) {
-                println!("Failed to create directories: {:?}", err);
-                return;
-            }
-        }
+    /// Returns the object exactly as git stores it before compression: the
+    /// `"<type> <size>\0"` header followed by the raw content. The object id is the
+    /// hash of these bytes.
+    fn serialize(&self) -> Vec<u8> {
+        Object::with_header(self.kind, self.size, &self.metadata_as_bytes())
+    }
 
-        let object_file = match File::create(&current_repo_directory) {
-            Ok(file) => file,
-            Err(err) => {
-                println!("Failed to create object file: {:?}", current_repo_directory);
-                return;
-            }
-        };
+    fn with_header(kind: ObjectType, size: usize, content: &[u8]) -> Vec<u8> {
+        let mut data = format!("{} {}\0", kind.as_str(), size).into_bytes();
+        data.extend(content);
+        data
+    }
 
-        let mut file_data = Vec::new();
+    /// Stores the object in the object database, compressed. Like a streamed blob it is
+    /// written to a temporary file first and renamed into place, so a crash or a concurrent
+    /// writer never leaves a truncated object behind.
+    pub(crate) fn save_object(&self, repo: &Repository) -> Result<()> {
+        Object::save_serialized(repo, &self.hash, &self.serialize())
+    }
 
-        let mut encoder = ZlibEncoder::new(object_file, Compression::default());
-        let header = format!("{} {}\0", self.kind.as_str(), self.size);
+    /// Stores `content` as an object of type `kind` exactly as given, for content that
+    /// `Metadata` can't represent, like a commit message that isn't UTF-8, and returns its id.
+    pub(crate) fn save_raw(
+        repo: &Repository,
+        kind: ObjectType,
+        content: &[u8],
+    ) -> Result<ObjectId> {
+        let data = Object::with_header(kind, content.len(), content);
+        let hash = repo.algorithm().digest(&data);
+        Object::save_serialized(repo, &hash, &data)?;
+        Ok(hash)
+    }
 
-        file_data.extend(header.as_bytes());
-        file_data.extend(self.metadata_as_bytes());
+    fn save_serialized(repo: &Repository, hash: &ObjectId, data: &[u8]) -> Result<()> {
+        let temp_path = Object::temp_object_path(repo);
+        let written = File::create(&temp_path)
+            .and_then(|temp_file| {
+                let mut encoder =
+                    ZlibEncoder::new(BufWriter::new(temp_file), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?.flush()
+            })
+            .map_err(|err| {
+                RevyError::io(
+                    format!("Failed to write object file {}", temp_path.display()),
+                    err,
+                )
+            });
+        if let Err(err) = written {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        Object::move_into_place(repo, &temp_path, hash)
+    }
 
-        encoder.write_all(&file_data).unwrap();
+    /// Returns a path in the object directory for a new object to be written to before its
+    /// id is known or while it is incomplete.
+    fn temp_object_path(repo: &Repository) -> PathBuf {
+        repo.objects_dir().join(format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            TEMP_OBJECT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
     }
 
-    pub fn load_object_from_hash(hash: &str) -> Result<Object, String> {
-        let object_file = match Object::load_file_from_hash(hash) {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+    /// Renames the complete object file at `temp_path` to the loose object path of `hash`,
+    /// or drops it if the object is already stored.
+    fn move_into_place(repo: &Repository, temp_path: &Path, hash: &ObjectId) -> Result<()> {
+        let object_path = Object::object_path(repo, hash);
+        if object_path.exists() {
+            let _ = std::fs::remove_file(temp_path);
+            return Ok(());
+        }
+        if let Some(parent) = object_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| {
+                RevyError::io(
+                    format!("Failed to create directory {}", parent.display()),
+                    err,
+                )
+            })?;
+        }
+        std::fs::rename(temp_path, &object_path).map_err(|err| {
+            let _ = std::fs::remove_file(temp_path);
+            RevyError::io(
+                format!("Failed to move object into {}", object_path.display()),
+                err,
+            )
+        })
+    }
+
+    /// Opens the loose object `hash` and reads its `"<type> <size>\0"` header, returning the
+    /// type, the content size and a reader positioned at the start of the content.
+    fn read_header(
+        repo: &Repository,
+        hash: &ObjectId,
+    ) -> Result<(ObjectType, usize, BufReader<ZlibDecoder<File>>)> {
+        let object_file = Object::load_file_from_hash(repo, hash)?;
 
         let decoder = ZlibDecoder::new(object_file);
         let mut decoded_reader = BufReader::new(decoder);
@@ -153,49 +332,193 @@ impl Object {
         match decoded_reader.read_until(0, &mut header_contents) {
             Ok(_size) => {}
             Err(_err) => {
-                return Err("Failed to read object file, invalid header".to_string());
+                return Err(RevyError::corrupt(hash, "failed to decompress the header"));
             }
         }
 
-        let header_parts: Result<Vec<&str>, String> =
-            match std::ffi::CStr::from_bytes_until_nul(&header_contents) {
-                Ok(header) => {
-                    let header_str = header.to_str().unwrap();
-                    let header_parts: Vec<&str> = header_str.split_whitespace().collect();
-                    Ok(header_parts)
-                }
+        let header_str = match std::ffi::CStr::from_bytes_until_nul(&header_contents) {
+            Ok(header) => match header.to_str() {
+                Ok(header_str) => header_str.to_string(),
                 Err(_err) => {
-                    return Err("Failed to read object file, invalid header".to_string());
+                    return Err(RevyError::corrupt(hash, "invalid header"));
                 }
-            };
-
-        let header = match header_parts {
-            Ok(parts) => parts,
-            Err(err) => {
-                return Err(err);
+            },
+            Err(_err) => {
+                return Err(RevyError::corrupt(hash, "invalid header"));
             }
         };
 
+        let header: Vec<&str> = header_str.split_whitespace().collect();
+        if header.len() != 2 {
+            return Err(RevyError::corrupt(hash, "malformed header"));
+        }
+
         let kind = match ObjectType::from_str(header[0]) {
             Some(object_type) => object_type,
             None => {
-                return Err(format!("Malformed object file size in {}", &hash));
+                return Err(RevyError::corrupt(
+                    hash,
+                    format!("unknown object type '{}'", header[0]),
+                ));
             }
         };
 
         let size = match header[1].parse::<usize>() {
             Ok(size) => size,
             Err(_err) => {
-                return Err(format!("Malformed object file size in {}", &hash));
+                return Err(RevyError::corrupt(hash, "malformed object size"));
             }
         };
 
+        Ok((kind, size, decoded_reader))
+    }
+
+    /// Opens the loose object `hash` for streaming, without reading its content into memory.
+    pub(crate) fn open_object_stream(repo: &Repository, hash: &ObjectId) -> Result<ObjectReader> {
+        let (kind, size, reader) = Object::read_header(repo, hash)?;
+        Ok(ObjectReader { kind, size, reader })
+    }
+
+    /// Hashes a blob of `size` bytes read from `reader` and, when `write` is set, stores it in
+    /// the object database.
+    ///
+    /// The content is hashed and zlib-compressed in a single pass, so memory use does not
+    /// depend on the size of the blob. The compressed data goes to a temporary file that is
+    /// renamed into place once the id is known.
+    pub(crate) fn stream_blob<R: Read>(
+        repo: &Repository,
+        mut reader: R,
+        size: usize,
+        write: bool,
+    ) -> Result<ObjectId> {
+        let header = format!("{} {}\0", ObjectType::Blob.as_str(), size);
+        let mut hasher = repo.algorithm().hasher();
+        hasher.update(header.as_bytes());
+
+        let temp_path = Object::temp_object_path(repo);
+        let mut encoder = if write {
+            let temp_file = File::create(&temp_path).map_err(|err| {
+                RevyError::io(
+                    format!("Failed to create object file {}", temp_path.display()),
+                    err,
+                )
+            })?;
+            let mut encoder = ZlibEncoder::new(BufWriter::new(temp_file), Compression::default());
+            encoder
+                .write_all(header.as_bytes())
+                .map_err(|err| RevyError::io("Failed to write object header", err))?;
+            Some(encoder)
+        } else {
+            None
+        };
+
+        let result = Object::stream_content(&mut reader, &mut hasher, encoder.as_mut());
+        let written = match result {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(err);
+            }
+        };
+        if written != size {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(RevyError::InvalidArgument(format!(
+                "Expected {} bytes of blob content but read {}; did the file change?",
+                size, written
+            )));
+        }
+
+        let hash = hasher.finalize();
+
+        if let Some(encoder) = encoder {
+            let finished = encoder
+                .finish()
+                .and_then(|mut writer| writer.flush())
+                .map_err(|err| RevyError::io("Failed to write object file", err));
+            if let Err(err) = finished {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(err);
+            }
+
+            Object::move_into_place(repo, &temp_path, &hash)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Copies `reader` into the hasher and the optional encoder, returning the byte count.
+    fn stream_content<R: Read, W: Write>(
+        reader: &mut R,
+        hasher: &mut Hasher,
+        mut encoder: Option<&mut W>,
+    ) -> Result<usize> {
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut written = 0;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(RevyError::io("Failed to read blob content", err)),
+            };
+            hasher.update(&buffer[..read]);
+            if let Some(encoder) = encoder.as_mut() {
+                encoder
+                    .write_all(&buffer[..read])
+                    .map_err(|err| RevyError::io("Failed to write object file", err))?;
+            }
+            written += read;
+        }
+        Ok(written)
+    }
+
+    /// Hashes the file at `path` as a blob, storing it when `write` is set, by streaming
+    /// its content from disk.
+    pub(crate) fn stream_blob_from_file(
+        repo: &Repository,
+        path: &Path,
+        write: bool,
+    ) -> Result<ObjectId> {
+        let file = File::open(path).map_err(|err| {
+            RevyError::io(
+                format!("could not open '{}' for reading", path.display()),
+                err,
+            )
+        })?;
+        let size = file
+            .metadata()
+            .map_err(|err| RevyError::io(format!("could not stat '{}'", path.display()), err))?
+            .len() as usize;
+        Object::stream_blob(repo, BufReader::new(file), size, write)
+    }
+
+    /// Loads the tree named by `hash`, peeling commits to their tree and tags to their target.
+    pub(crate) fn load_tree_from_hash(repo: &Repository, hash: &ObjectId) -> Result<tree::Tree> {
+        let mut current = *hash;
+        loop {
+            match Object::load_object_from_hash(repo, &current)?.metadata {
+                Metadata::Tree(tree) => return Ok(tree),
+                Metadata::Commit(commit) => current = commit.tree,
+                Metadata::Tag(tag) => current = tag.object,
+                Metadata::Blob(_blob) => {
+                    return Err(RevyError::InvalidArgument(format!(
+                        "{} is not a tree object",
+                        hash
+                    )))
+                }
+            }
+        }
+    }
+
+    pub(crate) fn load_object_from_hash(repo: &Repository, hash: &ObjectId) -> Result<Object> {
+        let (kind, size, decoded_reader) = Object::read_header(repo, hash)?;
+
         let metadata = match kind {
             ObjectType::Tree => {
-                let tree = match tree::Tree::parse_tree(decoded_reader) {
+                let tree = match tree::Tree::parse_tree(decoded_reader, repo.algorithm()) {
                     Ok(tree) => tree,
                     Err(err) => {
-                        return Err(err);
+                        return Err(err.with_object_id(hash));
                     }
                 };
                 Metadata::Tree(tree)
@@ -204,108 +527,55 @@ impl Object {
                 let blob = match blob::Blob::from_file(decoded_reader) {
                     Ok(blob) => blob,
                     Err(err) => {
-                        return Err(err);
+                        return Err(err.with_object_id(hash));
                     }
                 };
                 Metadata::Blob(blob)
             }
+            ObjectType::Commit => {
+                let commit = match commit::Commit::parse_commit(decoded_reader) {
+                    Ok(commit) => commit,
+                    Err(err) => {
+                        return Err(err.with_object_id(hash));
+                    }
+                };
+                Metadata::Commit(commit)
+            }
+            ObjectType::Tag => {
+                let tag = match tag::Tag::parse_tag(decoded_reader) {
+                    Ok(tag) => tag,
+                    Err(err) => {
+                        return Err(err.with_object_id(hash));
+                    }
+                };
+                Metadata::Tag(tag)
+            }
         };
 
         Ok(Object {
             kind,
-            hash: hash.to_string(),
+            hash: *hash,
             size,
             metadata,
         })
     }
 
-    pub fn create_tree(path: Option<std::path::PathBuf>) -> Result<Object, ()> {
-        let mut tree_nodes: Vec<Node> = Vec::new();
-        let tree_curr_path = match path {
-            Some(path) => path,
-            None => std::path::Path::new(".").to_path_buf(),
-        };
-        let all_paths = read_dir(tree_curr_path).unwrap();
-
-        let excluded_paths = utils::fetch_excluded_paths();
-        let entries = all_paths.filter_map(Result::ok).filter(|entry| {
-            let path = entry.path();
-            !utils::should_ignore(&path, &excluded_paths)
-        });
-
-        for entry in entries {
-            let curr_path = &entry.path();
-            let curr_path_file = curr_path.file_name().unwrap().to_str().unwrap();
-            if curr_path.is_dir() {
-                let sub_tree_object = Object::create_tree(Some(curr_path.to_path_buf())).unwrap();
-                tree_nodes.push(Node::new(
-                    tree::FileMode::Directory,
-                    curr_path_file.to_string(),
-                    sub_tree_object.hash,
-                ));
-            } else {
-                let blob_object = Object::create_blob(curr_path.to_path_buf()).unwrap();
-                blob_object.save_object();
-                tree_nodes.push(Node::new(
-                    tree::FileMode::RegularFile,
-                    curr_path_file.to_string(),
-                    blob_object.hash,
-                ));
-            }
-        }
-
-        let tree = tree::Tree::new(tree_nodes);
-        let object = Object::new(ObjectType::Tree, Metadata::Tree(tree)).unwrap();
-        object.save_object();
-
-        Ok(object)
-    }
-
-    pub fn create_blob(path: std::path::PathBuf) -> Result<Object, String> {
-        let blob_contents = match std::fs::read(&path) {
-            Ok(contents) => contents,
-            Err(_err) => {
-                return Err("Failed to read file".to_string());
-            }
-        };
-
-        let blob_contents_str = match String::from_utf8(blob_contents) {
-            Ok(contents) => contents,
-            Err(_err) => {
-                return Err("Failed to convert file contents to string".to_string());
-            }
-        };
-
-        let blob = blob::Blob::new(blob_contents_str);
-        let object = Object::new(ObjectType::Blob, Metadata::Blob(blob)).unwrap();
-
-        Ok(object)
-    }
-
-    pub fn print_object(&self) {
-        match &self.metadata {
-            Metadata::Tree(tree) => {
-                tree.print_tree();
-            }
-            Metadata::Blob(blob) => {
-                blob.print_blob();
-            }
-        }
+    /// Stores the file at `path` as a blob and returns its id, streaming the content so the
+    /// file is never read into memory whole.
+    pub fn create_blob(repo: &Repository, path: &Path) -> Result<ObjectId> {
+        Object::stream_blob_from_file(repo, path, true)
     }
 
-    fn metadata_as_str(&self) -> String {
-        let metadata = match &self.metadata {
-            Metadata::Tree(tree) => tree.as_str().to_string(),
-            Metadata::Blob(blob) => blob.as_str().to_string(),
-        };
-        metadata
+    fn metadata_as_bytes(&self) -> Vec<u8> {
+        Object::content_bytes(&self.metadata)
     }
 
-    fn metadata_as_bytes(&self) -> Vec<u8> {
-        let metadata = match &self.metadata {
+    fn content_bytes(metadata: &Metadata) -> Vec<u8> {
+        match metadata {
             Metadata::Tree(tree) => tree.as_bytes(),
-            Metadata::Blob(blob) => blob.as_bytes(),
-        };
-        metadata
+            Metadata::Blob(blob) => blob.as_bytes().to_vec(),
+            Metadata::Commit(commit) => commit.as_bytes(),
+            Metadata::Tag(tag) => tag.as_bytes(),
+        }
     }
 }
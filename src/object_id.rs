@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::error::{Result, RevyError};
+use crate::hash::HashAlgorithm;
+
+/// The shortest prefix accepted when resolving an abbreviated object id.
+pub const MIN_ABBREV_LEN: usize = 4;
+
+/// The length object ids are abbreviated to for display.
+pub const DEFAULT_ABBREV_LEN: usize = 7;
+
+/// The name of an object: the raw digest of its serialized form.
+///
+/// Stores the raw bytes rather than hex, and remembers which algorithm produced them so
+/// SHA-1 and SHA-256 ids never compare equal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId {
+    algorithm: HashAlgorithm,
+    bytes: [u8; 32],
+}
+
+impl ObjectId {
+    /// Creates an `ObjectId` from a raw digest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `raw` is not exactly `algorithm.raw_len()` bytes long.
+    pub fn from_bytes(algorithm: HashAlgorithm, raw: &[u8]) -> Result<ObjectId> {
+        if raw.len() != algorithm.raw_len() {
+            return Err(RevyError::InvalidArgument(format!(
+                "Invalid {} object id length: {} bytes",
+                algorithm.as_str(),
+                raw.len()
+            )));
+        }
+        let mut bytes = [0; 32];
+        bytes[..raw.len()].copy_from_slice(raw);
+        Ok(ObjectId { algorithm, bytes })
+    }
+
+    /// Parses a full hex object id. The algorithm is inferred from the length.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `hex` is not 40 or 64 hex characters.
+    pub fn from_hex(hex: &str) -> Result<ObjectId> {
+        let invalid = || RevyError::InvalidArgument(format!("Not a valid object id: '{}'", hex));
+        let algorithm = [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .into_iter()
+            .find(|algorithm| algorithm.hex_len() == hex.len())
+            .ok_or_else(invalid)?;
+        let raw = hex::decode(hex).map_err(|_err| invalid())?;
+        ObjectId::from_bytes(algorithm, &raw)
+    }
+
+    /// Returns the algorithm that produced this id.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the raw digest bytes, as stored in tree entries.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.algorithm.raw_len()]
+    }
+
+    /// Returns the full lowercase hex form of the id.
+    pub fn to_hex(self) -> String {
+        hex::encode(self.as_bytes())
+    }
+
+    /// Returns the id abbreviated to `DEFAULT_ABBREV_LEN` hex characters.
+    pub fn short(&self) -> String {
+        self.to_hex()[..DEFAULT_ABBREV_LEN].to_string()
+    }
+
+    /// Check if `value` could be an abbreviated or full object id: only hex characters and
+    /// at least `MIN_ABBREV_LEN` of them.
+    pub fn is_hex_prefix(value: &str) -> bool {
+        value.len() >= MIN_ABBREV_LEN
+            && value.len() <= HashAlgorithm::Sha256.hex_len()
+            && value.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
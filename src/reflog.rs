@@ -0,0 +1,349 @@
+//! Reflogs: the values a ref held over time, so a ref moved by mistake can be recovered.
+//!
+//! The log of a ref lives in `.revy/logs/<ref>`, one line per update, oldest first:
+//! `<old id> <new id> <committer> <epoch seconds> <+hhmm>\t<message>`. The old id is all
+//! zeros when the update created the ref.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::commit::Signature;
+use crate::error::{Result, RevyError};
+use crate::lockfile::LockFile;
+use crate::object_id::ObjectId;
+use crate::refs;
+use crate::repository::Repository;
+use crate::utils;
+
+const LOGS_DIR: &str = "logs";
+
+/// A single line of a reflog.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    /// What the ref held before the update, `None` if the update created it.
+    pub old: Option<ObjectId>,
+    pub new: ObjectId,
+    /// Who made the update, and when.
+    pub committer: Signature,
+    /// Why the ref moved, e.g. `commit: Fix the frobnicator`.
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Result<ReflogEntry> {
+        let malformed = || RevyError::malformed(format!("malformed reflog line: {}", line));
+        let (old, rest) = line.split_once(' ').ok_or_else(malformed)?;
+        let (new, rest) = rest.split_once(' ').ok_or_else(malformed)?;
+        let (committer, message) = rest.split_once('\t').unwrap_or((rest, ""));
+
+        let old = match old.chars().all(|c| c == '0') {
+            true => None,
+            false => Some(ObjectId::from_hex(old).map_err(|_err| malformed())?),
+        };
+        Ok(ReflogEntry {
+            old,
+            new: ObjectId::from_hex(new).map_err(|_err| malformed())?,
+            committer: Signature::parse(committer)?,
+            message: message.to_string(),
+        })
+    }
+
+    fn format(&self) -> String {
+        let old = match &self.old {
+            Some(old) => old.to_hex(),
+            None => "0".repeat(self.new.to_hex().len()),
+        };
+        format!(
+            "{} {} {}\t{}\n",
+            old,
+            self.new,
+            self.committer.as_str(),
+            self.message
+        )
+    }
+}
+
+fn log_path(repo: &Repository, name: &str) -> PathBuf {
+    repo.revy_dir().join(LOGS_DIR).join(name)
+}
+
+/// Check if the ref `name` (e.g. `refs/heads/main`) has a reflog.
+pub fn has_reflog(repo: &Repository, name: &str) -> bool {
+    log_path(repo, name).is_file()
+}
+
+/// Lists the refs that have a reflog, `HEAD` first and the rest sorted by name.
+pub fn list_reflogs(repo: &Repository) -> Vec<String> {
+    let mut names = Vec::new();
+    if has_reflog(repo, "HEAD") {
+        names.push("HEAD".to_string());
+    }
+    let mut logged = Vec::new();
+    collect_logs(&log_path(repo, "refs"), "refs", &mut logged);
+    logged.sort();
+    names.extend(logged);
+    names
+}
+
+fn collect_logs(dir: &Path, name: &str, names: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_err) => return,
+    };
+    for entry in entries.filter_map(std::result::Result::ok) {
+        let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            collect_logs(&entry.path(), &entry_name, names);
+        } else if !entry_name.ends_with(".lock") {
+            names.push(entry_name);
+        }
+    }
+}
+
+/// Check if updates of `name` should be logged, following `core.logAllRefUpdates`: by
+/// default `HEAD`, branches, remote-tracking branches and notes are, `always` logs every
+/// ref and `false` none. A ref that already has a log always keeps it up to date.
+fn should_log(repo: &Repository, name: &str) -> bool {
+    if has_reflog(repo, name) {
+        return true;
+    }
+    match repo.config().get("core.logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        Some(value) if crate::config::parse_bool(Some(value)) == Some(false) => false,
+        _ => {
+            name == "HEAD"
+                || [refs::HEADS_PREFIX, refs::REMOTES_PREFIX, "refs/notes/"]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        }
+    }
+}
+
+/// Records that `name` moved from `old` to `new`, if updates of it are logged.
+///
+/// Newlines in `message` are turned into spaces, so an entry stays on one line.
+///
+/// # Errors
+///
+/// Returns an error if the committer identity is invalid or the log can't be written.
+pub(crate) fn append(
+    repo: &Repository,
+    name: &str,
+    old: Option<&ObjectId>,
+    new: &ObjectId,
+    message: &str,
+) -> Result<()> {
+    if !should_log(repo, name) {
+        return Ok(());
+    }
+    let entry = ReflogEntry {
+        old: old.copied(),
+        new: *new,
+        committer: Signature::for_repository(repo, "COMMITTER")?,
+        message: message.trim().replace('\n', " "),
+    };
+
+    let path = log_path(repo, name);
+    let write_error = |err| RevyError::io(format!("Unable to append to {}", path.display()), err);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(entry.format().as_bytes()))
+        .map_err(write_error)
+}
+
+/// Reads the reflog of `name`, oldest entry first. A ref without a log has no entries.
+///
+/// # Errors
+///
+/// Returns a `CorruptObject` error if a line can't be parsed, or an I/O error if the log
+/// exists but can't be read.
+pub fn read_reflog(repo: &Repository, name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = log_path(repo, name);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(RevyError::io(
+                format!("Unable to read {}", path.display()),
+                err,
+            ))
+        }
+    };
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Replaces the reflog of `name` with `entries`, under a lock.
+fn write_reflog(repo: &Repository, name: &str, entries: &[ReflogEntry]) -> Result<()> {
+    let mut lock = LockFile::acquire(&log_path(repo, name))?;
+    let contents: String = entries.iter().map(ReflogEntry::format).collect();
+    lock.write_all(contents.as_bytes())?;
+    lock.commit()
+}
+
+/// Drops the entries of the reflog of `name` made at or before `before`, in seconds since
+/// the epoch, and returns how many were dropped.
+///
+/// # Errors
+///
+/// Returns an error if the log can't be read or rewritten.
+pub fn expire_reflog(repo: &Repository, name: &str, before: i64) -> Result<usize> {
+    let entries = read_reflog(repo, name)?;
+    let kept: Vec<ReflogEntry> = entries
+        .iter()
+        .filter(|entry| entry.committer.timestamp > before)
+        .cloned()
+        .collect();
+    let expired = entries.len() - kept.len();
+    if expired > 0 {
+        write_reflog(repo, name, &kept)?;
+    }
+    Ok(expired)
+}
+
+/// Deletes entry `index` of the reflog of `name`, counting from the newest as `0` the way
+/// `<ref>@{<index>}` does. With `rewrite` the next newer entry's old id is set to the
+/// deleted entry's, so the log still reads as an unbroken chain.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the log has no such entry.
+pub fn delete_reflog_entry(
+    repo: &Repository,
+    name: &str,
+    index: usize,
+    rewrite: bool,
+) -> Result<()> {
+    let mut entries = read_reflog(repo, name)?;
+    if index >= entries.len() {
+        return Err(RevyError::BadRef(format!(
+            "reflog of '{}' has no entry {}",
+            name, index
+        )));
+    }
+    let position = entries.len() - 1 - index;
+    let deleted = entries.remove(position);
+    if rewrite {
+        if let Some(newer) = entries.get_mut(position) {
+            newer.old = deleted.old;
+        }
+    }
+    write_reflog(repo, name, &entries)
+}
+
+/// Removes the reflog of `name`, pruning directories it leaves empty. Used when the ref
+/// itself is deleted.
+pub(crate) fn remove_reflog(repo: &Repository, name: &str) -> Result<()> {
+    let path = log_path(repo, name);
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(RevyError::io(
+                format!("Unable to remove {}", path.display()),
+                err,
+            ))
+        }
+    }
+    prune_log_dirs(repo, &path);
+    Ok(())
+}
+
+/// Moves the reflog of `old` to `new`, for a ref being renamed.
+///
+/// # Errors
+///
+/// Returns an I/O error if the log can't be moved.
+pub(crate) fn rename_reflog(repo: &Repository, old: &str, new: &str) -> Result<()> {
+    let (from, to) = (log_path(repo, old), log_path(repo, new));
+    if !from.is_file() {
+        return Ok(());
+    }
+    let move_error = |err| {
+        RevyError::io(
+            format!("Unable to move {} to {}", from.display(), to.display()),
+            err,
+        )
+    };
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(move_error)?;
+    }
+    fs::rename(&from, &to).map_err(move_error)?;
+    prune_log_dirs(repo, &from);
+    Ok(())
+}
+
+/// Removes the directories above the log at `path` that are left empty.
+fn prune_log_dirs(repo: &Repository, path: &Path) {
+    let logs_root = repo.revy_dir().join(LOGS_DIR);
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == logs_root || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+/// Splits a revision like `main@{2}` into the ref part and what is between the braces.
+pub fn split_reflog_suffix(revision: &str) -> Option<(&str, &str)> {
+    let inner = revision.strip_suffix('}')?;
+    let start = inner.rfind("@{")?;
+    Some((&inner[..start], &inner[start + 2..]))
+}
+
+/// Resolves `<ref>@{<spec>}` through the reflog of `name`, a full ref name. `spec` is
+/// either `n`, the value `n` updates ago, or a date, the value the ref had then.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the log doesn't go back far enough, and an
+/// `InvalidArgument` error if `spec` is neither a number nor a date.
+pub(crate) fn resolve_at(repo: &Repository, name: &str, spec: &str) -> Result<ObjectId> {
+    let entries = read_reflog(repo, name)?;
+    let display = refs::short_name(name);
+
+    if let Ok(count) = spec.parse::<usize>() {
+        if count < entries.len() {
+            return Ok(entries[entries.len() - 1 - count].new);
+        }
+        // One step past the oldest entry is what the ref held before it was logged.
+        return match entries.first() {
+            Some(ReflogEntry { old: Some(old), .. }) if count == entries.len() => Ok(*old),
+            _ => Err(RevyError::BadRef(format!(
+                "log for '{}' only has {} entries",
+                display,
+                entries.len()
+            ))),
+        };
+    }
+
+    let date = utils::parse_date(spec, utils::now()).ok_or_else(|| {
+        RevyError::InvalidArgument(format!("invalid date in '{}@{{{}}}'", display, spec))
+    })?;
+    if let Some(entry) = entries
+        .iter()
+        .rev()
+        .find(|entry| entry.committer.timestamp <= date)
+    {
+        return Ok(entry.new);
+    }
+    match entries.first() {
+        Some(ReflogEntry { old: Some(old), .. }) => Ok(*old),
+        Some(oldest) => Err(RevyError::BadRef(format!(
+            "log for '{}' only goes back to {}",
+            display,
+            utils::format_date(oldest.committer.timestamp, oldest.committer.tz_offset)
+        ))),
+        None => Err(RevyError::BadRef(format!("no reflog for '{}'", display))),
+    }
+}
@@ -0,0 +1,474 @@
+//! Refs: names for objects, like branches under `refs/heads/` and tags under `refs/tags/`.
+//!
+//! A ref is either a loose file under `.revy` holding an object id, or a line in
+//! `.revy/packed-refs`; a loose file takes precedence over a packed entry of the same name.
+//! A symbolic ref, such as `HEAD`, holds `ref: <name>` instead of an id and is always loose.
+//! Updates go through a lock file and can be made conditional on the value they replace.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RevyError};
+use crate::lockfile::LockFile;
+use crate::object::Object;
+use crate::object_id::ObjectId;
+use crate::reflog;
+use crate::repository::Repository;
+
+pub const HEADS_PREFIX: &str = "refs/heads/";
+pub const TAGS_PREFIX: &str = "refs/tags/";
+pub const REMOTES_PREFIX: &str = "refs/remotes/";
+
+/// What a symbolic ref holds before the name of the ref it points at.
+pub const SYMBOLIC_PREFIX: &str = "ref: ";
+
+const PACKED_REFS: &str = "packed-refs";
+/// Rewritten files only promise to be sorted: peeled ids are kept where the original file
+/// had them, but revy doesn't add missing ones.
+const PACKED_REFS_HEADER: &str = "# pack-refs with: sorted ";
+
+/// Bound on the number of symbolic refs followed, so a cycle can't loop forever.
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// The value a ref must have for an update to go ahead, so concurrent writers can't
+/// silently undo each other's changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// Update the ref whatever it holds.
+    Any,
+    /// Only create the ref; fail if it exists.
+    Missing,
+    /// Only update the ref if it points at this object.
+    Value(ObjectId),
+}
+
+/// A line of `packed-refs`, with the id an annotated tag peels to when it was recorded.
+#[derive(Debug, Clone)]
+struct PackedRef {
+    id: String,
+    peeled: Option<String>,
+}
+
+/// Returns the path of the file backing the ref `name`, e.g. `refs/tags/v1.0`.
+fn ref_path(revy_dir: &Path, name: &str) -> PathBuf {
+    revy_dir.join(name)
+}
+
+/// Check if `name` is acceptable as a ref name, following the main rules of
+/// `git check-ref-format`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && name != "@"
+        && !name.split('/').any(|component| component.starts_with('.'))
+        && !name.chars().any(|c| {
+            c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+        })
+}
+
+/// Returns `name` without the `refs/heads/`, `refs/tags/`, `refs/remotes/` or `refs/` it
+/// starts with, the way git shows it to people.
+pub fn short_name(name: &str) -> &str {
+    [HEADS_PREFIX, TAGS_PREFIX, REMOTES_PREFIX, "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Check if `name` can be updated: `HEAD` or a valid name under `refs/`.
+fn check_updatable(name: &str) -> Result<()> {
+    if name == "HEAD" || (name.starts_with("refs/") && is_valid_ref_name(name)) {
+        Ok(())
+    } else {
+        Err(RevyError::InvalidArgument(format!(
+            "refusing to update ref with bad name '{}'",
+            name
+        )))
+    }
+}
+
+/// Reads the raw contents of a ref, without following symbolic refs.
+pub(crate) fn read_ref(repo: &Repository, name: &str) -> Option<String> {
+    read_ref_in(repo.revy_dir(), name)
+}
+
+fn read_ref_in(revy_dir: &Path, name: &str) -> Option<String> {
+    let path = ref_path(revy_dir, name);
+    if path.is_file() {
+        return fs::read_to_string(path)
+            .ok()
+            .map(|contents| contents.trim().to_string());
+    }
+    if !name.starts_with("refs/") {
+        return None;
+    }
+    read_packed_refs(revy_dir)
+        .remove(name)
+        .map(|packed| packed.id)
+}
+
+/// Returns the name the symbolic ref `name` points at, or `None` if it isn't symbolic.
+pub(crate) fn read_symbolic_ref(repo: &Repository, name: &str) -> Option<String> {
+    read_ref(repo, name)?
+        .strip_prefix(SYMBOLIC_PREFIX)
+        .map(str::to_string)
+}
+
+/// Resolves the ref `name` (e.g. `HEAD` or `refs/tags/v1.0`) to an object id,
+/// following symbolic refs.
+pub(crate) fn resolve_ref(repo: &Repository, name: &str) -> Option<ObjectId> {
+    resolve_ref_in(repo.revy_dir(), name)
+}
+
+/// Like `resolve_ref`, but for the repository whose `.revy` directory is `repository`, e.g.
+/// a nested repository.
+pub fn resolve_ref_in(repository: &Path, name: &str) -> Option<ObjectId> {
+    let mut current = name.to_string();
+    for _ in 0..MAX_SYMBOLIC_DEPTH {
+        let contents = read_ref_in(repository, &current)?;
+        match contents.strip_prefix(SYMBOLIC_PREFIX) {
+            Some(target) => current = target.to_string(),
+            None => return ObjectId::from_hex(&contents).ok(),
+        }
+    }
+    None
+}
+
+/// Follows the symbolic refs starting at `name` and returns the ref at the end of the chain,
+/// which holds an object id or doesn't exist yet, like a branch before its first commit.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the symbolic refs form a cycle or a chain that is too long.
+pub(crate) fn follow_symbolic_refs(repo: &Repository, name: &str) -> Result<String> {
+    let mut current = name.to_string();
+    for _ in 0..MAX_SYMBOLIC_DEPTH {
+        match read_symbolic_ref(repo, &current) {
+            Some(target) => current = target,
+            None => return Ok(current),
+        }
+    }
+    Err(RevyError::BadRef(format!(
+        "symbolic ref '{}' is nested too deeply",
+        name
+    )))
+}
+
+/// Resolves a user supplied name, without the suffixes of a full revision expression, to
+/// an object id.
+///
+/// Names are looked up as refs as `expand_ref_name` does, after a full object id and before
+/// an abbreviated one, and `<name>@{<n or date>}` through the reflog of the ref.
+pub(crate) fn resolve_name(repo: &Repository, name: &str) -> Result<ObjectId> {
+    if let Ok(hash) = ObjectId::from_hex(name) {
+        if hash.algorithm() == repo.algorithm() {
+            return Ok(hash);
+        }
+    }
+
+    if let Some((base, spec)) = reflog::split_reflog_suffix(name) {
+        // A bare `@{n}` looks at the reflog of the current branch.
+        let full_name = match base {
+            "" => follow_symbolic_refs(repo, "HEAD")?,
+            base => expand_ref_name(repo, base)
+                .ok_or_else(|| RevyError::BadRef(format!("Not a valid object name: '{}'", name)))?,
+        };
+        return reflog::resolve_at(repo, &full_name, spec);
+    }
+
+    if let Some(hash) = expand_ref_name(repo, name).and_then(|full| resolve_ref(repo, &full)) {
+        return Ok(hash);
+    }
+
+    if ObjectId::is_hex_prefix(name) {
+        return Object::resolve_prefix(repo, name);
+    }
+    Err(RevyError::BadRef(format!(
+        "Not a valid object name: '{}'",
+        name
+    )))
+}
+
+/// Returns the full name of the ref a user supplied `name` refers to, trying `<name>`,
+/// `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`, `refs/remotes/<name>` and
+/// `refs/remotes/<name>/HEAD` in that order, as git does. A ref that was deleted but still
+/// has a reflog counts as existing.
+///
+/// `<name>` itself is only tried when it is under `refs/` or names a root ref like
+/// `ORIG_HEAD`, so files such as `config` or `index` are never read as refs.
+pub(crate) fn expand_ref_name(repo: &Repository, name: &str) -> Option<String> {
+    let bare = Some(name.to_string()).filter(|name| name.starts_with("refs/") || is_root_ref(name));
+    bare.into_iter()
+        .chain([
+            format!("refs/{}", name),
+            format!("{}{}", TAGS_PREFIX, name),
+            format!("{}{}", HEADS_PREFIX, name),
+            format!("{}{}", REMOTES_PREFIX, name),
+            format!("{}{}/HEAD", REMOTES_PREFIX, name),
+        ])
+        .filter(|candidate| is_valid_ref_name(candidate))
+        .find(|candidate| {
+            resolve_ref(repo, candidate).is_some() || reflog::has_reflog(repo, candidate)
+        })
+}
+
+/// Check if `name` has the form of a ref kept at the top of the repository, like `HEAD` or
+/// `ORIG_HEAD`: capital letters and underscores, ending in `HEAD`.
+fn is_root_ref(name: &str) -> bool {
+    name.ends_with("HEAD") && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// Checks that the ref `name` currently holds what `expected` asks for. Must be called with
+/// the ref locked, so it can't change between the check and the update.
+fn verify_expected(repo: &Repository, name: &str, expected: Expected) -> Result<()> {
+    let current = read_ref(repo, name);
+    let current_id = current
+        .as_deref()
+        .and_then(|contents| ObjectId::from_hex(contents).ok());
+    match expected {
+        Expected::Any => Ok(()),
+        Expected::Missing if current.is_none() => Ok(()),
+        Expected::Missing => Err(RevyError::BadRef(format!(
+            "cannot lock ref '{}': reference already exists",
+            name
+        ))),
+        Expected::Value(id) if current_id == Some(id) => Ok(()),
+        Expected::Value(id) => Err(RevyError::BadRef(match current {
+            Some(contents) => format!(
+                "cannot lock ref '{}': is at {} but expected {}",
+                name, contents, id
+            ),
+            None => format!("cannot lock ref '{}': unable to resolve reference", name),
+        })),
+    }
+}
+
+/// Points the ref `name` itself at `id`, if it currently holds what `expected` asks for.
+/// A symbolic ref is overwritten rather than followed.
+///
+/// The update is recorded in the reflog of `name` with `message`, and in the reflog of
+/// `HEAD` too when `HEAD` points at `name`.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the ref doesn't hold the expected value, and an I/O error if
+/// it is locked by another writer.
+pub(crate) fn update_ref(
+    repo: &Repository,
+    name: &str,
+    id: &ObjectId,
+    expected: Expected,
+    message: &str,
+) -> Result<()> {
+    check_updatable(name)?;
+    let mut lock = LockFile::acquire(&ref_path(repo.revy_dir(), name))?;
+    verify_expected(repo, name, expected)?;
+    let old = resolve_ref(repo, name);
+    lock.write_all(format!("{}\n", id).as_bytes())?;
+
+    reflog::append(repo, name, old.as_ref(), id, message)?;
+    if name != "HEAD" && read_symbolic_ref(repo, "HEAD").as_deref() == Some(name) {
+        reflog::append(repo, "HEAD", old.as_ref(), id, message)?;
+    }
+    lock.commit()
+}
+
+/// Points the ref `name` at `hash`, whatever it held before.
+pub(crate) fn write_ref(repo: &Repository, name: &str, hash: &ObjectId) -> Result<()> {
+    update_ref(repo, name, hash, Expected::Any, "")
+}
+
+/// Makes `name` a symbolic ref pointing at the ref `target`.
+///
+/// # Errors
+///
+/// Returns an error if `target` is not a valid name under `refs/`, or `name` is locked.
+pub(crate) fn write_symbolic_ref(repo: &Repository, name: &str, target: &str) -> Result<()> {
+    check_updatable(name)?;
+    if !target.starts_with("refs/") || !is_valid_ref_name(target) {
+        return Err(RevyError::InvalidArgument(format!(
+            "refusing to point {} outside of refs/: '{}'",
+            name, target
+        )));
+    }
+    let mut lock = LockFile::acquire(&ref_path(repo.revy_dir(), name))?;
+    lock.write_all(format!("{}{}\n", SYMBOLIC_PREFIX, target).as_bytes())?;
+    lock.commit()
+}
+
+/// Deletes the ref `name`, loose and packed, if it holds what `expected` asks for, pruning
+/// directories it leaves empty.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the ref doesn't exist or doesn't hold the expected value.
+pub(crate) fn delete_ref(repo: &Repository, name: &str, expected: Expected) -> Result<()> {
+    check_updatable(name)?;
+    let path = ref_path(repo.revy_dir(), name);
+    let lock = LockFile::acquire(&path)?;
+    if read_ref(repo, name).is_none() {
+        return Err(RevyError::BadRef(format!("ref '{}' not found", name)));
+    }
+    verify_expected(repo, name, expected)?;
+
+    let mut packed = read_packed_refs(repo.revy_dir());
+    if packed.contains_key(name) {
+        let mut packed_lock = LockFile::acquire(&repo.revy_dir().join(PACKED_REFS))?;
+        // Re-read under the lock, in case another writer changed the file meanwhile.
+        packed = read_packed_refs(repo.revy_dir());
+        packed.remove(name);
+        packed_lock.write_all(format_packed_refs(&packed).as_bytes())?;
+        packed_lock.commit()?;
+    }
+    lock.commit_removal()?;
+    reflog::remove_reflog(repo, name)?;
+
+    let refs_root = ref_path(repo.revy_dir(), "refs");
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == refs_root || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+/// Renames the ref `old` to `new`, which must not exist yet, taking its reflog along and
+/// logging the rename there with `message`. `HEAD` follows when it points at `old`.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if `old` doesn't exist or `new` does.
+pub(crate) fn rename_ref(repo: &Repository, old: &str, new: &str, message: &str) -> Result<()> {
+    check_updatable(new)?;
+    let id = resolve_ref(repo, old)
+        .filter(|_id| read_symbolic_ref(repo, old).is_none())
+        .ok_or_else(|| RevyError::BadRef(format!("ref '{}' not found", old)))?;
+    if read_ref(repo, new).is_some() {
+        return Err(RevyError::BadRef(format!("ref '{}' already exists", new)));
+    }
+
+    // The log moves first so the rename is appended to it. If the rename fails the log is
+    // moved back, and a new ref already written is removed, leaving the old ref as it was.
+    reflog::rename_reflog(repo, old, new)?;
+    let renamed = match create_renamed_ref(repo, new, &id, message) {
+        Ok(()) => delete_ref(repo, old, Expected::Value(id)).map_err(|err| (err, true)),
+        Err(err) => Err((err, false)),
+    };
+    if let Err((err, created)) = renamed {
+        let _ = reflog::rename_reflog(repo, new, old);
+        if created {
+            let _ = delete_ref(repo, new, Expected::Value(id));
+        }
+        return Err(err);
+    }
+    if read_symbolic_ref(repo, "HEAD").as_deref() == Some(old) {
+        write_symbolic_ref(repo, "HEAD", new)?;
+    }
+    Ok(())
+}
+
+/// Writes the ref `new` of a rename, which must not exist yet, pointing at `id`. Like git,
+/// the rename is logged as an update from `id` to itself rather than as a creation.
+fn create_renamed_ref(repo: &Repository, new: &str, id: &ObjectId, message: &str) -> Result<()> {
+    let mut lock = LockFile::acquire(&ref_path(repo.revy_dir(), new))?;
+    verify_expected(repo, new, Expected::Missing)?;
+    lock.write_all(format!("{}\n", id).as_bytes())?;
+    reflog::append(repo, new, Some(id), id, message)?;
+    lock.commit()
+}
+
+/// Lists every ref under `prefix` (e.g. `refs/tags/`), loose or packed, sorted by name.
+pub(crate) fn list_refs(repo: &Repository, prefix: &str) -> Vec<String> {
+    let mut refs = Vec::new();
+    collect_refs(
+        &ref_path(repo.revy_dir(), prefix),
+        prefix.trim_end_matches('/'),
+        &mut refs,
+    );
+    refs.extend(
+        read_packed_refs(repo.revy_dir())
+            .into_keys()
+            .filter(|name| name.starts_with(prefix)),
+    );
+    refs.sort();
+    refs.dedup();
+    refs
+}
+
+fn collect_refs(dir: &Path, name: &str, refs: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_err) => return,
+    };
+    for entry in entries.filter_map(std::result::Result::ok) {
+        let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            collect_refs(&entry.path(), &entry_name, refs);
+        } else if !entry_name.ends_with(".lock") {
+            refs.push(entry_name);
+        }
+    }
+}
+
+/// Returns the id the packed ref `name` peels to, when `packed-refs` recorded one. Only
+/// annotated tags have one.
+pub(crate) fn packed_peeled(repo: &Repository, name: &str) -> Option<ObjectId> {
+    read_packed_refs(repo.revy_dir())
+        .remove(name)?
+        .peeled
+        .and_then(|peeled| ObjectId::from_hex(&peeled).ok())
+}
+
+/// Parses `packed-refs`. Lines that can't be parsed are skipped, as git does.
+fn read_packed_refs(revy_dir: &Path) -> BTreeMap<String, PackedRef> {
+    let mut refs = BTreeMap::new();
+    let contents = match fs::read_to_string(revy_dir.join(PACKED_REFS)) {
+        Ok(contents) => contents,
+        Err(_err) => return refs,
+    };
+
+    let mut last: Option<String> = None;
+    for line in contents.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            if let Some(packed) = last.as_ref().and_then(|name| refs.get_mut(name)) {
+                packed.peeled = Some(peeled.trim().to_string());
+            }
+            continue;
+        }
+        last = line.split_once(' ').map(|(id, name)| {
+            refs.insert(
+                name.to_string(),
+                PackedRef {
+                    id: id.to_string(),
+                    peeled: None,
+                },
+            );
+            name.to_string()
+        });
+    }
+    refs
+}
+
+fn format_packed_refs(refs: &BTreeMap<String, PackedRef>) -> String {
+    let mut contents = format!("{}\n", PACKED_REFS_HEADER);
+    for (name, packed) in refs {
+        contents.push_str(&format!("{} {}\n", packed.id, name));
+        if let Some(peeled) = &packed.peeled {
+            contents.push_str(&format!("^{}\n", peeled));
+        }
+    }
+    contents
+}
@@ -0,0 +1,456 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::branch::{self, Branch};
+use crate::checkout::{self, SwitchOptions, Switched};
+use crate::commit::{self, CommitOptions, NewCommit};
+use crate::config::Config;
+use crate::error::{Result, RevyError};
+use crate::fsck::{self, FsckReport};
+use crate::hash::HashAlgorithm;
+use crate::index::Index;
+use crate::object::{Metadata, Object, ObjectReader, ObjectType};
+use crate::object_id::{ObjectId, MIN_ABBREV_LEN};
+use crate::reflog::{self, ReflogEntry};
+use crate::refs::{self, Expected};
+use crate::revision;
+use crate::status::{self, Status};
+use crate::tree::Tree;
+use crate::utils;
+
+/// A handle on a revy repository: a working tree and the `.revy` directory inside it.
+///
+/// Every operation goes through the handle, so nothing depends on the process's current
+/// directory and nothing is printed. The config, and the hash algorithm it names, are read
+/// once, when the repository is opened.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    work_tree: PathBuf,
+    revy_dir: PathBuf,
+    algorithm: HashAlgorithm,
+    config: Config,
+}
+
+impl Repository {
+    /// Opens the repository whose working tree is `work_tree`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `work_tree` has no `.revy` directory.
+    pub fn open(work_tree: impl AsRef<Path>) -> Result<Repository> {
+        let work_tree = work_tree.as_ref();
+        Repository::open_with_work_tree(work_tree.join(utils::REPO_FOLDER_NAME), work_tree)
+    }
+
+    /// Opens the repository stored in `revy_dir`, with `work_tree` as its working tree. The two
+    /// don't have to be related, as with `--revy-dir` and `--work-tree`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `revy_dir` is not a repository, or its config can't be read, and a
+    /// `BadConfig` error if the config names an unknown object format.
+    pub fn open_with_work_tree(
+        revy_dir: impl AsRef<Path>,
+        work_tree: impl AsRef<Path>,
+    ) -> Result<Repository> {
+        let revy_dir = revy_dir.as_ref().to_path_buf();
+        if !Repository::is_revy_dir(&revy_dir) {
+            return Err(RevyError::InvalidArgument(format!(
+                "not a revy repository: {}",
+                revy_dir.display()
+            )));
+        }
+
+        // Paths in the working tree are resolved against an absolute root, so they keep
+        // working if the process changes directory.
+        let work_tree = std::path::absolute(work_tree.as_ref()).map_err(|err| {
+            RevyError::io(
+                format!("Failed to resolve {}", work_tree.as_ref().display()),
+                err,
+            )
+        })?;
+        let algorithm = HashAlgorithm::for_repository(&revy_dir)?;
+        let config = Config::load(Some(&revy_dir))?;
+        Ok(Repository {
+            work_tree,
+            revy_dir,
+            algorithm,
+            config,
+        })
+    }
+
+    /// Finds the repository `start` belongs to by looking for a `.revy` directory in `start`
+    /// and then in each of its parents, like git does.
+    ///
+    /// The search stops at the root of the filesystem `start` is on, so a repository on
+    /// another mount is never picked up by accident.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no repository is found, or the one found can't be opened.
+    pub fn discover(start: impl AsRef<Path>) -> Result<Repository> {
+        let start = Repository::absolute_start(start.as_ref())?;
+        match Repository::find_work_tree(&start) {
+            Ok(work_tree) => Repository::open(work_tree),
+            Err(reason) => Err(RevyError::InvalidArgument(reason)),
+        }
+    }
+
+    /// Like `discover`, but returns `None` when `start` isn't inside a repository, for
+    /// commands that also work outside of one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository found can't be opened.
+    pub fn try_discover(start: impl AsRef<Path>) -> Result<Option<Repository>> {
+        let start = Repository::absolute_start(start.as_ref())?;
+        Repository::find_work_tree(&start)
+            .ok()
+            .map(Repository::open)
+            .transpose()
+    }
+
+    fn absolute_start(start: &Path) -> Result<PathBuf> {
+        std::path::absolute(start)
+            .map_err(|err| RevyError::io(format!("Failed to resolve {}", start.display()), err))
+    }
+
+    /// Returns the working tree of the repository `start` belongs to, or why none was found.
+    fn find_work_tree(start: &Path) -> std::result::Result<&Path, String> {
+        let device = Repository::device_of(start);
+
+        let mut current = start;
+        loop {
+            if Repository::is_revy_dir(&current.join(utils::REPO_FOLDER_NAME)) {
+                return Ok(current);
+            }
+            let parent = match current.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+            if Repository::device_of(parent) != device {
+                return Err(format!(
+                    "not a revy repository (or any parent up to mount point {})\n\
+                     Stopping at filesystem boundary.",
+                    current.display()
+                ));
+            }
+            current = parent;
+        }
+
+        Err(format!(
+            "not a revy repository (or any of the parent directories): {}",
+            utils::REPO_FOLDER_NAME
+        ))
+    }
+
+    /// Returns the same repository with `work_tree` as its working tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `work_tree` can't be made absolute.
+    pub fn with_work_tree(self, work_tree: impl AsRef<Path>) -> Result<Repository> {
+        Repository::open_with_work_tree(self.revy_dir, work_tree)
+    }
+
+    /// Check if `path` looks like a `.revy` directory.
+    fn is_revy_dir(path: &Path) -> bool {
+        path.join("objects").is_dir() && path.join("HEAD").is_file()
+    }
+
+    /// Returns the id of the device `path` is on, where the platform has one.
+    fn device_of(path: &Path) -> Option<u64> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            std::fs::metadata(path).ok().map(|metadata| metadata.dev())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            None
+        }
+    }
+
+    /// Creates an empty repository in `work_tree`, whose objects are named with
+    /// `object_format`. An existing repository is opened as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `.revy` directory can't be created.
+    pub fn init(work_tree: impl AsRef<Path>, object_format: HashAlgorithm) -> Result<Repository> {
+        let revy_dir = work_tree.as_ref().join(utils::REPO_FOLDER_NAME);
+        if !revy_dir.exists() {
+            let branch = utils::initial_branch()?;
+            utils::initialize_repository(&revy_dir.to_string_lossy(), object_format, &branch)?;
+        }
+        Repository::open(work_tree)
+    }
+
+    /// Returns the root of the working tree.
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    /// Returns the `.revy` directory.
+    pub fn revy_dir(&self) -> &Path {
+        &self.revy_dir
+    }
+
+    /// Returns the directory loose objects are stored in.
+    pub fn objects_dir(&self) -> PathBuf {
+        self.revy_dir.join("objects")
+    }
+
+    /// Returns the path of the index file.
+    pub fn index_path(&self) -> PathBuf {
+        self.revy_dir.join("index")
+    }
+
+    /// Returns the hash algorithm objects in this repository are named with.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the config, the repository's own cascaded over the global and system ones.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Check if the object `id` is in the object database.
+    pub fn has_object(&self, id: &ObjectId) -> bool {
+        Object::exists(self, id)
+    }
+
+    /// Reads and parses the object `id`.
+    pub fn read_object(&self, id: &ObjectId) -> Result<Object> {
+        Object::load_object_from_hash(self, id)
+    }
+
+    /// Opens the object `id` for streaming its content.
+    pub fn open_object(&self, id: &ObjectId) -> Result<ObjectReader> {
+        Object::open_object_stream(self, id)
+    }
+
+    /// Reads the tree named by `id`, peeling commits and tags down to their tree.
+    pub fn read_tree(&self, id: &ObjectId) -> Result<Tree> {
+        Object::load_tree_from_hash(self, id)
+    }
+
+    /// Stores a new object built from `metadata` and returns it.
+    pub fn write_object(&self, kind: ObjectType, metadata: Metadata) -> Result<Object> {
+        let object = Object::new(self.algorithm, kind, metadata)?;
+        object.save_object(self)?;
+        Ok(object)
+    }
+
+    /// Hashes `size` bytes from `reader` as a blob, storing it when `write` is set.
+    pub fn hash_blob<R: Read>(&self, reader: R, size: usize, write: bool) -> Result<ObjectId> {
+        Object::stream_blob(self, reader, size, write)
+    }
+
+    /// Hashes the file at `path` as a blob, storing it when `write` is set.
+    pub fn hash_file(&self, path: &Path, write: bool) -> Result<ObjectId> {
+        Object::stream_blob_from_file(self, path, write)
+    }
+
+    /// Reads the index. A repository without an index file has an empty one.
+    pub fn read_index(&self) -> Result<Index> {
+        Index::load(self)
+    }
+
+    /// Replaces the index with `index`.
+    pub fn write_index(&self, index: &Index) -> Result<()> {
+        index.save(self)
+    }
+
+    /// Stores the content of the index as tree objects, returning the id of the root tree.
+    pub fn write_tree(&self) -> Result<ObjectId> {
+        self.read_index()?.write_tree(self)
+    }
+
+    /// Lists the ids of every loose object, sorted.
+    pub fn list_objects(&self) -> Result<Vec<ObjectId>> {
+        Object::list_loose_objects(self)
+    }
+
+    /// Resolves a full or abbreviated hex object id.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<ObjectId> {
+        Object::resolve_prefix(self, prefix)
+    }
+
+    /// Resolves a revision expression, like `main`, `a1b2c3`, `HEAD~2^{tree}` or
+    /// `v1.0:src/lib.rs`, to an object id. See the `revision` module for the syntax.
+    pub fn resolve_revision(&self, name: &str) -> Result<ObjectId> {
+        revision::resolve_revision(self, name)
+    }
+
+    /// Returns the shortest abbreviation of `id`, at least `min_len` digits long, that no
+    /// other object shares.
+    pub fn abbreviate(&self, id: &ObjectId, min_len: usize) -> String {
+        let hex = id.to_hex();
+        (min_len.max(MIN_ABBREV_LEN)..hex.len())
+            .map(|len| &hex[..len])
+            .find(|prefix| Object::resolve_prefix(self, prefix).is_ok_and(|found| found == *id))
+            .unwrap_or(&hex)
+            .to_string()
+    }
+
+    /// Resolves the ref `name` to an object id, following symbolic refs.
+    pub fn resolve_ref(&self, name: &str) -> Option<ObjectId> {
+        refs::resolve_ref(self, name)
+    }
+
+    /// Returns the full name of the ref a user supplied `name`, like `main`, refers to.
+    pub fn expand_ref_name(&self, name: &str) -> Option<String> {
+        refs::expand_ref_name(self, name)
+    }
+
+    /// Reads the raw contents of the ref `name`, without following symbolic refs.
+    pub fn read_ref(&self, name: &str) -> Option<String> {
+        refs::read_ref(self, name)
+    }
+
+    /// Points the ref `name` at `id`, whatever it held before.
+    pub fn write_ref(&self, name: &str, id: &ObjectId) -> Result<()> {
+        refs::write_ref(self, name, id)
+    }
+
+    /// Points the ref `name` at `id` if it currently holds what `expected` asks for, logging
+    /// the update with `message`.
+    pub fn update_ref(
+        &self,
+        name: &str,
+        id: &ObjectId,
+        expected: Expected,
+        message: &str,
+    ) -> Result<()> {
+        refs::update_ref(self, name, id, expected, message)
+    }
+
+    /// Returns the reflog of the ref `name`, oldest entry first.
+    pub fn reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        reflog::read_reflog(self, name)
+    }
+
+    /// Deletes the ref `name` if it currently holds what `expected` asks for.
+    pub fn delete_ref(&self, name: &str, expected: Expected) -> Result<()> {
+        refs::delete_ref(self, name, expected)
+    }
+
+    /// Renames the ref `old` to `new` along with its reflog, moving `HEAD` too if it points
+    /// at `old`.
+    pub fn rename_ref(&self, old: &str, new: &str, message: &str) -> Result<()> {
+        refs::rename_ref(self, old, new, message)
+    }
+
+    /// Returns the ref the symbolic ref `name` points at, or `None` if it isn't symbolic.
+    pub fn read_symbolic_ref(&self, name: &str) -> Option<String> {
+        refs::read_symbolic_ref(self, name)
+    }
+
+    /// Makes `name` a symbolic ref pointing at the ref `target`.
+    pub fn write_symbolic_ref(&self, name: &str, target: &str) -> Result<()> {
+        refs::write_symbolic_ref(self, name, target)
+    }
+
+    /// Follows the symbolic refs starting at `name`, returning the ref that holds the id.
+    pub fn follow_symbolic_refs(&self, name: &str) -> Result<String> {
+        refs::follow_symbolic_refs(self, name)
+    }
+
+    /// Returns the id the packed ref `name` is recorded to peel to, if any.
+    pub fn packed_peeled(&self, name: &str) -> Option<ObjectId> {
+        refs::packed_peeled(self, name)
+    }
+
+    /// Lists every ref under `prefix`, sorted by name.
+    pub fn list_refs(&self, prefix: &str) -> Vec<String> {
+        refs::list_refs(self, prefix)
+    }
+
+    /// Records the index as a new commit on top of `HEAD`, or in place of it with `amend`,
+    /// and moves the current branch to it. See `commit::commit_index`.
+    pub fn commit(&self, options: &CommitOptions) -> Result<NewCommit> {
+        commit::commit_index(self, options)
+    }
+
+    /// Returns the branch `HEAD` points at, without `refs/heads/`, or `None` when it is
+    /// detached.
+    pub fn current_branch(&self) -> Option<String> {
+        branch::current_branch(self)
+    }
+
+    /// Returns the full name of the ref the branch `name` tracks, if any.
+    pub fn branch_upstream(&self, name: &str) -> Option<String> {
+        branch::branch_upstream(self, name)
+    }
+
+    /// Creates the branch `name` at `start`, `HEAD` by default, returning the upstream it
+    /// tracks. See `branch::create_branch`.
+    pub fn create_branch(
+        &self,
+        name: &str,
+        start: Option<&str>,
+        force: bool,
+        track: Option<bool>,
+    ) -> Result<Option<String>> {
+        branch::create_branch(self, name, start, force, track)
+    }
+
+    /// Deletes the branch `name` and its config, returning the commit it pointed at. Unless
+    /// `force` is set the branch must be merged.
+    pub fn delete_branch(&self, name: &str, force: bool) -> Result<ObjectId> {
+        branch::delete_branch(self, name, force)
+    }
+
+    /// Renames the branch `old`, the current branch by default, to `new`, along with its
+    /// reflog and config.
+    pub fn rename_branch(&self, old: Option<&str>, new: &str, force: bool) -> Result<()> {
+        branch::rename_branch(self, old, new, force)
+    }
+
+    /// Lists the branches matching the glob `patterns`, or all of them, sorted by name.
+    pub fn list_branches(&self, patterns: &[String]) -> Result<Vec<Branch>> {
+        branch::list_branches(self, patterns)
+    }
+
+    /// Makes `branch`, the current branch by default, track `upstream`, returning the names
+    /// of both.
+    pub fn set_upstream(&self, branch: Option<&str>, upstream: &str) -> Result<(String, String)> {
+        branch::set_upstream(self, branch, upstream)
+    }
+
+    /// Stops `branch`, the current branch by default, from tracking an upstream.
+    pub fn unset_upstream(&self, branch: Option<&str>) -> Result<()> {
+        branch::unset_upstream(self, branch)
+    }
+
+    /// Compares `HEAD`, the index and the working tree.
+    pub fn status(&self) -> Result<Status> {
+        status::status(self)
+    }
+
+    /// Makes the index and the working tree match the tree of the commit `id`, refusing to
+    /// overwrite local changes unless `force` is set.
+    pub fn checkout_tree(&self, id: &ObjectId, force: bool) -> Result<()> {
+        checkout::checkout_tree(self, id, force)
+    }
+
+    /// Switches to the branch `name`, creating it or detaching `HEAD` as `options` ask. See
+    /// `checkout::switch_branch`.
+    pub fn switch_branch(&self, name: &str, options: &SwitchOptions) -> Result<Switched> {
+        checkout::switch_branch(self, name, options)
+    }
+
+    /// Checks out the branch or commit `target`, detaching `HEAD` unless it is a local
+    /// branch. See `checkout::checkout`.
+    pub fn checkout(&self, target: &str, detach: bool, force: bool) -> Result<Switched> {
+        checkout::checkout(self, target, detach, force)
+    }
+
+    /// Checks the integrity and connectivity of the object database.
+    pub fn fsck(&self) -> Result<FsckReport> {
+        fsck::check(self)
+    }
+}
@@ -0,0 +1,341 @@
+//! Revision expressions, the way `git rev-parse` reads them.
+//!
+//! A revision starts with a name: a full or abbreviated object id, a ref like `main`,
+//! `v1.0` or `origin/main`, `@` for `HEAD`, or a reflog entry like `main@{2}`. It may be
+//! followed by any number of suffixes:
+//!
+//! - `~<n>`: the `n`th first-parent ancestor, `~` alone being `~1`.
+//! - `^<n>`: the `n`th parent, `^` alone being `^1` and `^0` the commit itself.
+//! - `^{<type>}`: the object peeled to a `commit`, `tree`, `blob` or `tag`; `^{}` peels
+//!   tags until something else is found, and `^{object}` only checks the object exists.
+//!
+//! `<rev>:<path>` names the blob or tree at `path` in the tree of `rev`, and `:<path>` or
+//! `:<stage>:<path>` the entry staged in the index.
+//!
+//! Ranges expand into several revisions, some of them excluded: `^<rev>` excludes `rev`,
+//! `a..b` is `b` without what is reachable from `a`, and `a...b` is what is reachable
+//! from either but not from both. A side left empty is `HEAD`.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::error::{Result, RevyError};
+use crate::object::{Metadata, ObjectType};
+use crate::object_id::ObjectId;
+use crate::refs;
+use crate::repository::Repository;
+
+/// One revision of an expanded range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Revision {
+    /// Commits reachable from the object are wanted.
+    Include(ObjectId),
+    /// Commits reachable from the object are not wanted, even if an included one reaches
+    /// them.
+    Exclude(ObjectId),
+}
+
+/// Resolves a single revision expression to an object id.
+///
+/// # Errors
+///
+/// Returns a `BadRef` error if the expression names something that doesn't exist, like a
+/// missing ref, parent or path, an `AmbiguousObject` error if an abbreviated id matches
+/// several objects, and an `InvalidArgument` error if it can't be parsed.
+pub fn resolve_revision(repo: &Repository, spec: &str) -> Result<ObjectId> {
+    if let Some(colon) = find_outside_braces(spec, ":") {
+        let (revision, path) = (&spec[..colon], &spec[colon + 1..]);
+        return match revision {
+            "" => resolve_index_path(repo, spec, path),
+            revision => resolve_tree_path(repo, spec, revision, path),
+        };
+    }
+
+    // Ref names can't contain `~` or `^`, so the name ends at the first of them.
+    let name_end = find_outside_braces(spec, "~^").unwrap_or(spec.len());
+    let name = match &spec[..name_end] {
+        "@" => "HEAD",
+        name => name,
+    };
+    let mut id = refs::resolve_name(repo, name)?;
+
+    let mut rest = &spec[name_end..];
+    while let Some(operator) = rest.chars().next() {
+        rest = &rest[1..];
+        if operator == '^' && rest.starts_with('{') {
+            let close = rest.find('}').ok_or_else(|| invalid_revision(spec))?;
+            id = peel(repo, spec, id, &rest[1..close])?;
+            rest = &rest[close + 1..];
+            continue;
+        }
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let count = match &rest[..digits] {
+            "" => 1,
+            digits => digits
+                .parse::<usize>()
+                .map_err(|_err| invalid_revision(spec))?,
+        };
+        rest = &rest[digits..];
+        id = match operator {
+            '~' => (0..count).try_fold(id, |id, _step| nth_parent(repo, spec, id, 1))?,
+            '^' => nth_parent(repo, spec, id, count)?,
+            _ => return Err(invalid_revision(spec)),
+        };
+    }
+    Ok(id)
+}
+
+/// Expands a revision argument, which may be a range, into the revisions it stands for:
+/// `a..b` into `b` and an excluded `a`, and `a...b` into `b`, `a` and their excluded
+/// merge bases, in the order `git rev-parse` prints them.
+///
+/// # Errors
+///
+/// Returns the errors of `resolve_revision` for either side.
+pub fn resolve_range(repo: &Repository, spec: &str) -> Result<Vec<Revision>> {
+    if find_outside_braces(spec, ":").is_none() {
+        if let Some(start) = spec.find("...") {
+            let left = resolve_side(repo, &spec[..start])?;
+            let right = resolve_side(repo, &spec[start + 3..])?;
+            let mut revisions = vec![Revision::Include(right), Revision::Include(left)];
+            revisions.extend(
+                merge_bases(repo, &left, &right)?
+                    .into_iter()
+                    .map(Revision::Exclude),
+            );
+            return Ok(revisions);
+        }
+        if let Some(start) = spec.find("..") {
+            let left = resolve_side(repo, &spec[..start])?;
+            let right = resolve_side(repo, &spec[start + 2..])?;
+            return Ok(vec![Revision::Include(right), Revision::Exclude(left)]);
+        }
+    }
+    match spec.strip_prefix('^') {
+        Some(excluded) => Ok(vec![Revision::Exclude(resolve_revision(repo, excluded)?)]),
+        None => Ok(vec![Revision::Include(resolve_revision(repo, spec)?)]),
+    }
+}
+
+/// Resolves one side of a range, where nothing stands for `HEAD`.
+fn resolve_side(repo: &Repository, spec: &str) -> Result<ObjectId> {
+    resolve_revision(repo, if spec.is_empty() { "HEAD" } else { spec })
+}
+
+/// Returns the byte offset of the first of `chars` in `spec` that isn't inside `@{...}`,
+/// where reflog dates may use any character.
+fn find_outside_braces(spec: &str, chars: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (offset, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if depth == 0 && chars.contains(c) => return Some(offset),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn invalid_revision(spec: &str) -> RevyError {
+    RevyError::BadRef(format!("Not a valid object name: '{}'", spec))
+}
+
+/// Returns the parents of the commit `id`, in order.
+///
+/// # Errors
+///
+/// Returns an `InvalidArgument` error if `id` is not a commit.
+pub fn commit_parents(repo: &Repository, id: &ObjectId) -> Result<Vec<ObjectId>> {
+    match repo.read_object(id)?.metadata {
+        Metadata::Commit(commit) => Ok(commit.parents),
+        _ => Err(RevyError::InvalidArgument(format!(
+            "{} is not a commit",
+            id
+        ))),
+    }
+}
+
+/// Returns the `n`th parent of what `id` peels to, or the commit itself for `n == 0`.
+fn nth_parent(repo: &Repository, spec: &str, id: ObjectId, n: usize) -> Result<ObjectId> {
+    let commit = peel(repo, spec, id, "commit")?;
+    if n == 0 {
+        return Ok(commit);
+    }
+    commit_parents(repo, &commit)?
+        .get(n - 1)
+        .copied()
+        .ok_or_else(|| {
+            RevyError::BadRef(format!(
+                "Not a valid object name: '{}' ({} has no parent {})",
+                spec,
+                commit.short(),
+                n
+            ))
+        })
+}
+
+/// Peels `id` to an object of type `target`, following tags, and from a commit to its tree
+/// for anything but a commit.
+/// An empty `target` peels tags until something else is found, and `object` accepts any
+/// object that exists.
+fn peel(repo: &Repository, spec: &str, id: ObjectId, target: &str) -> Result<ObjectId> {
+    let wanted = match target {
+        "" | "object" => None,
+        kind => Some(ObjectType::from_str(kind).ok_or_else(|| {
+            RevyError::InvalidArgument(format!("unknown object type '{}' in '{}'", kind, spec))
+        })?),
+    };
+
+    let mut current = id;
+    loop {
+        let kind = repo.open_object(&current)?.kind;
+        if target == "object"
+            || Some(kind) == wanted
+            || (wanted.is_none() && kind != ObjectType::Tag)
+        {
+            return Ok(current);
+        }
+        current = match (repo.read_object(&current)?.metadata, wanted) {
+            (Metadata::Tag(tag), _) => tag.object,
+            // Like git, anything but a commit is looked for in its tree.
+            (Metadata::Commit(commit), Some(kind)) if kind != ObjectType::Commit => commit.tree,
+            _ => {
+                return Err(RevyError::BadRef(format!(
+                    "{}: expected {} type, but the object dereferences to {} type",
+                    spec,
+                    target,
+                    kind.as_str()
+                )))
+            }
+        };
+    }
+}
+
+/// Looks `path` up in the tree `revision` peels to.
+fn resolve_tree_path(
+    repo: &Repository,
+    spec: &str,
+    revision: &str,
+    path: &str,
+) -> Result<ObjectId> {
+    let tree = peel(repo, spec, resolve_revision(repo, revision)?, "tree")?;
+    tree_entry(repo, &tree, path)?.ok_or_else(|| {
+        RevyError::BadRef(format!("path '{}' does not exist in '{}'", path, revision))
+    })
+}
+
+/// Returns the id of the blob or tree at `path` in the tree `tree`, or `None` if there is
+/// nothing there. The empty path is the tree itself.
+///
+/// # Errors
+///
+/// Returns an error if a tree on the way can't be read.
+pub fn tree_entry(repo: &Repository, tree: &ObjectId, path: &str) -> Result<Option<ObjectId>> {
+    let mut id = *tree;
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let tree = match repo.read_tree(&id) {
+            Ok(tree) => tree,
+            // A file where a directory was expected.
+            Err(RevyError::InvalidArgument(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        match tree.data.iter().find(|node| node.name() == component) {
+            Some(node) => id = *node.hash(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(id))
+}
+
+/// Looks `path`, optionally prefixed with `<stage>:`, up in the index.
+fn resolve_index_path(repo: &Repository, spec: &str, path: &str) -> Result<ObjectId> {
+    let (stage, path) = match path.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 => (
+            stage
+                .parse::<u8>()
+                .ok()
+                .filter(|stage| *stage <= 3)
+                .ok_or_else(|| invalid_revision(spec))?,
+            path,
+        ),
+        _ => (0, path),
+    };
+    repo.read_index()?
+        .entries()
+        .iter()
+        .find(|entry| entry.path == path && entry.stage == stage)
+        .map(|entry| entry.id)
+        .ok_or_else(|| {
+            RevyError::BadRef(format!(
+                "path '{}' is not in the index at stage {}",
+                path, stage
+            ))
+        })
+}
+
+/// Returns every commit reachable from `start`, `start` included.
+fn ancestors(repo: &Repository, start: &ObjectId) -> Result<BTreeSet<ObjectId>> {
+    let mut seen = BTreeSet::new();
+    let mut queue = VecDeque::from([*start]);
+    while let Some(id) = queue.pop_front() {
+        if seen.insert(id) {
+            queue.extend(commit_parents(repo, &id)?);
+        }
+    }
+    Ok(seen)
+}
+
+/// Check if the commit `ancestor` is reachable from the commit `descendant`, which counts
+/// itself as reachable.
+pub fn is_ancestor(repo: &Repository, ancestor: &ObjectId, descendant: &ObjectId) -> Result<bool> {
+    Ok(ancestors(repo, descendant)?.contains(ancestor))
+}
+
+/// Counts the commits reachable from `a` but not from `b`, and from `b` but not from `a`:
+/// how far `a` is ahead of and behind `b`.
+pub fn ahead_behind(repo: &Repository, a: &ObjectId, b: &ObjectId) -> Result<(usize, usize)> {
+    let from_a = ancestors(repo, a)?;
+    let from_b = ancestors(repo, b)?;
+    Ok((
+        from_a.difference(&from_b).count(),
+        from_b.difference(&from_a).count(),
+    ))
+}
+
+/// Returns the best common ancestors of the commits `a` and `b`: the commits reachable
+/// from both that no other such commit reaches. Usually there is one; unrelated histories
+/// have none.
+pub fn merge_bases(repo: &Repository, a: &ObjectId, b: &ObjectId) -> Result<Vec<ObjectId>> {
+    let from_a = ancestors(repo, a)?;
+
+    // Walk back from `b`, stopping at the first commits `a` reaches too.
+    let mut common = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut queue = VecDeque::from([*b]);
+    while let Some(id) = queue.pop_front() {
+        if !seen.insert(id) {
+            continue;
+        }
+        if from_a.contains(&id) {
+            common.insert(id);
+        } else {
+            queue.extend(commit_parents(repo, &id)?);
+        }
+    }
+
+    let mut bases = Vec::new();
+    for candidate in &common {
+        let mut redundant = false;
+        for other in common.iter().filter(|other| *other != candidate) {
+            if is_ancestor(repo, candidate, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            bases.push(*candidate);
+        }
+    }
+    Ok(bases)
+}
@@ -0,0 +1,249 @@
+//! Comparing `HEAD`, the index and the working tree, as reported by `revy status`.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+
+use crate::error::{Result, RevyError};
+use crate::index::{Index, IndexEntry, StatData};
+use crate::object_id::ObjectId;
+use crate::repository::Repository;
+use crate::tree::FileMode;
+use crate::utils;
+use crate::worktree;
+
+/// How a path differs from one side of a comparison to the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// The path changed between a file, a symbolic link and a gitlink.
+    TypeChanged,
+}
+
+impl Change {
+    /// Returns the letter `--short` output uses for the change.
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+        }
+    }
+
+    /// Returns the label the long output puts before the path.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Change::Added => "new file:",
+            Change::Modified => "modified:",
+            Change::Deleted => "deleted:",
+            Change::TypeChanged => "typechange:",
+        }
+    }
+}
+
+/// The differences between `HEAD`, the index and the working tree. Paths are relative to the
+/// root of the working tree, and each list is sorted.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// The commit `HEAD` points at, or `None` before the first commit.
+    pub head: Option<ObjectId>,
+    /// Changes between the tree of `HEAD` and the index, which the next commit would record.
+    pub staged: Vec<(String, Change)>,
+    /// Changes between the index and the working tree.
+    pub unstaged: Vec<(String, Change)>,
+    /// Files that are neither staged nor ignored. A directory holding no tracked files is
+    /// listed once, with a trailing `/`, instead of each file in it.
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    /// Check if the index matches `HEAD` and the working tree has no changes or untracked
+    /// files.
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.untracked.is_empty()
+    }
+}
+
+/// Compares `HEAD`, the index and the working tree of `repo`.
+///
+/// Files whose stat data matches the index are taken to be unchanged without being read.
+/// Files modified no earlier than the index was written can't be trusted that way, since
+/// a second change in the same clock tick keeps the stat data, so they are hashed. The stat
+/// data of files found unchanged after hashing is refreshed in the index, so the next run
+/// doesn't have to hash them again.
+///
+/// # Errors
+///
+/// Returns an error if `HEAD`, the index or the working tree can't be read.
+pub fn status(repo: &Repository) -> Result<Status> {
+    let mut index = repo.read_index()?;
+    let head = repo.resolve_ref("HEAD");
+
+    let mut head_entries = BTreeMap::new();
+    if let Some(commit) = head {
+        flatten_tree(repo, &commit, "", &mut head_entries)?;
+    }
+
+    let mut status = Status {
+        head,
+        ..Status::default()
+    };
+
+    let mut staged_paths = HashSet::new();
+    for entry in index.entries() {
+        staged_paths.insert(entry.path.as_str());
+        match head_entries.get(&entry.path) {
+            None => status.staged.push((entry.path.clone(), Change::Added)),
+            Some((mode, id)) => {
+                if let Some(change) = compare(*mode, id, entry.mode, &entry.id) {
+                    status.staged.push((entry.path.clone(), change));
+                }
+            }
+        }
+    }
+    for path in head_entries.keys() {
+        if !staged_paths.contains(path.as_str()) {
+            status.staged.push((path.clone(), Change::Deleted));
+        }
+    }
+    status.staged.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let index_mtime = index_mtime(repo);
+    let mut refreshed = Vec::new();
+    for entry in index.entries() {
+        let full_path = repo.work_tree().join(&entry.path);
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                status.unstaged.push((entry.path.clone(), Change::Deleted));
+                continue;
+            }
+            Err(err) => {
+                return Err(RevyError::io(
+                    format!("could not stat '{}'", entry.path),
+                    err,
+                ))
+            }
+        };
+
+        // A directory that isn't a nested repository took the place of the file.
+        if metadata.is_dir() && !full_path.join(utils::REPO_FOLDER_NAME).is_dir() {
+            status.unstaged.push((entry.path.clone(), Change::Deleted));
+            continue;
+        }
+
+        let mode = worktree::mode_of(&metadata);
+        if mode == entry.mode
+            && StatData::from_metadata(&metadata) == entry.stat
+            && !is_racy(entry, index_mtime)
+        {
+            continue;
+        }
+
+        let current = worktree::entry_for(repo, &entry.path, false)?;
+        match compare(entry.mode, &entry.id, current.mode, &current.id) {
+            Some(change) => status.unstaged.push((entry.path.clone(), change)),
+            None => refreshed.push(current),
+        }
+    }
+
+    let tracked_dirs = tracked_directories(&index);
+    let untracked: BTreeSet<String> = worktree::list_files(repo, "")?
+        .into_iter()
+        .filter(|path| index.get(path).is_none() && !tracked_dirs.contains(path.as_str()))
+        .map(|path| untracked_name(repo, &path, &tracked_dirs))
+        .collect();
+    status.untracked = untracked.into_iter().collect();
+
+    if !refreshed.is_empty() {
+        for entry in refreshed {
+            index.add(entry);
+        }
+        // Refreshing is only an optimisation, so a locked or read-only index is not an error.
+        let _ = repo.write_index(&index);
+    }
+
+    Ok(status)
+}
+
+/// Returns how the entry `(old_mode, old_id)` changed into `(new_mode, new_id)`, if it did.
+fn compare(
+    old_mode: FileMode,
+    old_id: &ObjectId,
+    new_mode: FileMode,
+    new_id: &ObjectId,
+) -> Option<Change> {
+    let is_file = |mode| matches!(mode, FileMode::RegularFile | FileMode::ExecutableFile);
+    if old_mode != new_mode && !(is_file(old_mode) && is_file(new_mode)) {
+        Some(Change::TypeChanged)
+    } else if old_mode != new_mode || old_id != new_id {
+        Some(Change::Modified)
+    } else {
+        None
+    }
+}
+
+/// Adds every file in the tree of `id`, a commit or tree, to `entries`, under `prefix`.
+pub(crate) fn flatten_tree(
+    repo: &Repository,
+    id: &ObjectId,
+    prefix: &str,
+    entries: &mut BTreeMap<String, (FileMode, ObjectId)>,
+) -> Result<()> {
+    for node in repo.read_tree(id)?.data {
+        let path = format!("{}{}", prefix, node.name());
+        if node.mode() == FileMode::Directory {
+            flatten_tree(repo, node.hash(), &format!("{}/", path), entries)?;
+        } else {
+            entries.insert(path, (node.mode(), *node.hash()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the modification time of the index file as seconds and nanoseconds.
+fn index_mtime(repo: &Repository) -> Option<(u32, u32)> {
+    fs::metadata(repo.index_path())
+        .ok()
+        .map(|metadata| StatData::from_metadata(&metadata))
+        .map(|stat| (stat.mtime, stat.mtime_nsec))
+}
+
+/// Check if `entry` was modified no earlier than the index was written, in which case a
+/// later change to the file could leave its stat data the same.
+fn is_racy(entry: &IndexEntry, index_mtime: Option<(u32, u32)>) -> bool {
+    index_mtime.is_none_or(|index_mtime| (entry.stat.mtime, entry.stat.mtime_nsec) >= index_mtime)
+}
+
+/// Returns every directory that holds a staged file.
+fn tracked_directories(index: &Index) -> HashSet<&str> {
+    let mut dirs = HashSet::new();
+    for entry in index.entries() {
+        let mut path = entry.path.as_str();
+        while let Some((dir, _name)) = path.rsplit_once('/') {
+            if !dirs.insert(dir) {
+                break;
+            }
+            path = dir;
+        }
+    }
+    dirs
+}
+
+/// Returns the name the untracked file `path` is reported under: its outermost directory
+/// holding no tracked files, or the path itself.
+fn untracked_name(repo: &Repository, path: &str, tracked_dirs: &HashSet<&str>) -> String {
+    for (position, _separator) in path.match_indices('/') {
+        let dir = &path[..position];
+        if !tracked_dirs.contains(dir) {
+            return format!("{}/", dir);
+        }
+    }
+    if repo.work_tree().join(path).is_dir() {
+        format!("{}/", path)
+    } else {
+        path.to_string()
+    }
+}
@@ -0,0 +1,116 @@
+use flate2::read::ZlibDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use crate::commit::Signature;
+use crate::error::{Result, RevyError};
+use crate::object::ObjectType;
+use crate::object_id::ObjectId;
+
+#[derive(Debug)]
+pub struct Tag {
+    /// The hash of the tagged object.
+    pub object: ObjectId,
+    pub kind: ObjectType,
+    pub name: String,
+    /// Very old tags were written without a tagger, so it is optional when parsing.
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    /// Creates a new annotated `Tag` named `name` pointing at `object`.
+    pub fn new(
+        object: ObjectId,
+        kind: ObjectType,
+        name: String,
+        tagger: Signature,
+        message: String,
+    ) -> Tag {
+        Tag {
+            object,
+            kind,
+            name,
+            tagger: Some(tagger),
+            message,
+        }
+    }
+
+    /// Parses a `Tag` from a buffered `ZlibDecoder` reader positioned after the object header.
+    pub fn parse_tag(mut decoded_reader: BufReader<ZlibDecoder<File>>) -> Result<Tag> {
+        let mut buffer = Vec::new();
+        if decoded_reader.read_to_end(&mut buffer).is_err() {
+            return Err(RevyError::malformed("failed to decompress tag"));
+        }
+        Tag::from_bytes(&buffer)
+    }
+
+    /// Parses a `Tag` from the raw object body.
+    ///
+    /// The body holds the `object`, `type`, `tag` and `tagger` headers, an empty line and the
+    /// message. Unknown headers are ignored, and bytes that aren't UTF-8 replaced, so the
+    /// stored bytes are what `cat-file -p` shows.
+    pub fn from_bytes(data: &[u8]) -> Result<Tag> {
+        let content = String::from_utf8_lossy(data);
+        let (headers, message) = match content.split_once("\n\n") {
+            Some((headers, message)) => (headers, message),
+            None => (content.trim_end_matches('\n'), ""),
+        };
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut tagger = None;
+
+        for line in headers.lines() {
+            let (key, value) = match line.split_once(' ') {
+                Some(header) => header,
+                None => {
+                    return Err(RevyError::malformed(format!(
+                        "malformed tag header: {}",
+                        line
+                    )))
+                }
+            };
+            match key {
+                "object" => {
+                    object = Some(ObjectId::from_hex(value).map_err(|_err| {
+                        RevyError::malformed(format!("invalid object id '{}'", value))
+                    })?)
+                }
+                "type" => {
+                    kind = Some(ObjectType::from_str(value).ok_or_else(|| {
+                        RevyError::malformed(format!("invalid object type in tag: {}", value))
+                    })?)
+                }
+                "tag" => name = Some(value.to_string()),
+                "tagger" => tagger = Some(Signature::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Tag {
+            object: object.ok_or_else(|| RevyError::malformed("tag is missing an object"))?,
+            kind: kind.ok_or_else(|| RevyError::malformed("tag is missing a type"))?,
+            name: name.ok_or_else(|| RevyError::malformed("tag is missing a name"))?,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    /// Converts the tag to the byte vector stored in the object database.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut contents = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object,
+            self.kind.as_str(),
+            self.name
+        );
+        if let Some(tagger) = &self.tagger {
+            contents.push_str(&format!("tagger {}\n", tagger.as_str()));
+        }
+        contents.push('\n');
+        contents.push_str(&self.message);
+        contents.into_bytes()
+    }
+}
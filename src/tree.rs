@@ -1,43 +1,56 @@
 use std::fs::File;
-use std::hash::{self, Hash};
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Write};
 
 use flate2::read::ZlibDecoder;
 
-#[derive(Debug)]
+use crate::error::{Result, RevyError};
+use crate::hash::HashAlgorithm;
+use crate::object_id::ObjectId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileMode {
     RegularFile = 100644,
     ExecutableFile = 100755,
     SymbolicLink = 120000,
-    Directory = 040000,
+    Directory = 40000,
+    /// A commit in a nested repository (a submodule).
+    Gitlink = 160000,
 }
 
 impl FileMode {
-    /// Returns a string representation of the file mode.
+    /// Returns the type of the object an entry with this mode points at.
     pub fn as_str(&self) -> &'static str {
         match self {
             FileMode::RegularFile => "blob",
+            FileMode::ExecutableFile => "blob",
+            FileMode::SymbolicLink => "blob",
             FileMode::Directory => "tree",
-            FileMode::SymbolicLink => "commit",
-            FileMode::ExecutableFile => "commit",
+            FileMode::Gitlink => "commit",
         }
     }
 
     /// Converts a `u32` value to a `FileMode`.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the mode is invalid.
-    pub fn from_u32(mode: u32) -> FileMode {
+    /// Returns an error if the mode is not one git uses in trees.
+    pub fn from_u32(mode: u32) -> Result<FileMode> {
         match mode {
-            100644 => FileMode::RegularFile,
-            100755 => FileMode::ExecutableFile,
-            120000 => FileMode::SymbolicLink,
-            040000 => FileMode::Directory,
-            _ => panic!("Invalid file mode"),
+            100644 => Ok(FileMode::RegularFile),
+            100755 => Ok(FileMode::ExecutableFile),
+            120000 => Ok(FileMode::SymbolicLink),
+            40000 => Ok(FileMode::Directory),
+            160000 => Ok(FileMode::Gitlink),
+            _ => Err(RevyError::malformed(format!("invalid file mode {}", mode))),
         }
     }
 
+    /// Returns the mode as it is written inside a tree object. Git stores directories as
+    /// `40000`, without the leading zero shown by `ls-tree`.
+    pub fn as_object_str(&self) -> &str {
+        self.as_u32_str().trim_start_matches('0')
+    }
+
     /// Returns the string representation of the `u32` file mode.
     pub fn as_u32_str(&self) -> &str {
         match self {
@@ -45,6 +58,7 @@ impl FileMode {
             FileMode::Directory => "040000",
             FileMode::SymbolicLink => "120000",
             FileMode::ExecutableFile => "100755",
+            FileMode::Gitlink => "160000",
         }
     }
 }
@@ -53,14 +67,47 @@ impl FileMode {
 pub struct Node {
     mode: FileMode,
     name: String,
-    hash: String,
+    hash: ObjectId,
 }
 
 impl Node {
     /// Creates a new `Node` with the given mode, name, and hash.
-    pub fn new(mode: FileMode, name: String, hash: String) -> Node {
+    pub fn new(mode: FileMode, name: String, hash: ObjectId) -> Node {
         Node { mode, name, hash }
     }
+
+    pub fn mode(&self) -> FileMode {
+        self.mode
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn hash(&self) -> &ObjectId {
+        &self.hash
+    }
+
+    /// Returns the key git sorts tree entries by: the name, with a trailing `/` for
+    /// directories. This puts `a.txt` before the directory `a`, which sorts as `a/`.
+    pub fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.as_bytes().to_vec();
+        if self.mode == FileMode::Directory {
+            key.push(b'/');
+        }
+        key
+    }
+
+    /// Formats the node the way `ls-tree` does: `<mode> <type> <hash>\t<path>`.
+    pub fn as_ls_tree_line(&self, path: &str) -> String {
+        format!(
+            "{} {} {}\t{}",
+            self.mode.as_u32_str(),
+            self.mode.as_str(),
+            self.hash,
+            path
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -69,9 +116,41 @@ pub struct Tree {
 }
 
 impl Tree {
-    /// Creates a new `Tree` with the given nodes.
-    pub fn new(data: Vec<Node>) -> Tree {
-        Tree { data }
+    /// Creates a new `Tree` with the given nodes, in git's canonical order.
+    ///
+    /// The nodes may come in any order, so the same set of entries always produces the same
+    /// tree and hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if two nodes share a name.
+    pub fn new(data: Vec<Node>) -> Result<Tree> {
+        let mut tree = Tree { data: Vec::new() };
+        for node in data {
+            tree.insert(node)?;
+        }
+        Ok(tree)
+    }
+
+    /// Inserts `node` at its sorted position.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tree already has an entry with the same name, whatever its mode.
+    pub fn insert(&mut self, node: Node) -> Result<()> {
+        if self.data.iter().any(|existing| existing.name == node.name) {
+            return Err(RevyError::InvalidArgument(format!(
+                "Duplicate tree entry: {}",
+                node.name
+            )));
+        }
+
+        let key = node.sort_key();
+        let position = self
+            .data
+            .partition_point(|existing| existing.sort_key() < key);
+        self.data.insert(position, node);
+        Ok(())
     }
 
     /// Parses a `Tree` from a buffered `ZlibDecoder` reader.
@@ -79,19 +158,21 @@ impl Tree {
     /// # Arguments
     ///
     /// * `decoded_reader` - A buffered `ZlibDecoder` reader that reads the compressed tree data.
+    /// * `algorithm` - The hash algorithm of the repository, which decides how many raw bytes
+    ///   each entry's hash takes.
     ///
     /// # Errors
     ///
-    /// Returns a `Result` containing the parsed `Tree` on success, or an error string if the tree cannot be parsed.
+    /// Returns a `Result` containing the parsed `Tree` on success, or a `CorruptObject` error if the tree cannot be parsed.
     ///
     /// The function reads until it encounters a null byte (0), indicating the end of a node's metadata.
     /// It then splits the metadata string to extract the mode and name of the node.
-    /// The function also reads the following 20 bytes to get the SHA-1 hash of the node.
+    /// The function also reads the following `algorithm.raw_len()` bytes to get the hash of the node.
     /// If any of these operations fail, an error string is returned.
     ///
     /// # Example
     ///
-    /// ```
+    /// ```ignore
     /// use std::fs::File;
     /// use std::io::BufReader;
     /// use flate2::read::ZlibDecoder;
@@ -100,48 +181,65 @@ impl Tree {
     /// let decoder = ZlibDecoder::new(file);
     /// let reader = BufReader::new(decoder);
     ///
-    /// match Tree::parse_tree(reader) {
+    /// match Tree::parse_tree(reader, HashAlgorithm::Sha1) {
     ///     Ok(tree) => println!("Parsed tree: {:?}", tree),
     ///     Err(err) => println!("Error parsing tree: {}", err),
     /// }
     /// ```
-    pub fn parse_tree(mut decoded_reader: BufReader<ZlibDecoder<File>>) -> Result<Tree, String> {
+    pub fn parse_tree(
+        mut decoded_reader: BufReader<ZlibDecoder<File>>,
+        algorithm: HashAlgorithm,
+    ) -> Result<Tree> {
         let mut tree = Tree { data: Vec::new() };
 
-        while !decoded_reader.buffer().is_empty() {
+        loop {
+            match decoded_reader.fill_buf() {
+                Ok([]) => break,
+                Ok(_buffer) => {}
+                Err(_err) => {
+                    return Err(RevyError::malformed("failed to decompress tree entries"));
+                }
+            }
+
             let mut node_data = Vec::new();
             match decoded_reader.read_until(0, &mut node_data) {
                 Ok(_size) => {}
                 Err(_err) => {
-                    return Err("Failed to read object file while parsing tree".to_string());
+                    return Err(RevyError::malformed("failed to decompress tree entries"));
                 }
             }
 
-            let node_str = std::ffi::CStr::from_bytes_until_nul(&node_data)
-                .unwrap()
-                .to_str()
-                .unwrap();
-
-            let node_parts: Vec<&str> = node_str.split_whitespace().collect();
+            let node_str = match std::ffi::CStr::from_bytes_until_nul(&node_data)
+                .ok()
+                .and_then(|node| node.to_str().ok())
+            {
+                Some(node_str) => node_str,
+                None => return Err(RevyError::malformed("invalid tree entry")),
+            };
 
-            if node_parts.len() != 2 {
-                return Err("Failed to read object file while parsing tree".to_string());
-            }
+            // Names may contain spaces, so only the first one separates the mode.
+            let (mode, name) = match node_str.split_once(' ') {
+                Some(parts) => parts,
+                None => return Err(RevyError::malformed("tree entry without a mode")),
+            };
 
-            let mode = match node_parts[0].parse::<u32>() {
+            let mode = match mode.parse::<u32>() {
                 Ok(mode) => mode,
                 Err(_err) => {
-                    return Err("Failed to read object file while parsing tree".to_string());
+                    return Err(RevyError::malformed(format!("invalid mode '{}'", mode)));
                 }
             };
 
-            let hash_buffer = decoded_reader.buffer()[0..20].to_vec();
-            decoded_reader.consume(20);
-            let hash = hex::encode(hash_buffer);
+            let mut hash_buffer = vec![0; algorithm.raw_len()];
+            if decoded_reader.read_exact(&mut hash_buffer).is_err() {
+                return Err(RevyError::malformed("truncated tree entry hash"));
+            }
+            let hash = ObjectId::from_bytes(algorithm, &hash_buffer)
+                .map_err(|_err| RevyError::malformed("invalid tree entry hash"))?;
 
             tree.data.push(Node {
-                mode: FileMode::from_u32(mode),
-                name: node_parts[1].to_string(),
+                mode: FileMode::from_u32(mode)?,
+                name: name.to_string(),
                 hash,
             });
         }
@@ -166,35 +264,21 @@ impl Tree {
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut tree_contents = Vec::new();
         for node in &self.data {
-            let contents = format!("{} {}\0", node.mode.as_u32_str(), node.name);
+            let contents = format!("{} {}\0", node.mode.as_object_str(), node.name);
             tree_contents.extend(contents.as_bytes());
 
-            let hash = hex::decode(&node.hash).unwrap();
-            tree_contents.extend(&hash);
-        }
-        tree_contents
-    }
-
-    /// Converts the tree to a string.
-    pub fn as_str(&self) -> String {
-        let mut tree_contents = String::new();
-        for node in &self.data {
-            let contents = format!("{} {}\0{}", node.mode.as_u32_str(), node.name, node.hash);
-            tree_contents.push_str(&contents);
+            tree_contents.extend(node.hash.as_bytes());
         }
         tree_contents
     }
 
     /// Prints a pretty representation of the tree.
-    pub fn print_pretty_tree(&self) {
+    pub fn print_pretty_tree(&self) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
         for node in &self.data {
-            println!(
-                "{} {} {} {}",
-                node.mode.as_u32_str(),
-                node.mode.as_str(),
-                node.hash,
-                node.name
-            );
+            writeln!(stdout, "{}", node.as_ls_tree_line(&node.name))
+                .map_err(|err| RevyError::io("Failed to write to stdout", err))?;
         }
+        Ok(())
     }
 }
@@ -1,6 +1,12 @@
-use glob;
-use sha1::{Digest, Sha1};
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::config::Config;
+use crate::error::{Result, RevyError};
+use crate::hash::HashAlgorithm;
+use crate::refs;
 
 pub const REPO_FOLDER_NAME: &str = ".revy";
 
@@ -17,8 +23,8 @@ pub fn check_if_directory_exists(path: &String) -> bool {
     fs::metadata(path).is_ok()
 }
 
-/// Fetch the path for setting up a repository. If a repository name is provided, a directory with the
-/// repository name is created.
+/// Fetch the path for setting up a repository. If a repository name is provided, the path points
+/// into a directory with the repository name.
 ///
 /// # Arguments
 ///
@@ -28,74 +34,142 @@ pub fn check_if_directory_exists(path: &String) -> bool {
 ///
 /// Returns a String containing the path for repository setup.
 pub fn fetch_path_for_repository(repository_name: Option<&str>) -> String {
-    let current_directory_path = std::env::current_dir().unwrap();
-    let mut curent_working_directory = current_directory_path.to_str().unwrap().to_string();
+    let current_directory_path = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut curent_working_directory = current_directory_path.to_string_lossy().to_string();
 
     if let Some(repo_name) = repository_name {
         curent_working_directory.push_str(&format!("/{}", repo_name));
-        fs::create_dir(&curent_working_directory).unwrap();
     }
 
     format!("{}/{}", curent_working_directory, REPO_FOLDER_NAME)
 }
 
+/// Returns the branch new repositories start on: `init.defaultBranch` from the global or
+/// system config, or `main`.
+///
+/// # Errors
+///
+/// Returns an error if the config can't be read or names an invalid branch.
+pub fn initial_branch() -> Result<String> {
+    let config = Config::load(None)?;
+    let branch = config.get("init.defaultbranch").unwrap_or("main");
+    if !refs::is_valid_ref_name(&format!("refs/heads/{}", branch)) {
+        return Err(RevyError::BadConfig(format!(
+            "invalid initial branch name: '{}'",
+            branch
+        )));
+    }
+    Ok(branch.to_string())
+}
+
 /// Initialize a repository at the specified path.
 ///
 /// # Arguments
 ///
 /// * `current_repo_initiation_path` - A string slice containing the path for repository initialization.
+/// * `object_format` - The hash algorithm objects in the repository are named with.
+/// * `initial_branch` - The branch `HEAD` points at until the first commit creates it.
 ///
-/// # Panics
+/// # Errors
 ///
-/// Panics if directory or file creation fails.
-pub fn initialize_repository(current_repo_initiation_path: &str) {
-    fs::create_dir(&current_repo_initiation_path).unwrap();
-    fs::create_dir(format!("{}/objects", &current_repo_initiation_path)).unwrap();
-    fs::create_dir(format!("{}/refs", &current_repo_initiation_path)).unwrap();
-    fs::write(
-        format!("{}/HEAD", &current_repo_initiation_path),
-        "ref: refs/head/main\n",
-    )
-    .unwrap();
+/// Returns an error if directory or file creation fails.
+pub fn initialize_repository(
+    current_repo_initiation_path: &str,
+    object_format: HashAlgorithm,
+    initial_branch: &str,
+) -> Result<()> {
+    let create_dir = |path: String| {
+        fs::create_dir_all(&path)
+            .map_err(|err| RevyError::io(format!("Failed to create directory {}", path), err))
+    };
+    create_dir(current_repo_initiation_path.to_string())?;
+    create_dir(format!("{}/objects", &current_repo_initiation_path))?;
+    create_dir(format!("{}/refs/heads", &current_repo_initiation_path))?;
+    create_dir(format!("{}/refs/tags", &current_repo_initiation_path))?;
+
+    let head_path = format!("{}/HEAD", &current_repo_initiation_path);
+    fs::write(&head_path, format!("ref: refs/heads/{}\n", initial_branch))
+        .map_err(|err| RevyError::io(format!("Failed to write {}", head_path), err))?;
+
+    // Like git, only repositories that need an extension bump the format version.
+    let mut config = String::from("[core]\n");
+    match object_format {
+        HashAlgorithm::Sha1 => config.push_str("\trepositoryformatversion = 0\n"),
+        HashAlgorithm::Sha256 => config.push_str("\trepositoryformatversion = 1\n"),
+    }
+    config.push_str("\tfilemode = true\n\tbare = false\n");
+    if object_format != HashAlgorithm::Sha1 {
+        config.push_str(&format!(
+            "[extensions]\n\tobjectformat = {}\n",
+            object_format.as_str()
+        ));
+    }
+    let config_path = format!("{}/config", &current_repo_initiation_path);
+    fs::write(&config_path, config)
+        .map_err(|err| RevyError::io(format!("Failed to write {}", config_path), err))
 }
 
-/// Generate a SHA1 hash for the provided object data.
+/// Check if a file is executable by its owner. Git only records whether a file is
+/// executable, and like git only the user bit decides it.
+pub fn is_executable(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o100 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+/// Read the target of the symlink at `path` as raw bytes, which is what git stores as the
+/// content of a symlink blob.
+pub fn read_symlink_target(path: &std::path::Path) -> Result<Vec<u8>> {
+    let target = fs::read_link(path)
+        .map_err(|err| RevyError::io(format!("Failed to read symlink {}", path.display()), err))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+    }
+}
+
+/// Fetch Excluded Paths
 ///
 /// # Arguments
-/// * `data` - A reference to a String containing the data to hash.
 ///
-/// # Returns
-/// Returns a String containing the SHA1 hash.
-pub fn generate_sha1(data: &String) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(data);
-    let result = hasher.finalize();
-    format!("{:x}", result)
+/// * `work_tree` - The root of the working tree, where `.revyignore` lives.
+///
+/// # Errors
+///
+/// Returns an error if the `.revyignore` file exists but can't be read.
+pub fn fetch_excluded_paths(work_tree: &Path) -> Result<Vec<String>> {
+    read_ignore_file(&work_tree.join(".revyignore"))
 }
 
-/// Fetch Excluded Paths
-pub fn fetch_excluded_paths() -> Vec<String> {
-    // TODO: Implement this function to fetch excluded paths from the .revyignore file
-    // and check if a local .revyignore file exists in the current directory and ignore those too
-
-    //Objectives:
-
-    /*
-        - Check if a .revyignore file exists in the current directory
-        - Read the contents of the .revyignore file
-        - Parse the contents of the .revyignore file
-        - Return the list of excluded paths
-    */
-
+/// Read the patterns of an ignore file, skipping blank lines and `#` comments.
+///
+/// # Arguments
+///
+/// * `path` - The ignore file, e.g. `.revyignore` or the file `core.excludesFile` names.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but can't be read. A missing file has no patterns.
+pub fn read_ignore_file(path: &Path) -> Result<Vec<String>> {
     let mut excluded_paths: Vec<String> = Vec::new();
 
-    let global_revy_ignore_path = PathBuf::from("./.revyignore");
-
-    if check_if_directory_exists(&global_revy_ignore_path.to_str().unwrap().to_string()) {
-        let global_revy_ignore_contents = fs::read_to_string(global_revy_ignore_path).unwrap();
-        let global_revy_ignore_lines = global_revy_ignore_contents.lines();
+    if path.exists() {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| RevyError::io(format!("Failed to read {}", path.display()), err))?;
 
-        for line in global_revy_ignore_lines {
+        for line in contents.lines() {
             if line.trim().starts_with("#") {
                 continue;
             }
@@ -110,18 +184,190 @@ pub fn fetch_excluded_paths() -> Vec<String> {
             excluded_paths.push(line.to_string());
         }
     }
-    excluded_paths
+    Ok(excluded_paths)
 }
 
 /// Check if a path should be ignored.
-
+///
+/// Patterns follow `.gitignore`: one without a `/` is matched against each component of
+/// `path`, so `target` ignores `target/debug` but not `src/targeting.rs`. One with a `/`
+/// is anchored at the root of the working tree and matches `path` or a directory above it.
+/// A trailing `/` limits a pattern to directories; files and directories aren't told apart
+/// here, so it is dropped. Patterns that aren't valid globs match nothing.
 pub fn should_ignore(path: &std::path::Path, patterns: &[String]) -> bool {
-    // TODO: Implement a better way to check if a path should be ignored.
-
-    // println!("Path : {:?} , Pattern: {:?}", path, patterns);
-    let path_str = path.to_str().unwrap();
+    let components: Vec<String> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    let options = glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
     patterns.iter().any(|pattern| {
-        glob::Pattern::new(pattern).unwrap().matches_path(path)
-            || path_str.contains(pattern.trim_end_matches('/'))
+        let pattern = pattern.trim_end_matches('/');
+        let glob = match glob::Pattern::new(pattern.trim_start_matches('/')) {
+            Ok(glob) if !pattern.is_empty() => glob,
+            _ => return false,
+        };
+        if pattern.contains('/') {
+            (1..=components.len())
+                .any(|len| glob.matches_with(&components[..len].join("/"), options))
+        } else {
+            components
+                .iter()
+                .any(|name| glob.matches_with(name, options))
+        }
     })
 }
+
+/// Returns the current time in seconds since the epoch. A clock set before 1970 reads as the
+/// epoch.
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Parse a date the way people write them in revisions and options, relative to `now`.
+///
+/// Accepted forms are `now`, `yesterday`, relative dates like `2.weeks.ago` or
+/// `3 days ago`, `YYYY-MM-DD` with an optional `HH:MM[:SS]` (read as UTC, after a space or
+/// a `T`), and raw `<epoch seconds>` optionally prefixed with `@`.
+///
+/// # Arguments
+///
+/// * `text` - The date as the user wrote it.
+/// * `now` - The time relative dates count back from, in seconds since the epoch.
+///
+/// # Returns
+///
+/// Returns the date in seconds since the epoch, or `None` if it can't be parsed.
+pub fn parse_date(text: &str, now: i64) -> Option<i64> {
+    let text = text.trim().to_lowercase();
+    match text.as_str() {
+        "now" => return Some(now),
+        "yesterday" => return Some(now - 24 * 60 * 60),
+        _ => {}
+    }
+    if let Ok(epoch) = text.strip_prefix('@').unwrap_or(&text).parse::<i64>() {
+        return Some(epoch);
+    }
+
+    let words: Vec<&str> = text
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if let [count, unit, "ago"] = words.as_slice() {
+        let seconds = match unit.trim_end_matches('s') {
+            "second" | "sec" => 1,
+            "minute" | "min" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+        return Some(now - count.parse::<i64>().ok()?.checked_mul(seconds)?);
+    }
+
+    let (date, time) = match text.split_once(['t', ' ']) {
+        Some((date, time)) => (date, Some(time.trim())),
+        None => (text.as_str(), None),
+    };
+    let mut date_parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let parts = time
+            .split(':')
+            .map(|part| part.parse::<i64>().ok())
+            .collect::<Option<Vec<i64>>>()?;
+        seconds = match parts.as_slice() {
+            [hours, minutes] => hours * 3600 + minutes * 60,
+            [hours, minutes, secs] => hours * 3600 + minutes * 60 + secs,
+            _ => return None,
+        };
+    }
+    Some(days_from_civil(year, month, day) * 24 * 60 * 60 + seconds)
+}
+
+/// Format a timestamp the way git shows dates by default, in the timezone it was recorded
+/// in, e.g. `Tue Nov 14 23:13:20 2023 +0100`.
+///
+/// # Arguments
+///
+/// * `timestamp` - Seconds since the epoch.
+/// * `tz_offset` - The offset from UTC in minutes.
+///
+/// # Returns
+///
+/// Returns the formatted date.
+pub fn format_date(timestamp: i64, tz_offset: i32) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let local = timestamp + i64::from(tz_offset) * 60;
+    let days = local.div_euclid(24 * 60 * 60);
+    let seconds = local.rem_euclid(24 * 60 * 60);
+    let (year, month, day) = civil_from_days(days);
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}{:02}{:02}",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        year,
+        sign,
+        tz_offset.abs() / 60,
+        tz_offset.abs() % 60
+    )
+}
+
+/// Returns the `(year, month, day)` of the proleptic Gregorian calendar that is `days` days
+/// after 1970-01-01; the inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Returns the number of days between 1970-01-01 and the given date of the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
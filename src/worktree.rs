@@ -0,0 +1,164 @@
+//! Reading files from the working tree the way they are stored in the index and in trees.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Result, RevyError};
+use crate::index::{IndexEntry, StatData};
+use crate::refs;
+use crate::repository::Repository;
+use crate::tree::FileMode;
+use crate::utils;
+
+/// Converts `path`, relative to the current directory or absolute, to a path relative to the
+/// root of the working tree with `/` separators. The root itself is the empty string.
+///
+/// The path doesn't have to exist; `.` and `..` components are resolved lexically.
+///
+/// # Errors
+///
+/// Returns an error if `path` is outside the working tree.
+pub fn relative_path(repo: &Repository, path: &Path) -> Result<String> {
+    let absolute = std::path::absolute(path)
+        .map_err(|err| RevyError::io(format!("Failed to resolve {}", path.display()), err))?;
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    let relative = normalized.strip_prefix(repo.work_tree()).map_err(|_err| {
+        RevyError::InvalidArgument(format!(
+            "'{}' is outside repository at '{}'",
+            path.display(),
+            repo.work_tree().display()
+        ))
+    })?;
+    let components: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Ok(components.join("/"))
+}
+
+/// Returns the patterns of ignored paths: those in `.revyignore` at the root of the working
+/// tree, then those in the file the `core.excludesFile` config names.
+///
+/// # Errors
+///
+/// Returns an error if an ignore file exists but can't be read.
+pub fn ignore_patterns(repo: &Repository) -> Result<Vec<String>> {
+    let mut patterns = utils::fetch_excluded_paths(repo.work_tree())?;
+    if let Some(path) = repo.config().get_path("core.excludesfile") {
+        patterns.extend(utils::read_ignore_file(&path)?);
+    }
+    Ok(patterns)
+}
+
+/// Check if the working tree path `path` is matched by the ignore `patterns`.
+pub fn is_ignored(path: &str, patterns: &[String]) -> bool {
+    utils::should_ignore(Path::new(path), patterns)
+}
+
+/// Lists the files under the working tree directory `dir` (relative, `""` for the root)
+/// that can be staged: regular files, symbolic links and nested repositories. Ignored paths
+/// and the `.revy` directory are skipped. The result is sorted.
+pub fn list_files(repo: &Repository, dir: &str) -> Result<Vec<String>> {
+    let patterns = ignore_patterns(repo)?;
+    let mut files = Vec::new();
+    collect_files(repo, dir, &patterns, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(
+    repo: &Repository,
+    dir: &str,
+    patterns: &[String],
+    files: &mut Vec<String>,
+) -> Result<()> {
+    let full_path = repo.work_tree().join(dir);
+    let entries = fs::read_dir(&full_path)
+        .map_err(|err| RevyError::io(format!("Failed to read {}", full_path.display()), err))?;
+
+    for entry in entries.filter_map(std::result::Result::ok) {
+        if entry.file_name() == utils::REPO_FOLDER_NAME {
+            continue;
+        }
+        let name = entry
+            .file_name()
+            .to_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                RevyError::InvalidArgument(format!(
+                    "File name {} is not valid UTF-8",
+                    entry.path().display()
+                ))
+            })?;
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        if is_ignored(&path, patterns) {
+            continue;
+        }
+
+        let file_type = entry
+            .file_type()
+            .map_err(|err| RevyError::io(format!("could not stat '{}'", path), err))?;
+        if file_type.is_dir() && !entry.path().join(utils::REPO_FOLDER_NAME).is_dir() {
+            collect_files(repo, &path, patterns, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Hashes the working tree file `path` and returns the index entry describing it, storing
+/// the blob when `write` is set.
+///
+/// Symbolic links are stored as a blob of their target, and a nested repository as a
+/// gitlink to the commit it has checked out.
+pub fn entry_for(repo: &Repository, path: &str, write: bool) -> Result<IndexEntry> {
+    let full_path = repo.work_tree().join(path);
+    // Look at the entry itself rather than following symlinks.
+    let metadata = fs::symlink_metadata(&full_path)
+        .map_err(|err| RevyError::io(format!("could not stat '{}'", path), err))?;
+    let stat = StatData::from_metadata(&metadata);
+
+    let (mode, id) = if metadata.file_type().is_symlink() {
+        let target = utils::read_symlink_target(&full_path)?;
+        let id = repo.hash_blob(target.as_slice(), target.len(), write)?;
+        (FileMode::SymbolicLink, id)
+    } else if metadata.is_dir() {
+        let nested = full_path.join(utils::REPO_FOLDER_NAME);
+        let id = refs::resolve_ref_in(&nested, "HEAD").ok_or_else(|| {
+            RevyError::BadRef(format!("'{}' does not have a commit checked out", path))
+        })?;
+        (FileMode::Gitlink, id)
+    } else {
+        (mode_of(&metadata), repo.hash_file(&full_path, write)?)
+    };
+
+    Ok(IndexEntry::new(path.to_string(), mode, id, stat))
+}
+
+/// Returns the mode a working tree entry is staged with, from its `symlink_metadata`.
+pub fn mode_of(metadata: &fs::Metadata) -> FileMode {
+    if metadata.file_type().is_symlink() {
+        FileMode::SymbolicLink
+    } else if metadata.is_dir() {
+        FileMode::Gitlink
+    } else if utils::is_executable(metadata) {
+        FileMode::ExecutableFile
+    } else {
+        FileMode::RegularFile
+    }
+}
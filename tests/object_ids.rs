@@ -0,0 +1,699 @@
+//! Checks that revy computes the same object ids as git for known content.
+//!
+//! The expected ids were produced with `git hash-object`, `git write-tree` and `git commit`,
+//! with the same identities and dates revy is given here.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use revy::hash::HashAlgorithm;
+
+/// Creates an empty scratch directory unique to `name` and initializes a repository in it.
+fn setup_repository(name: &str) -> PathBuf {
+    setup_repository_with(name, &["init"])
+}
+
+/// Like `setup_repository`, but initializes the repository with `init_args`.
+fn setup_repository_with(name: &str, init_args: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("revy-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    revy(&dir, init_args);
+    dir
+}
+
+/// Runs revy inside `dir`, with a fixed author, committer and date, and returns its trimmed
+/// stdout.
+fn revy(dir: &Path, args: &[&str]) -> String {
+    let output = revy_command(dir, args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "revy {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Runs revy inside `dir` like `revy`, expecting it to fail, and returns its exit code and
+/// trimmed stderr.
+fn revy_fails(dir: &Path, args: &[&str]) -> (i32, String) {
+    let output = revy_command(dir, args).output().unwrap();
+    assert!(!output.status.success(), "revy {:?} succeeded", args);
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stderr).unwrap().trim().to_string(),
+    )
+}
+
+/// Stores `body` as a loose object of type `kind` in the repository in `dir`, bypassing
+/// revy's parsers, and returns its id.
+fn write_loose_object(dir: &Path, kind: &str, body: &[u8]) -> String {
+    let mut object = format!("{} {}\0", kind, body.len()).into_bytes();
+    object.extend_from_slice(body);
+    let id = HashAlgorithm::Sha1.digest(&object).to_hex();
+
+    let path = dir.join(".revy/objects").join(&id[..2]).join(&id[2..]);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&object).unwrap();
+    fs::write(path, encoder.finish().unwrap()).unwrap();
+    id
+}
+
+/// Builds the command running revy with `args` inside `dir`.
+fn revy_command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_revy"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("REVY_AUTHOR_NAME", "A U Thor")
+        .env("REVY_AUTHOR_EMAIL", "author@example.com")
+        .env("REVY_AUTHOR_DATE", "1700000000 +0100")
+        .env("REVY_COMMITTER_NAME", "C O Mitter")
+        .env("REVY_COMMITTER_EMAIL", "committer@example.com")
+        .env("REVY_COMMITTER_DATE", "1700000000 +0100")
+        .env("REVY_CONFIG_NOSYSTEM", "1")
+        .env("REVY_CONFIG_GLOBAL", dir.join(".revyconfig-global"));
+    command
+}
+
+#[test]
+fn blob_ids_match_git() {
+    let dir = setup_repository("blob-ids");
+    let fixtures: [(&str, &[u8], &str); 3] = [
+        ("empty", b"", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"),
+        (
+            "hello.txt",
+            b"hello\n",
+            "ce013625030ba8dba906f756967f9e9ca394464a",
+        ),
+        (
+            "bin.dat",
+            b"\x00\x01\x02\xff\xfe binary",
+            "08048af0d78a4750f9ce568ce6238283790df81a",
+        ),
+    ];
+
+    for (file_name, contents, expected) in fixtures {
+        fs::write(dir.join(file_name), contents).unwrap();
+        assert_eq!(revy(&dir, &["hash-object", file_name]), expected);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stored_blob_round_trips() {
+    let dir = setup_repository("blob-round-trip");
+    let contents = b"\x00\x01\x02\xff\xfe binary";
+    fs::write(dir.join("bin.dat"), contents).unwrap();
+
+    let hash = revy(&dir, &["hash-object", "-w", "bin.dat"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_revy"))
+        .args(["cat-file", "-p", &hash])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, contents);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn single_file_tree_id_matches_git() {
+    let dir = setup_repository("single-file-tree");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+
+    revy(&dir, &["add", "."]);
+    assert_eq!(
+        revy(&dir, &["write-tree"]),
+        "aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn nested_tree_id_matches_git() {
+    let dir = setup_repository("nested-tree");
+    fs::create_dir(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/readme.md"), "hello\n").unwrap();
+
+    revy(&dir, &["add", "."]);
+    assert_eq!(
+        revy(&dir, &["write-tree"]),
+        "4821f24a4b86228e968f4f6b1854ca72a679cedb"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn sha256_ids_match_git() {
+    let dir = setup_repository_with("sha256", &["init", "--object-format=sha256"]);
+    fs::create_dir(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/readme.md"), "hello\n").unwrap();
+
+    assert_eq!(
+        revy(&dir, &["hash-object", "docs/readme.md"]),
+        "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4"
+    );
+    revy(&dir, &["add", "."]);
+    let tree = revy(&dir, &["write-tree"]);
+    assert_eq!(
+        tree,
+        "11aa418c6e326988f38eb368dcabe9d9310f2a7f9d1792551694c0da4aaf1ba8"
+    );
+    assert_eq!(revy(&dir, &["ls-tree", "--name-only", &tree]), "docs");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn tree_entries_use_git_order() {
+    let dir = setup_repository("tree-order");
+    for (path, contents) in [
+        ("a.txt", "1\n"),
+        ("a-b", "2\n"),
+        ("a/x", "3\n"),
+        ("b/y", "4\n"),
+        ("B", "5\n"),
+        ("a0", "6\n"),
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    revy(&dir, &["add", "."]);
+    let tree = revy(&dir, &["write-tree"]);
+    assert_eq!(tree, "c51e61198a76be1aee1adc7d58ec8ab3d2a95480");
+    assert_eq!(
+        revy(&dir, &["ls-tree", "--name-only", &tree]),
+        "B\na-b\na.txt\na\na0\nb"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn executable_and_symlink_modes_match_git() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = setup_repository("file-modes");
+    fs::write(dir.join("run.sh"), "#!/bin/sh\necho hi\n").unwrap();
+    fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("run.sh", dir.join("link")).unwrap();
+    fs::write(dir.join("plain.txt"), "plain\n").unwrap();
+
+    revy(&dir, &["add", "."]);
+    let tree = revy(&dir, &["write-tree"]);
+    assert_eq!(tree, "c3813ba4a5e493fb98eb9c7b2d6b38bed22daf82");
+    assert_eq!(
+        revy(&dir, &["ls-tree", &tree]),
+        "120000 blob e0e63473c2593040d7d1c67637864821b28cef4b\tlink\n\
+         100644 blob b9bca019c83a65e6d717d0b6da86215f45dde1b3\tplain.txt\n\
+         100755 blob 4163036efa65bd4a469e752267498f01ea36a55c\trun.sh"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn commit_ids_match_git() {
+    let dir = setup_repository("commit");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    revy(&dir, &["add", "hello.txt"]);
+    assert_eq!(
+        revy(&dir, &["commit", "-m", "first"]),
+        "[main (root-commit) 3043f07] first"
+    );
+
+    fs::write(dir.join("more.txt"), "more\n").unwrap();
+    revy(&dir, &["add", "more.txt"]);
+    assert_eq!(
+        revy(&dir, &["commit", "-m", "second", "-m", "with a body"]),
+        "[main 309941e] second"
+    );
+    assert_eq!(
+        revy(&dir, &["show-ref", "--hash", "refs/heads/main"]),
+        "309941e48ced4db740a69cb6261fd59508a9af3a"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn commit_amend_allow_empty_and_messages_match_git() {
+    let dir = setup_repository("commit-options");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    revy(&dir, &["add", "hello.txt"]);
+    revy(&dir, &["commit", "-m", "first"]);
+
+    assert_eq!(
+        revy_fails(&dir, &["commit", "-m", "again"]),
+        (
+            2,
+            "fatal: nothing to commit (use \"revy add\" to stage changes, or --allow-empty)"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        revy(&dir, &["commit", "--allow-empty", "-m", "empty"]),
+        "[main 1c8afaa] empty"
+    );
+    assert_eq!(
+        revy(&dir, &["commit", "--amend", "-m", "amended"]),
+        "[main 9720a0b] amended"
+    );
+    assert_eq!(
+        revy(&dir, &["rev-parse", "HEAD~1"]),
+        "3043f07e341d70ba383dd36f073889e10e6fd573"
+    );
+
+    // An editor that saves only the template's comments aborts the commit.
+    let output = revy_command(&dir, &["commit", "--allow-empty"])
+        .env("REVY_EDITOR", "true")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "fatal: Aborting commit due to empty commit message."
+    );
+
+    // Comments, trailing whitespace and extra blank lines are stripped from what is saved.
+    let output = revy_command(&dir, &["commit", "--allow-empty"])
+        .env(
+            "REVY_EDITOR",
+            "printf 'subject\\n# comment\\n\\n\\nbody  \\n' >",
+        )
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        revy(&dir, &["rev-parse", "HEAD"]),
+        "ec0474f8a7b29b39893c84c972126a735efdce0f"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn commit_amend_keeps_a_message_that_is_not_utf8() {
+    let dir = setup_repository("commit-amend-encoding");
+    let tree = revy(&dir, &["write-tree"]);
+    let mut body = format!(
+        "tree {}\n\
+         author A U Thor <author@example.com> 1600000000 +0000\n\
+         committer A U Thor <author@example.com> 1600000000 +0000\n\
+         encoding ISO-8859-1\n\
+         gpgsig -----BEGIN PGP SIGNATURE-----\n \n -----END PGP SIGNATURE-----\n\n",
+        tree
+    )
+    .into_bytes();
+    body.extend_from_slice(b"caf\xe9\n\nna\xefve body\n");
+    let id = write_loose_object(&dir, "commit", &body);
+    revy(&dir, &["update-ref", "refs/heads/main", &id]);
+
+    let output = revy_command(&dir, &["commit", "--amend"])
+        .env("REVY_EDITOR", "true")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = revy_command(&dir, &["cat-file", "-p", "HEAD"])
+        .output()
+        .unwrap();
+    let mut expected = format!(
+        "tree {}\n\
+         author A U Thor <author@example.com> 1600000000 +0000\n\
+         committer C O Mitter <committer@example.com> 1700000000 +0100\n\
+         encoding ISO-8859-1\n\n",
+        tree
+    )
+    .into_bytes();
+    expected.extend_from_slice(b"caf\xe9\n\nna\xefve body\n");
+    assert_eq!(output.stdout, expected);
+
+    // A new message is UTF-8, so the encoding no longer applies.
+    revy(&dir, &["commit", "--amend", "-m", "caf\u{e9}"]);
+    let message = revy(&dir, &["cat-file", "-p", "HEAD"]);
+    assert!(!message.contains("encoding"), "{}", message);
+    assert!(message.ends_with("\n\ncaf\u{e9}"), "{}", message);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn annotated_tag_ids_match_git() {
+    let dir = setup_repository("tag");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    revy(&dir, &["add", "hello.txt"]);
+    revy(&dir, &["commit", "-m", "first"]);
+
+    revy(&dir, &["tag", "-a", "v1.0", "-m", "Release 1.0"]);
+    revy(&dir, &["tag", "light"]);
+    revy(&dir, &["tag", "-m", "old", "v0.9", "HEAD^{tree}"]);
+    assert_eq!(
+        revy(
+            &dir,
+            &["rev-parse", "v1.0", "v1.0^{}", "v0.9", "v0.9^{}", "light"]
+        ),
+        "fc50fe6dc25ac72334984817608bb71bf4f9c182\n\
+         3043f07e341d70ba383dd36f073889e10e6fd573\n\
+         ef5b93b0f675dde3fa78fb2840740399b5c2d359\n\
+         aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7\n\
+         3043f07e341d70ba383dd36f073889e10e6fd573"
+    );
+    assert_eq!(
+        revy(&dir, &["cat-file", "-p", "v0.9"]),
+        "object aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7\n\
+         type tree\n\
+         tag v0.9\n\
+         tagger C O Mitter <committer@example.com> 1700000000 +0100\n\
+         \n\
+         old"
+    );
+
+    assert_eq!(revy(&dir, &["tag"]), "light\nv0.9\nv1.0");
+    assert_eq!(revy(&dir, &["tag", "-l", "v*"]), "v0.9\nv1.0");
+    assert_eq!(
+        revy(&dir, &["tag", "-d", "v1.0"]),
+        "Deleted tag 'v1.0' (was fc50fe6)"
+    );
+    assert_eq!(revy(&dir, &["tag", "-l", "v*"]), "v0.9");
+    assert_eq!(revy_fails(&dir, &["rev-parse", "v1.0"]).0, 4);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rm_keeps_staged_and_local_changes_unless_forced() {
+    let dir = setup_repository("rm");
+    fs::write(dir.join("f"), "a\n").unwrap();
+    revy(&dir, &["add", "f"]);
+    revy(&dir, &["commit", "-m", "first"]);
+
+    fs::write(dir.join("f"), "b\n").unwrap();
+    revy(&dir, &["add", "f"]);
+    let (code, stderr) = revy_fails(&dir, &["rm", "f"]);
+    assert_eq!(code, 2);
+    assert!(
+        stderr.contains("has changes staged in the index"),
+        "{}",
+        stderr
+    );
+
+    fs::write(dir.join("f"), "c\n").unwrap();
+    let (_code, stderr) = revy_fails(&dir, &["rm", "--cached", "f"]);
+    assert!(
+        stderr.contains("staged content different from both the file and the HEAD"),
+        "{}",
+        stderr
+    );
+    assert_eq!(revy(&dir, &["status", "--short"]), "MM f");
+    assert_eq!(fs::read_to_string(dir.join("f")).unwrap(), "c\n");
+
+    fs::write(dir.join("f"), "b\n").unwrap();
+    assert_eq!(revy(&dir, &["rm", "--cached", "f"]), "rm 'f'");
+    assert!(dir.join("f").exists());
+    revy(&dir, &["add", "f"]);
+    assert_eq!(revy(&dir, &["rm", "-f", "f"]), "rm 'f'");
+    assert!(!dir.join("f").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cat_file_shows_commits_that_are_not_utf8_as_stored() {
+    let dir = setup_repository("latin1-commit");
+    let tree = revy(&dir, &["write-tree"]);
+    let mut body = format!(
+        "tree {}\n\
+         author A U Thor <author@example.com> 1700000000 +0100\n\
+         committer C O Mitter <committer@example.com> 1700000000 +0100\n\
+         encoding ISO-8859-1\n\ncaf",
+        tree
+    )
+    .into_bytes();
+    body.extend_from_slice(b"\xe9\n");
+    let id = write_loose_object(&dir, "commit", &body);
+
+    let output = revy_command(&dir, &["cat-file", "-p", &id])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, body);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cat_file_shows_tags_that_are_not_utf8_as_stored() {
+    let dir = setup_repository("latin1-tag");
+    let tree = revy(&dir, &["write-tree"]);
+    let mut body = format!(
+        "object {}\ntype tree\ntag v1\n\
+         tagger C O Mitter <committer@example.com> 1700000000 +0100\n\ncaf",
+        tree
+    )
+    .into_bytes();
+    body.extend_from_slice(b"\xe9\n");
+    let id = write_loose_object(&dir, "tag", &body);
+
+    let output = revy_command(&dir, &["cat-file", "-p", &id])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, body);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn short_ids_resolve_by_prefix_and_report_ambiguity() {
+    let dir = setup_repository("prefix");
+    // Both blob ids start with 6bb2f.
+    fs::write(dir.join("a.txt"), "195\n").unwrap();
+    fs::write(dir.join("b.txt"), "389\n").unwrap();
+    assert_eq!(
+        revy(&dir, &["hash-object", "-w", "a.txt"]),
+        "6bb2f98fb0227744dff2c9023c2a8d53cc721588"
+    );
+    assert_eq!(
+        revy(&dir, &["hash-object", "-w", "b.txt"]),
+        "6bb2f4ee89f3ff56785055f588c560ce557d0655"
+    );
+
+    assert_eq!(revy(&dir, &["cat-file", "-p", "6bb2f9"]), "195");
+    assert_eq!(revy(&dir, &["cat-file", "-p", "6BB2F4E"]), "389");
+
+    let (code, stderr) = revy_fails(&dir, &["cat-file", "-p", "6bb2f"]);
+    assert_eq!(code, 2);
+    assert_eq!(
+        stderr,
+        "fatal: short object ID 6bb2f is ambiguous\n\
+         hint: The candidates are:\n\
+         hint:   6bb2f4ee89f3ff56785055f588c560ce557d0655 blob\n\
+         hint:   6bb2f98fb0227744dff2c9023c2a8d53cc721588 blob"
+    );
+
+    // Under four characters a prefix isn't looked up at all.
+    let (code, stderr) = revy_fails(&dir, &["cat-file", "-p", "6bb"]);
+    assert_ne!(code, 0);
+    assert_eq!(stderr, "fatal: Not a valid object name: '6bb'");
+    assert_eq!(revy_fails(&dir, &["cat-file", "-p", "0000"]).0, 3);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cat_file_script_modes_match_git() {
+    let dir = setup_repository("cat-file");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    revy(&dir, &["add", "hello.txt"]);
+    revy(&dir, &["commit", "-m", "first"]);
+    let commit = "3043f07e341d70ba383dd36f073889e10e6fd573";
+    let tree = "aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7";
+    let blob = "ce013625030ba8dba906f756967f9e9ca394464a";
+
+    assert_eq!(revy(&dir, &["cat-file", "-t", "HEAD"]), "commit");
+    assert_eq!(revy(&dir, &["cat-file", "-s", "HEAD"]), "169");
+    assert_eq!(revy(&dir, &["cat-file", "-s", "HEAD:hello.txt"]), "6");
+    assert_eq!(revy(&dir, &["cat-file", "-e", blob]), "");
+    assert_eq!(
+        revy_fails(&dir, &["cat-file", "-e", "0123456789abcdef"]),
+        (1, String::new())
+    );
+
+    assert_eq!(revy(&dir, &["cat-file", "blob", "HEAD:hello.txt"]), "hello");
+    assert!(revy(&dir, &["cat-file", "commit", "HEAD"]).starts_with(&format!("tree {}", tree)));
+    // A commit peels to its tree, which is shown in its binary form.
+    let output = revy_command(&dir, &["cat-file", "tree", "HEAD"])
+        .output()
+        .unwrap();
+    let mut expected = b"100644 hello.txt\0".to_vec();
+    expected.extend_from_slice(&hex::decode(blob).unwrap());
+    assert_eq!(output.stdout, expected);
+    assert_eq!(revy_fails(&dir, &["cat-file", "blob", "HEAD"]).0, 2);
+
+    let batch = |args: &[&str], input: &str| {
+        let mut child = revy_command(&dir, args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        batch(&["cat-file", "--batch-check"], "HEAD\nhello\nce0136\n"),
+        format!("{} commit 169\nhello missing\n{} blob 6\n", commit, blob)
+    );
+    assert_eq!(
+        batch(&["cat-file", "--batch"], "HEAD:hello.txt\n"),
+        format!("{} blob 6\nhello\n\n", blob)
+    );
+    assert_eq!(
+        batch(&["cat-file", "--batch-check", "--batch-all-objects"], ""),
+        format!("{} commit 169\n{} tree 37\n{} blob 6\n", commit, tree, blob)
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ls_tree_output_matches_git() {
+    let dir = setup_repository("ls-tree");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    fs::create_dir_all(dir.join("dir/sub")).unwrap();
+    fs::write(dir.join("dir/a.txt"), "a\n").unwrap();
+    fs::write(dir.join("dir/sub/b.txt"), "bb\n").unwrap();
+    revy(&dir, &["add", "hello.txt", "dir"]);
+    let tree = revy(&dir, &["write-tree"]);
+    assert_eq!(tree, "937d6690e466b93716f0a6a425abe36b4ab06297");
+
+    let dir_entry = "040000 tree 6035fc56e6b2a43666b3585a25ded127905ac68b\tdir";
+    let a_entry = "100644 blob 78981922613b2afb6025042ff6bd878ac1994e85\tdir/a.txt";
+    let sub_entry = "040000 tree 7f2b5e86e3b2e63f74259cb78fb691823e54eed0\tdir/sub";
+    let b_entry = "100644 blob e0b3f1b09bd1819ed1f7ce2e75fc7400809f5350\tdir/sub/b.txt";
+    let hello_entry = "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\thello.txt";
+    let ls_tree = |args: &[&str]| revy(&dir, &[&["ls-tree"], args, &[tree.as_str()]].concat());
+
+    assert_eq!(ls_tree(&[]), [dir_entry, hello_entry].join("\n"));
+    assert_eq!(
+        ls_tree(&["-l"]),
+        "040000 tree 6035fc56e6b2a43666b3585a25ded127905ac68b       -\tdir\n\
+         100644 blob ce013625030ba8dba906f756967f9e9ca394464a       6\thello.txt"
+    );
+    assert_eq!(ls_tree(&["-r"]), [a_entry, b_entry, hello_entry].join("\n"));
+    assert_eq!(
+        ls_tree(&["-r", "-t"]),
+        [dir_entry, a_entry, sub_entry, b_entry, hello_entry].join("\n")
+    );
+    assert_eq!(ls_tree(&["--name-only"]), "dir\nhello.txt");
+    assert_eq!(ls_tree(&["-d"]), dir_entry);
+
+    assert_eq!(revy(&dir, &["ls-tree", &tree, "dir"]), dir_entry);
+    assert_eq!(
+        revy(&dir, &["ls-tree", &tree, "dir/"]),
+        [a_entry, sub_entry].join("\n")
+    );
+    assert_eq!(
+        revy(&dir, &["ls-tree", "-r", &tree, "dir/sub", "hello.txt"]),
+        [b_entry, hello_entry].join("\n")
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failures_exit_with_the_documented_codes() {
+    let dir = setup_repository("exit-codes");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    let blob = revy(&dir, &["hash-object", "-w", "hello.txt"]);
+
+    let (code, stderr) = revy_fails(&dir, &["cat-file", "--no-such-flag", &blob]);
+    assert_eq!(code, 2, "{}", stderr);
+    assert_eq!(revy_fails(&dir, &["cat-file", "-p"]).0, 2);
+    assert_eq!(
+        revy_fails(
+            &dir,
+            &["cat-file", "-p", "0123456789abcdef0123456789abcdef01234567"]
+        ),
+        (
+            3,
+            "fatal: object 0123456789abcdef0123456789abcdef01234567 not found".to_string()
+        )
+    );
+    assert_eq!(revy_fails(&dir, &["rev-parse", "no-such-branch"]).0, 4);
+
+    let object_file = dir.join(".revy/objects").join(&blob[..2]).join(&blob[2..]);
+    fs::write(object_file, "garbage").unwrap();
+    let (code, stderr) = revy_fails(&dir, &["cat-file", "-p", &blob]);
+    assert_eq!(code, 5, "{}", stderr);
+    assert!(stderr.starts_with("fatal: "), "{}", stderr);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn symbolic_ref_refuses_to_delete_head() {
+    let dir = setup_repository("symbolic-ref-delete");
+    let head = revy(&dir, &["symbolic-ref", "HEAD"]);
+
+    assert_eq!(
+        revy_fails(&dir, &["symbolic-ref", "-d", "HEAD"]),
+        (2, "fatal: deleting 'HEAD' is not allowed".to_string())
+    );
+    assert_eq!(revy(&dir, &["symbolic-ref", "HEAD"]), head);
+
+    revy(&dir, &["symbolic-ref", "refs/remotes/origin/HEAD", &head]);
+    revy(&dir, &["symbolic-ref", "-d", "refs/remotes/origin/HEAD"]);
+    assert!(!dir.join(".revy/refs/remotes/origin/HEAD").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn config_falls_back_to_global_files_only_outside_a_repository() {
+    let dir = std::env::temp_dir().join(format!("revy-config-outside-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    revy(&dir, &["config", "set", "--global", "user.name", "Global"]);
+    assert_eq!(revy(&dir, &["config", "get", "user.name"]), "Global");
+
+    // A repository that was named or found but can't be opened is an error.
+    let (code, stderr) = revy_fails(
+        &dir,
+        &["--revy-dir", "missing", "config", "get", "user.name"],
+    );
+    assert_eq!(code, 2, "{}", stderr);
+    revy(&dir, &["init"]);
+    fs::write(
+        dir.join(".revy/config"),
+        "[extensions]\n\tobjectformat = sha512\n",
+    )
+    .unwrap();
+    let (code, stderr) = revy_fails(&dir, &["config", "get", "user.name"]);
+    assert_eq!(code, 5, "{}", stderr);
+
+    fs::remove_dir_all(dir).unwrap();
+}
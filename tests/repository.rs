@@ -0,0 +1,934 @@
+//! Exercises the library API directly, without going through the `revy` binary.
+
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use regex::Regex;
+use revy::checkout::{self, SwitchOptions};
+use revy::commit::{Commit, Signature};
+use revy::config::{self, Config, ConfigLevel};
+use revy::fsck::Problem;
+use revy::hash::HashAlgorithm;
+use revy::index::Index;
+use revy::log::{self, Graph, LogOptions};
+use revy::object::{Metadata, Object, ObjectType};
+use revy::reflog;
+use revy::refs::Expected;
+use revy::revision::{self, Revision};
+use revy::status::Change;
+use revy::{worktree, ObjectId, Repository, RevyError};
+
+/// Creates an empty scratch directory unique to `name`.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("revy-lib-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Stages every file in the working tree of `repo`, like `revy add .`.
+fn stage_all(repo: &Repository) {
+    let mut index = repo.read_index().unwrap();
+    for path in worktree::list_files(repo, "").unwrap() {
+        index.add(worktree::entry_for(repo, &path, true).unwrap());
+    }
+    repo.write_index(&index).unwrap();
+}
+
+/// Stores a commit of `tree` with the given parents and returns its id.
+fn write_commit(
+    repo: &Repository,
+    tree: ObjectId,
+    parents: Vec<ObjectId>,
+    message: &str,
+) -> ObjectId {
+    let signature = Signature::new(
+        "A U Thor".to_string(),
+        "author@example.com".to_string(),
+        0,
+        0,
+    );
+    let commit = Commit::new(
+        tree,
+        parents,
+        signature.clone(),
+        signature,
+        format!("{}\n", message),
+    );
+    repo.write_object(ObjectType::Commit, Metadata::Commit(commit))
+        .unwrap()
+        .hash
+}
+
+#[test]
+fn open_requires_a_repository() {
+    let dir = scratch_dir("open");
+    assert!(matches!(
+        Repository::open(&dir),
+        Err(RevyError::InvalidArgument(_))
+    ));
+
+    Repository::init(&dir, HashAlgorithm::Sha256).unwrap();
+    let repo = Repository::open(&dir).unwrap();
+    assert_eq!(repo.algorithm(), HashAlgorithm::Sha256);
+
+    let config = dir.join(".revy").join("config");
+    fs::write(&config, "[extensions]\n\tobjectformat = sha512\n").unwrap();
+    assert!(matches!(
+        Repository::open(&dir),
+        Err(RevyError::BadConfig(_))
+    ));
+    fs::write(&config, "[extensions\n").unwrap();
+    assert!(matches!(
+        Repository::open(&dir),
+        Err(RevyError::BadConfig(_))
+    ));
+}
+
+#[test]
+fn blobs_round_trip_through_the_object_database() {
+    let dir = scratch_dir("blobs");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+
+    let id = repo.hash_blob(&b"hello\n"[..], 6, true).unwrap();
+    assert_eq!(id.to_hex(), "ce013625030ba8dba906f756967f9e9ca394464a");
+    assert!(repo.has_object(&id));
+    assert_eq!(repo.resolve_revision("ce0136").unwrap(), id);
+
+    let mut stream = repo.open_object(&id).unwrap();
+    let mut content = String::new();
+    stream.read_to_string(&mut content).unwrap();
+    assert_eq!(stream.kind, ObjectType::Blob);
+    assert_eq!(content, "hello\n");
+
+    match repo.read_object(&id).unwrap().metadata {
+        Metadata::Blob(blob) => assert_eq!(blob.as_bytes(), b"hello\n"),
+        other => panic!("expected a blob, got {:?}", other),
+    }
+
+    // Objects are written to a temporary file and renamed, leaving nothing else behind.
+    fs::write(dir.join("big.bin"), vec![7u8; 200_000]).unwrap();
+    let big = Object::create_blob(&repo, &dir.join("big.bin")).unwrap();
+    assert_eq!(repo.open_object(&big).unwrap().size, 200_000);
+    let tree = repo.write_tree().unwrap();
+    assert!(repo.has_object(&tree));
+    let leftovers: Vec<_> = fs::read_dir(dir.join(".revy/objects"))
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn trees_and_refs_resolve_without_the_current_directory() {
+    let dir = scratch_dir("trees");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+
+    stage_all(&repo);
+    let tree = repo.write_tree().unwrap();
+    assert_eq!(tree.to_hex(), "aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7");
+    let names: Vec<_> = repo
+        .read_tree(&tree)
+        .unwrap()
+        .data
+        .iter()
+        .map(|node| node.name().to_string())
+        .collect();
+    assert_eq!(names, ["hello.txt"]);
+
+    repo.write_ref("refs/tags/v1", &tree).unwrap();
+    assert_eq!(repo.resolve_revision("v1").unwrap(), tree);
+    assert_eq!(repo.list_refs("refs/tags/"), ["refs/tags/v1"]);
+    repo.delete_ref("refs/tags/v1", Expected::Value(tree))
+        .unwrap();
+    assert!(repo.resolve_ref("refs/tags/v1").is_none());
+}
+
+#[test]
+fn discover_walks_up_from_subdirectories() {
+    let dir = scratch_dir("discover");
+    Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let nested = dir.join("src").join("deep");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+
+    let repo = Repository::discover(&nested).unwrap();
+    assert_eq!(repo.work_tree(), dir);
+    assert_eq!(repo.revy_dir(), dir.join(".revy"));
+
+    // The whole working tree is stored, not just the directory discovery started from.
+    fs::write(nested.join("f"), "x\n").unwrap();
+    stage_all(&repo);
+    let tree = repo.write_tree().unwrap();
+    let names: Vec<_> = repo
+        .read_tree(&tree)
+        .unwrap()
+        .data
+        .iter()
+        .map(|node| node.name().to_string())
+        .collect();
+    assert_eq!(names, ["hello.txt", "src"]);
+}
+
+#[test]
+fn work_tree_can_live_outside_the_repository() {
+    let dir = scratch_dir("work-tree");
+    let repo_dir = dir.join("repo");
+    let work_tree = dir.join("files");
+    fs::create_dir_all(&work_tree).unwrap();
+    fs::write(work_tree.join("hello.txt"), "hello\n").unwrap();
+    Repository::init(&repo_dir, HashAlgorithm::Sha1).unwrap();
+
+    let repo = Repository::open_with_work_tree(repo_dir.join(".revy"), &work_tree).unwrap();
+    stage_all(&repo);
+    assert_eq!(
+        repo.write_tree().unwrap().to_hex(),
+        "aaa96ced2d9a1c8e72c56b253a0e2fe78393feb7"
+    );
+}
+
+#[test]
+fn index_round_trips_and_replaces_conflicting_entries() {
+    let dir = scratch_dir("index");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/readme.md"), "hello\n").unwrap();
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    stage_all(&repo);
+
+    let index = repo.read_index().unwrap();
+    let paths: Vec<_> = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    assert_eq!(paths, ["docs/readme.md", "hello.txt"]);
+    let bytes = index.as_bytes(repo.algorithm());
+    let parsed = Index::from_bytes(&bytes, repo.algorithm()).unwrap();
+    assert_eq!(parsed.as_bytes(repo.algorithm()), bytes);
+
+    // A file replacing a directory drops everything that was staged under it.
+    fs::remove_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs"), "now a file\n").unwrap();
+    let mut index = parsed;
+    index.add(worktree::entry_for(&repo, "docs", true).unwrap());
+    let paths: Vec<_> = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    assert_eq!(paths, ["docs", "hello.txt"]);
+
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xff;
+    assert!(matches!(
+        Index::from_bytes(&corrupt, repo.algorithm()),
+        Err(RevyError::CorruptIndex(_))
+    ));
+}
+
+#[test]
+fn status_compares_head_the_index_and_the_working_tree() {
+    let dir = scratch_dir("status");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::write(dir.join("kept.txt"), "kept\n").unwrap();
+    fs::write(dir.join("edited.txt"), "before\n").unwrap();
+    fs::write(dir.join("gone.txt"), "gone\n").unwrap();
+    stage_all(&repo);
+
+    let status = repo.status().unwrap();
+    assert_eq!(status.head, None);
+    assert_eq!(status.staged.len(), 3);
+    assert!(status.unstaged.is_empty() && status.untracked.is_empty());
+
+    let signature = Signature::new(
+        "A U Thor".to_string(),
+        "author@example.com".to_string(),
+        0,
+        0,
+    );
+    let commit = Commit::new(
+        repo.write_tree().unwrap(),
+        Vec::new(),
+        signature.clone(),
+        signature,
+        "initial\n".to_string(),
+    );
+    let commit = repo
+        .write_object(ObjectType::Commit, Metadata::Commit(commit))
+        .unwrap()
+        .hash;
+    repo.write_ref("HEAD", &commit).unwrap();
+    assert!(repo.status().unwrap().is_clean());
+
+    fs::write(dir.join("edited.txt"), "after\n").unwrap();
+    fs::remove_file(dir.join("gone.txt")).unwrap();
+    fs::write(dir.join("added.txt"), "added\n").unwrap();
+    fs::create_dir_all(dir.join("new/deep")).unwrap();
+    fs::write(dir.join("new/deep/file"), "untracked\n").unwrap();
+    let mut index = repo.read_index().unwrap();
+    index.add(worktree::entry_for(&repo, "added.txt", true).unwrap());
+    repo.write_index(&index).unwrap();
+
+    let status = repo.status().unwrap();
+    assert_eq!(status.head, Some(commit));
+    assert_eq!(status.staged, [("added.txt".to_string(), Change::Added)]);
+    assert_eq!(
+        status.unstaged,
+        [
+            ("edited.txt".to_string(), Change::Modified),
+            ("gone.txt".to_string(), Change::Deleted),
+        ]
+    );
+    assert_eq!(status.untracked, ["new/"]);
+
+    // A plain directory in place of a tracked file means the file was deleted.
+    fs::remove_file(dir.join("kept.txt")).unwrap();
+    fs::create_dir(dir.join("kept.txt")).unwrap();
+    fs::write(dir.join("kept.txt/inner"), "inner\n").unwrap();
+    let status = repo.status().unwrap();
+    assert_eq!(
+        status.unstaged,
+        [
+            ("edited.txt".to_string(), Change::Modified),
+            ("gone.txt".to_string(), Change::Deleted),
+            ("kept.txt".to_string(), Change::Deleted),
+        ]
+    );
+    assert_eq!(status.untracked, ["kept.txt/", "new/"]);
+}
+
+#[test]
+fn ignore_patterns_match_whole_path_components() {
+    let patterns = [
+        "target".to_string(),
+        "*.log".to_string(),
+        "/build/".to_string(),
+    ];
+    assert!(worktree::is_ignored("target", &patterns));
+    assert!(worktree::is_ignored("target/debug/revy", &patterns));
+    assert!(worktree::is_ignored("crates/core/target", &patterns));
+    assert!(!worktree::is_ignored("src/targeting.rs", &patterns));
+    assert!(!worktree::is_ignored("target.txt", &patterns));
+
+    assert!(worktree::is_ignored("out.log", &patterns));
+    assert!(worktree::is_ignored("logs/today.log", &patterns));
+    assert!(!worktree::is_ignored("out.log.txt", &patterns));
+
+    assert!(worktree::is_ignored("build", &patterns));
+    assert!(worktree::is_ignored("build/main.o", &patterns));
+    assert!(!worktree::is_ignored("src/build", &patterns));
+    assert!(!worktree::is_ignored("builder", &patterns));
+
+    let patterns = ["docs/*.md".to_string(), "a".to_string()];
+    assert!(worktree::is_ignored("docs/intro.md", &patterns));
+    assert!(!worktree::is_ignored("docs/api/intro.md", &patterns));
+    assert!(!worktree::is_ignored("src/docs/intro.md", &patterns));
+    assert!(worktree::is_ignored("a/b", &patterns));
+    assert!(!worktree::is_ignored("alpha/beta", &patterns));
+}
+
+#[test]
+fn refs_update_atomically_and_read_packed_refs() {
+    let dir = scratch_dir("refs");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let first = repo.hash_blob(&b"first\n"[..], 6, true).unwrap();
+    let second = repo.hash_blob(&b"second\n"[..], 7, true).unwrap();
+
+    assert_eq!(
+        repo.read_symbolic_ref("HEAD").as_deref(),
+        Some("refs/heads/main")
+    );
+    assert_eq!(
+        repo.follow_symbolic_refs("HEAD").unwrap(),
+        "refs/heads/main"
+    );
+
+    repo.update_ref("refs/heads/main", &first, Expected::Missing, "")
+        .unwrap();
+    assert_eq!(repo.resolve_ref("HEAD"), Some(first));
+    assert!(matches!(
+        repo.update_ref("refs/heads/main", &second, Expected::Missing, ""),
+        Err(RevyError::BadRef(_))
+    ));
+    assert!(matches!(
+        repo.update_ref("refs/heads/main", &second, Expected::Value(second), ""),
+        Err(RevyError::BadRef(_))
+    ));
+    repo.update_ref("refs/heads/main", &second, Expected::Value(first), "")
+        .unwrap();
+    assert_eq!(repo.resolve_ref("refs/heads/main"), Some(second));
+
+    // A held lock keeps other writers out.
+    fs::write(dir.join(".revy/refs/heads/main.lock"), "").unwrap();
+    assert!(matches!(
+        repo.update_ref("refs/heads/main", &first, Expected::Any, ""),
+        Err(RevyError::Io { .. })
+    ));
+    assert_eq!(repo.list_refs("refs/heads/"), ["refs/heads/main"]);
+    fs::remove_file(dir.join(".revy/refs/heads/main.lock")).unwrap();
+
+    fs::write(
+        dir.join(".revy/packed-refs"),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted \n{} refs/heads/main\n{} refs/tags/v1\n",
+            first, first
+        ),
+    )
+    .unwrap();
+    // The loose ref shadows its packed copy.
+    assert_eq!(repo.resolve_ref("refs/heads/main"), Some(second));
+    assert_eq!(repo.resolve_revision("v1").unwrap(), first);
+    assert_eq!(repo.list_refs("refs/"), ["refs/heads/main", "refs/tags/v1"]);
+
+    repo.delete_ref("refs/tags/v1", Expected::Value(first))
+        .unwrap();
+    assert!(repo.resolve_ref("refs/tags/v1").is_none());
+    repo.delete_ref("refs/heads/main", Expected::Any).unwrap();
+    assert!(repo.resolve_ref("HEAD").is_none());
+    assert!(repo.list_refs("refs/").is_empty());
+
+    // Only root refs like ORIG_HEAD are looked up by their bare name.
+    fs::write(dir.join(".revy/description"), format!("{}\n", first)).unwrap();
+    fs::write(dir.join(".revy/ORIG_HEAD"), format!("{}\n", first)).unwrap();
+    assert_eq!(repo.expand_ref_name("description"), None);
+    assert_eq!(
+        repo.expand_ref_name("ORIG_HEAD").as_deref(),
+        Some("ORIG_HEAD")
+    );
+    assert_eq!(repo.resolve_revision("ORIG_HEAD").unwrap(), first);
+}
+
+#[test]
+fn config_parses_includes_and_edits_values_in_place() {
+    let dir = scratch_dir("config");
+    let path = dir.join("config");
+    fs::write(
+        &path,
+        "# comment\n[Core]\n\tBare = false ; trailing\n\tflag\n\
+         [remote \"Origin\"]\n\turl = \"a \\\"quoted\\\" url\"\n\
+         \tfetch = one\n\tfetch = two\n[include]\n\tpath = extra\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("extra"),
+        "[pack]\n\twindow = 1k\n[core]\n\tbare = yes\n",
+    )
+    .unwrap();
+
+    let config = Config::from_file(&path, ConfigLevel::Local).unwrap();
+    assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+    assert_eq!(config.get_bool("CORE.FLAG").unwrap(), Some(true));
+    assert_eq!(config.get_int("pack.window").unwrap(), Some(1024));
+    assert_eq!(config.get("remote.Origin.url"), Some("a \"quoted\" url"));
+    assert_eq!(config.get("remote.origin.url"), None);
+    assert_eq!(config.get_all("remote.Origin.fetch"), ["one", "two"]);
+    assert_eq!(
+        config.get_entry("pack.window").unwrap().origin,
+        dir.join("extra")
+    );
+    assert!(matches!(
+        config.get_int("remote.Origin.fetch"),
+        Err(RevyError::BadConfig(_))
+    ));
+
+    config::set_value(&path, "core.bare", "true", false).unwrap();
+    config::set_value(&path, "remote.Origin.fetch", "three", true).unwrap();
+    config::set_value(&path, "user.name", "A U Thor", false).unwrap();
+    assert!(config::set_value(&path, "remote.Origin.fetch", "four", false).is_err());
+    assert_eq!(config::unset_value(&path, "core.flag", false).unwrap(), 1);
+
+    let text = fs::read_to_string(&path).unwrap();
+    assert!(text.starts_with("# comment\n[Core]\n\tbare = true\n[remote"));
+    assert!(text.ends_with("[user]\n\tname = A U Thor\n"));
+    let config = Config::from_file(&path, ConfigLevel::Local).unwrap();
+    assert_eq!(
+        config.get_all("remote.Origin.fetch"),
+        ["one", "two", "three"]
+    );
+    assert_eq!(config.get("user.name"), Some("A U Thor"));
+    assert_eq!(config.get("core.flag"), None);
+
+    fs::write(dir.join("broken"), "[core\n").unwrap();
+    assert!(matches!(
+        Config::from_file(&dir.join("broken"), ConfigLevel::Local),
+        Err(RevyError::BadConfig(_))
+    ));
+}
+
+#[test]
+fn reflogs_record_ref_updates_and_resolve_at_revisions() {
+    let dir = scratch_dir("reflog");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let first = repo.hash_blob(&b"first\n"[..], 6, true).unwrap();
+    let second = repo.hash_blob(&b"second\n"[..], 7, true).unwrap();
+
+    repo.update_ref("refs/heads/main", &first, Expected::Missing, "create")
+        .unwrap();
+    repo.update_ref("refs/heads/main", &second, Expected::Any, "move\non")
+        .unwrap();
+    repo.write_ref("refs/tags/v1", &first).unwrap();
+
+    // HEAD points at main, so both logs record the updates; tags aren't logged.
+    for name in ["HEAD", "refs/heads/main"] {
+        let log = repo.reflog(name).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].old, log[0].new), (None, first));
+        assert_eq!((log[1].old, log[1].new), (Some(first), second));
+        assert_eq!(log[1].message, "move on");
+    }
+    assert!(repo.reflog("refs/tags/v1").unwrap().is_empty());
+
+    assert_eq!(repo.resolve_revision("HEAD@{0}").unwrap(), second);
+    assert_eq!(repo.resolve_revision("main@{1}").unwrap(), first);
+    assert_eq!(repo.resolve_revision("@{1}").unwrap(), first);
+    assert_eq!(repo.resolve_revision("main@{now}").unwrap(), second);
+    assert!(matches!(
+        repo.resolve_revision("main@{2}"),
+        Err(RevyError::BadRef(_))
+    ));
+    assert!(matches!(
+        repo.resolve_revision("main@{yesterday}"),
+        Err(RevyError::BadRef(_))
+    ));
+
+    reflog::delete_reflog_entry(&repo, "HEAD", 1, true).unwrap();
+    let log = repo.reflog("HEAD").unwrap();
+    assert_eq!((log.len(), log[0].old), (1, None));
+    assert_eq!(reflog::expire_reflog(&repo, "HEAD", i64::MAX).unwrap(), 1);
+    assert!(repo.reflog("HEAD").unwrap().is_empty());
+
+    repo.delete_ref("refs/heads/main", Expected::Value(second))
+        .unwrap();
+    assert!(!reflog::has_reflog(&repo, "refs/heads/main"));
+    assert!(!dir.join(".revy/logs/refs/heads").exists());
+}
+
+#[test]
+fn revisions_follow_ancestry_peel_and_expand_ranges() {
+    let dir = scratch_dir("revision");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/lib.rs"), "fn main() {}\n").unwrap();
+    stage_all(&repo);
+    let tree = repo.write_tree().unwrap();
+    let blob = repo.read_index().unwrap().get("src/lib.rs").unwrap().id;
+
+    let root = write_commit(&repo, tree, Vec::new(), "root");
+    let main = write_commit(&repo, tree, vec![root], "main");
+    let side = write_commit(&repo, tree, vec![root], "side");
+    let merge = write_commit(&repo, tree, vec![main, side], "merge");
+    repo.write_ref("refs/heads/main", &merge).unwrap();
+    repo.write_ref("refs/remotes/origin/side", &side).unwrap();
+
+    assert_eq!(repo.resolve_revision("HEAD^").unwrap(), main);
+    assert_eq!(repo.resolve_revision("main^2").unwrap(), side);
+    assert_eq!(repo.resolve_revision("@~2").unwrap(), root);
+    assert_eq!(repo.resolve_revision("HEAD^2~1").unwrap(), root);
+    assert_eq!(repo.resolve_revision("main^0").unwrap(), merge);
+    assert_eq!(repo.resolve_revision("origin/side").unwrap(), side);
+    assert_eq!(repo.resolve_revision("main^{tree}").unwrap(), tree);
+    assert_eq!(repo.resolve_revision("HEAD:src/lib.rs").unwrap(), blob);
+    assert_eq!(repo.resolve_revision(":src/lib.rs").unwrap(), blob);
+    assert_eq!(repo.resolve_revision("HEAD:").unwrap(), tree);
+    for missing in ["HEAD~3", "HEAD^3", "HEAD:nope", "HEAD^{blob}", "nope"] {
+        assert!(
+            matches!(repo.resolve_revision(missing), Err(RevyError::BadRef(_))),
+            "{} resolved",
+            missing
+        );
+    }
+
+    assert_eq!(
+        revision::resolve_range(&repo, "origin/side..main").unwrap(),
+        [Revision::Include(merge), Revision::Exclude(side)]
+    );
+    assert_eq!(
+        revision::resolve_range(&repo, "HEAD~1...origin/side").unwrap(),
+        [
+            Revision::Include(side),
+            Revision::Include(main),
+            Revision::Exclude(root)
+        ]
+    );
+    assert!(revision::is_ancestor(&repo, &side, &merge).unwrap());
+    assert!(!revision::is_ancestor(&repo, &merge, &side).unwrap());
+    assert_eq!(repo.abbreviate(&merge, 4).len(), 4);
+}
+
+#[test]
+fn checkout_materializes_trees_and_keeps_local_changes() {
+    let dir = scratch_dir("checkout");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::write(dir.join("a.txt"), "one\n").unwrap();
+    fs::write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::create_dir_all(dir.join("dir")).unwrap();
+    fs::write(dir.join("dir/nested.txt"), "nested\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("a.txt", dir.join("link")).unwrap();
+    }
+    stage_all(&repo);
+    let first = write_commit(&repo, repo.write_tree().unwrap(), Vec::new(), "first");
+    repo.update_ref("refs/heads/main", &first, Expected::Missing, "create")
+        .unwrap();
+
+    fs::write(dir.join("a.txt"), "two\n").unwrap();
+    fs::remove_dir_all(dir.join("dir")).unwrap();
+    fs::write(dir.join("dir"), "now a file\n").unwrap();
+    let mut index = repo.read_index().unwrap();
+    index.remove("dir/nested.txt");
+    repo.write_index(&index).unwrap();
+    stage_all(&repo);
+    let second = write_commit(&repo, repo.write_tree().unwrap(), vec![first], "second");
+    repo.update_ref("refs/heads/topic", &second, Expected::Missing, "create")
+        .unwrap();
+
+    // Forcing back to HEAD throws away the staged and working tree changes.
+    repo.checkout_tree(&first, true).unwrap();
+    assert!(repo.status().unwrap().is_clean());
+    assert_eq!(
+        fs::read_to_string(dir.join("dir/nested.txt")).unwrap(),
+        "nested\n"
+    );
+
+    fs::write(dir.join("run.sh"), "#!/bin/sh\nexit 1\n").unwrap();
+    repo.checkout_tree(&second, false).unwrap();
+    checkout::update_head(&repo, Some("refs/heads/topic"), &second, "to topic").unwrap();
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "two\n");
+    assert_eq!(fs::read_to_string(dir.join("dir")).unwrap(), "now a file\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dir.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_ne!(mode & 0o111, 0);
+        assert_eq!(
+            fs::read_link(dir.join("link")).unwrap(),
+            PathBuf::from("a.txt")
+        );
+    }
+    // The change to run.sh, which both commits share, is carried over.
+    let status = repo.status().unwrap();
+    assert_eq!(status.unstaged, [("run.sh".to_string(), Change::Modified)]);
+    assert!(status.staged.is_empty() && status.untracked.is_empty());
+
+    fs::write(dir.join("a.txt"), "local\n").unwrap();
+    assert!(matches!(
+        repo.checkout_tree(&first, false),
+        Err(RevyError::InvalidArgument(_))
+    ));
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "local\n");
+
+    repo.checkout_tree(&first, true).unwrap();
+    checkout::update_head(&repo, None, &first, "detach").unwrap();
+    assert!(repo.status().unwrap().is_clean());
+    assert_eq!(repo.read_symbolic_ref("HEAD"), None);
+    assert_eq!(repo.resolve_ref("HEAD"), Some(first));
+    assert_eq!(repo.resolve_ref("refs/heads/topic"), Some(second));
+    let messages: Vec<String> = repo
+        .reflog("HEAD")
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.message)
+        .collect();
+    assert_eq!(messages, ["create", "to topic", "detach"]);
+
+    let switched = repo
+        .switch_branch("topic", &SwitchOptions::default())
+        .unwrap();
+    assert_eq!(switched.branch.as_deref(), Some("topic"));
+    assert_eq!(switched.left_detached, Some(first));
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "two\n");
+    assert!(
+        repo.switch_branch("topic", &SwitchOptions::default())
+            .unwrap()
+            .already_on
+    );
+    assert!(matches!(
+        repo.switch_branch(&first.to_hex(), &SwitchOptions::default()),
+        Err(RevyError::InvalidArgument(_))
+    ));
+    let switched = repo.checkout("main", false, false).unwrap();
+    assert_eq!(
+        (switched.branch.as_deref(), switched.id),
+        (Some("main"), Some(first))
+    );
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "one\n");
+}
+
+#[test]
+fn refs_rename_with_their_reflog_and_branch_config() {
+    let dir = scratch_dir("branch");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let tree = repo.write_tree().unwrap();
+    let root = write_commit(&repo, tree, Vec::new(), "root");
+    let main = write_commit(&repo, tree, vec![root], "main");
+    let topic = write_commit(&repo, tree, vec![root], "topic");
+    let topic_next = write_commit(&repo, tree, vec![topic], "topic next");
+    repo.update_ref("refs/heads/main", &main, Expected::Missing, "create")
+        .unwrap();
+    repo.update_ref("refs/heads/topic", &topic_next, Expected::Missing, "create")
+        .unwrap();
+
+    assert_eq!(
+        revision::ahead_behind(&repo, &topic_next, &main).unwrap(),
+        (2, 1)
+    );
+    assert_eq!(revision::ahead_behind(&repo, &main, &main).unwrap(), (0, 0));
+
+    repo.rename_ref("refs/heads/main", "refs/heads/trunk", "rename")
+        .unwrap();
+    assert_eq!(
+        repo.read_symbolic_ref("HEAD").as_deref(),
+        Some("refs/heads/trunk")
+    );
+    assert_eq!(repo.resolve_ref("refs/heads/main"), None);
+    let entries = repo.reflog("refs/heads/trunk").unwrap();
+    let messages: Vec<&str> = entries.iter().map(|entry| entry.message.as_str()).collect();
+    assert_eq!(messages, ["create", "rename"]);
+    assert_eq!((entries[1].old, entries[1].new), (Some(main), main));
+    assert!(!reflog::has_reflog(&repo, "refs/heads/main"));
+    assert!(matches!(
+        repo.rename_ref("refs/heads/topic", "refs/heads/trunk", ""),
+        Err(RevyError::BadRef(_))
+    ));
+
+    // A rename that can't write the new ref leaves the old one and its log in place.
+    fs::write(dir.join(".revy/refs/heads/next.lock"), "").unwrap();
+    assert!(repo
+        .rename_ref("refs/heads/topic", "refs/heads/next", "rename")
+        .is_err());
+    assert_eq!(repo.resolve_ref("refs/heads/topic"), Some(topic_next));
+    assert_eq!(repo.reflog("refs/heads/topic").unwrap().len(), 1);
+    assert!(!reflog::has_reflog(&repo, "refs/heads/next"));
+    fs::remove_file(dir.join(".revy/refs/heads/next.lock")).unwrap();
+
+    let path = dir.join(".revy/config");
+    config::set_value(&path, "branch.main.merge", "refs/heads/up", false).unwrap();
+    config::set_value(&path, "branch.topic.remote", ".", false).unwrap();
+    assert!(config::rename_section(&path, "branch.main", Some("branch.trunk")).unwrap());
+    assert!(config::rename_section(&path, "branch.topic", None).unwrap());
+    assert!(!config::rename_section(&path, "branch.topic", None).unwrap());
+    let config = Config::from_file(&path, ConfigLevel::Local).unwrap();
+    assert_eq!(config.get("branch.trunk.merge"), Some("refs/heads/up"));
+    assert_eq!(config.get("branch.main.merge"), None);
+    assert_eq!(config.get("branch.topic.remote"), None);
+}
+
+#[test]
+fn branches_are_created_listed_and_deleted_with_their_config() {
+    let dir = scratch_dir("branches");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let current = repo.current_branch().unwrap();
+    let tree = repo.write_tree().unwrap();
+    let root = write_commit(&repo, tree, Vec::new(), "root");
+    let next = write_commit(&repo, tree, vec![root], "next");
+    repo.write_ref(&format!("refs/heads/{}", current), &root)
+        .unwrap();
+
+    assert_eq!(
+        repo.create_branch("topic", None, false, None).unwrap(),
+        None
+    );
+    assert!(matches!(
+        repo.create_branch("topic", None, false, None),
+        Err(RevyError::BadRef(_))
+    ));
+    assert!(matches!(
+        repo.create_branch("bad..name", None, false, None),
+        Err(RevyError::InvalidArgument(_))
+    ));
+    let upstream = repo
+        .create_branch("feature", Some(&next.to_hex()), false, None)
+        .unwrap();
+    assert_eq!(upstream, None);
+    assert_eq!(
+        repo.set_upstream(Some("feature"), "topic").unwrap(),
+        ("feature".to_string(), "refs/heads/topic".to_string())
+    );
+
+    // The config is read when the repository is opened.
+    let repo = Repository::open(&dir).unwrap();
+    assert_eq!(
+        repo.branch_upstream("feature").as_deref(),
+        Some("refs/heads/topic")
+    );
+    let names: Vec<(String, bool)> = repo
+        .list_branches(&[])
+        .unwrap()
+        .into_iter()
+        .map(|branch| (branch.name, branch.is_current))
+        .collect();
+    let mut expected = vec![
+        ("feature".to_string(), false),
+        (current.clone(), true),
+        ("topic".to_string(), false),
+    ];
+    expected.sort();
+    assert_eq!(names, expected);
+    assert_eq!(repo.list_branches(&["t*".to_string()]).unwrap().len(), 1);
+
+    // `feature` is ahead of its upstream, so it isn't merged.
+    assert!(matches!(
+        repo.delete_branch("feature", false),
+        Err(RevyError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        repo.delete_branch(&current, true),
+        Err(RevyError::InvalidArgument(_))
+    ));
+    repo.rename_branch(Some("feature"), "done", false).unwrap();
+    assert_eq!(repo.delete_branch("done", true).unwrap(), next);
+    assert_eq!(repo.delete_branch("topic", false).unwrap(), root);
+    let repo = Repository::open(&dir).unwrap();
+    assert_eq!(repo.list_branches(&[]).unwrap().len(), 1);
+    assert_eq!(repo.config().get("branch.done.merge"), None);
+    assert_eq!(repo.config().get("branch.feature.merge"), None);
+}
+
+#[test]
+fn log_lists_history_with_filters_path_limiting_and_a_graph() {
+    let dir = scratch_dir("log");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    let snapshot = |files: &[(&str, &str)]| {
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        stage_all(&repo);
+        repo.write_tree().unwrap()
+    };
+    let root_tree = snapshot(&[("a", "1\n")]);
+    let main_tree = snapshot(&[("a", "2\n")]);
+    let merged_tree = snapshot(&[("b", "side\n")]);
+    fs::write(dir.join("a"), "1\n").unwrap();
+    let side_tree = snapshot(&[]);
+
+    let root = write_commit(&repo, root_tree, Vec::new(), "root");
+    let main = write_commit(&repo, main_tree, vec![root], "change a");
+    let side = write_commit(&repo, side_tree, vec![root], "add b");
+    let merge = write_commit(&repo, merged_tree, vec![main, side], "merge side");
+    let ids_from = |revisions: &[Revision], options: &LogOptions| -> Vec<ObjectId> {
+        log::log(&repo, revisions, options)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect()
+    };
+    let ids = |options: &LogOptions| ids_from(&[Revision::Include(merge)], options);
+
+    assert_eq!(ids(&LogOptions::default()), [merge, main, side, root]);
+    let limited = LogOptions {
+        max_count: Some(2),
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&limited), [merge, main]);
+    let grep = LogOptions {
+        grep: Some(Regex::new("side").unwrap()),
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&grep), [merge]);
+    let grep = LogOptions {
+        grep: Some(Regex::new("^(add|change) [a-z]$").unwrap()),
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&grep), [main, side]);
+    let author = LogOptions {
+        author: Some(Regex::new("^A U .*@example").unwrap()),
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&author), [merge, main, side, root]);
+    let author = LogOptions {
+        author: Some(Regex::new("^Thor").unwrap()),
+        ..LogOptions::default()
+    };
+    assert!(ids(&author).is_empty());
+    // The merge took `b` unchanged from `side`, so only the commits that changed it show.
+    let paths = LogOptions {
+        paths: vec!["b".to_string()],
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&paths), [side]);
+    assert_eq!(
+        ids_from(
+            &[Revision::Include(merge), Revision::Exclude(main)],
+            &LogOptions::default()
+        ),
+        [merge, side]
+    );
+
+    let topo = LogOptions {
+        topo_order: true,
+        ..LogOptions::default()
+    };
+    let mut graph = Graph::new();
+    let mut lines = Vec::new();
+    for entry in log::log(&repo, &[Revision::Include(merge)], &topo).unwrap() {
+        let subject = entry.commit.subject();
+        lines.extend(graph.draw(&entry.id, &entry.parents, &subject, false));
+    }
+    assert_eq!(
+        lines,
+        [
+            "*   merge side",
+            "|\\  ",
+            "| * add b",
+            "* | change a",
+            "|/  ",
+            "* root"
+        ]
+    );
+
+    // With a count the walk stops early, never reading the root commit.
+    let hex = root.to_hex();
+    fs::remove_file(dir.join(".revy/objects").join(&hex[..2]).join(&hex[2..])).unwrap();
+    let first = LogOptions {
+        max_count: Some(1),
+        ..LogOptions::default()
+    };
+    assert_eq!(ids(&first), [merge]);
+}
+
+#[test]
+fn fsck_reports_missing_corrupt_and_dangling_objects() {
+    let dir = scratch_dir("fsck");
+    let repo = Repository::init(&dir, HashAlgorithm::Sha1).unwrap();
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    fs::write(dir.join("other.txt"), "other\n").unwrap();
+    stage_all(&repo);
+    let tree = repo.write_tree().unwrap();
+    repo.write_ref("refs/tags/snapshot", &tree).unwrap();
+    let loose = repo.hash_blob(&b"loose\n"[..], 6, true).unwrap();
+
+    let report = repo.fsck().unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.dangling, [(ObjectType::Blob, loose)]);
+
+    let object_file = |id: &revy::ObjectId| {
+        let hex = id.to_hex();
+        dir.join(".revy/objects").join(&hex[..2]).join(&hex[2..])
+    };
+    let entries = repo.read_tree(&tree).unwrap().data;
+    fs::remove_file(object_file(entries[0].hash())).unwrap();
+    fs::write(object_file(entries[1].hash()), "garbage").unwrap();
+
+    let report = repo.fsck().unwrap();
+    assert!(!report.is_ok());
+    assert!(report.problems.contains(&Problem::Missing {
+        kind: ObjectType::Blob,
+        id: *entries[0].hash(),
+        referenced_by: tree,
+    }));
+    assert!(report.problems.iter().any(|problem| matches!(
+        problem,
+        Problem::Corrupt { id, .. } if id == entries[1].hash()
+    )));
+}